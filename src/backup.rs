@@ -0,0 +1,252 @@
+//! Cartridge backup memory at 0x0E000000, stores the saved games
+
+use crate::gba_file::SaveType;
+
+/// Flash command sequence is started by writing 0xAA to 0x5555 and 0x55 to 0x2AAA
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum FlashCommand {
+    #[default]
+    Ready,
+    /// 0xAA was written to 0x5555
+    Unlock1,
+    /// 0x55 was written to 0x2AAA, the next write is the command
+    Unlock2,
+}
+
+/// State of the Flash chip between the accesses
+#[derive(Debug, Default, Clone, Copy)]
+struct Flash {
+    command: FlashCommand,
+    /// Chip identification is read from the first two bytes
+    id_mode: bool,
+    /// Erase command was started, waiting for a chip or sector erase
+    erase: bool,
+    /// Next write programs a byte
+    write: bool,
+    /// Next write to 0x0000 selects the bank
+    bank_switch: bool,
+    /// Selected 64KB bank of 128KB Flash
+    bank: usize,
+}
+
+/// Backup memory of the cartridge
+#[derive(Debug, Default)]
+pub struct Backup {
+    save_type: SaveType,
+    /// Contents of the backup memory, written into the save file
+    data: Vec<u8>,
+    flash: Flash,
+}
+
+impl SaveType {
+    /// Size of the backup memory in bytes, None if there's no backup
+    fn size(self) -> Option<usize> {
+        match self {
+            SaveType::None => None,
+            SaveType::Eeprom => Some(0x2000),
+            SaveType::Sram => Some(0x8000),
+            SaveType::Flash64K => Some(0x10000),
+            SaveType::Flash128K => Some(0x20000),
+        }
+    }
+
+    /// Manufacturer and device code returned in the Flash identification mode
+    fn flash_id(self) -> [u8; 2] {
+        match self {
+            // Sanyo
+            SaveType::Flash128K => [0x62, 0x13],
+            // Panasonic
+            _ => [0x32, 0x1B],
+        }
+    }
+}
+
+impl Backup {
+    pub fn new(save_type: SaveType) -> Self {
+        let mut backup = Self::default();
+        backup.set_save_type(save_type);
+        backup
+    }
+
+    pub fn save_type(&self) -> SaveType {
+        self.save_type
+    }
+
+    /// Change the type, the data is kept and resized to the new type.
+    /// Erased backup memory reads as 0xFF.
+    pub fn set_save_type(&mut self, save_type: SaveType) {
+        self.save_type = save_type;
+        self.flash = Flash::default();
+        if let Some(size) = save_type.size() {
+            self.data.resize(size, 0xFF);
+        }
+    }
+
+    /// Contents of the backup memory, for example from a save file.
+    /// Can be loaded before the type is known.
+    pub fn load(&mut self, data: &[u8]) {
+        self.data = data.to_vec();
+        self.set_save_type(self.save_type);
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Is the backup memory readable from the 0x0E region
+    pub fn is_mapped(&self) -> bool {
+        matches!(
+            self.save_type,
+            SaveType::Sram | SaveType::Flash64K | SaveType::Flash128K
+        )
+    }
+
+    pub fn read8(&self, addr: u32) -> u8 {
+        let offset = (addr & 0xFFFF) as usize;
+        match self.save_type {
+            SaveType::Sram => self.data[offset & 0x7FFF],
+            SaveType::Flash64K | SaveType::Flash128K => {
+                if self.flash.id_mode && offset < 2 {
+                    self.save_type.flash_id()[offset]
+                } else {
+                    self.data[self.flash.bank * 0x10000 + offset]
+                }
+            }
+            // TODO: EEPROM is accessed serially through the 0x0D region with DMA
+            SaveType::Eeprom | SaveType::None => 0xFF,
+        }
+    }
+
+    pub fn write8(&mut self, addr: u32, value: u8) {
+        let offset = (addr & 0xFFFF) as usize;
+        match self.save_type {
+            SaveType::Sram => self.data[offset & 0x7FFF] = value,
+            SaveType::Flash64K | SaveType::Flash128K => self.write_flash(offset, value),
+            SaveType::Eeprom | SaveType::None => {}
+        }
+    }
+
+    fn write_flash(&mut self, offset: usize, value: u8) {
+        let bank = self.flash.bank * 0x10000;
+        if self.flash.write {
+            self.flash.write = false;
+            self.data[bank + offset] = value;
+            return;
+        }
+        if self.flash.bank_switch {
+            self.flash.bank_switch = false;
+            if offset == 0 {
+                self.flash.bank = (value & 1) as usize;
+            }
+            return;
+        }
+
+        self.flash.command = match (self.flash.command, offset, value) {
+            (FlashCommand::Ready, 0x5555, 0xAA) => FlashCommand::Unlock1,
+            (FlashCommand::Unlock1, 0x2AAA, 0x55) => FlashCommand::Unlock2,
+            // erase the 4KB sector
+            (FlashCommand::Unlock2, _, 0x30) if self.flash.erase => {
+                self.flash.erase = false;
+                let sector = bank + (offset & 0xF000);
+                self.data[sector..sector + 0x1000].fill(0xFF);
+                FlashCommand::Ready
+            }
+            (FlashCommand::Unlock2, 0x5555, command) => {
+                match command {
+                    0x90 => self.flash.id_mode = true,
+                    0xF0 => self.flash.id_mode = false,
+                    0x80 => self.flash.erase = true,
+                    0x10 if self.flash.erase => {
+                        self.flash.erase = false;
+                        self.data.fill(0xFF);
+                    }
+                    0xA0 => self.flash.write = true,
+                    0xB0 if self.save_type == SaveType::Flash128K => self.flash.bank_switch = true,
+                    _ => {}
+                }
+                FlashCommand::Ready
+            }
+            _ => FlashCommand::Ready,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write the Flash command `command` to 0x5555
+    fn flash_command(backup: &mut Backup, command: u8) {
+        backup.write8(0x0E005555, 0xAA);
+        backup.write8(0x0E002AAA, 0x55);
+        backup.write8(0x0E005555, command);
+    }
+
+    #[test]
+    fn test_sram() {
+        let mut backup = Backup::new(SaveType::Sram);
+        assert_eq!(backup.data().len(), 0x8000);
+        assert_eq!(backup.read8(0x0E000000), 0xFF);
+
+        backup.write8(0x0E000010, 0x12);
+        assert_eq!(backup.read8(0x0E000010), 0x12);
+        // mirrored every 32KB
+        assert_eq!(backup.read8(0x0E008010), 0x12);
+        assert_eq!(backup.read8(0x0F000010), 0x12);
+    }
+
+    #[test]
+    fn test_load_before_type() {
+        let mut backup = Backup::default();
+        backup.load(&[1, 2, 3]);
+        assert_eq!(backup.data(), &[1, 2, 3]);
+
+        backup.set_save_type(SaveType::Sram);
+        assert_eq!(backup.data().len(), 0x8000);
+        assert_eq!(backup.read8(0x0E000002), 3);
+        assert_eq!(backup.read8(0x0E000003), 0xFF);
+    }
+
+    #[test]
+    fn test_flash_commands() {
+        let mut backup = Backup::new(SaveType::Flash128K);
+
+        flash_command(&mut backup, 0x90);
+        assert_eq!(backup.read8(0x0E000000), 0x62);
+        assert_eq!(backup.read8(0x0E000001), 0x13);
+        flash_command(&mut backup, 0xF0);
+        assert_eq!(backup.read8(0x0E000000), 0xFF);
+
+        // plain writes don't program the Flash
+        backup.write8(0x0E000100, 0x12);
+        assert_eq!(backup.read8(0x0E000100), 0xFF);
+        flash_command(&mut backup, 0xA0);
+        backup.write8(0x0E000100, 0x12);
+        assert_eq!(backup.read8(0x0E000100), 0x12);
+        flash_command(&mut backup, 0xA0);
+        backup.write8(0x0E001000, 0x34);
+
+        // second bank
+        flash_command(&mut backup, 0xB0);
+        backup.write8(0x0E000000, 1);
+        assert_eq!(backup.read8(0x0E000100), 0xFF);
+        flash_command(&mut backup, 0xA0);
+        backup.write8(0x0E000100, 0x56);
+        assert_eq!(backup.data()[0x10100], 0x56);
+        flash_command(&mut backup, 0xB0);
+        backup.write8(0x0E000000, 0);
+        assert_eq!(backup.read8(0x0E000100), 0x12);
+
+        // sector erase only clears its 4KB
+        flash_command(&mut backup, 0x80);
+        backup.write8(0x0E005555, 0xAA);
+        backup.write8(0x0E002AAA, 0x55);
+        backup.write8(0x0E000000, 0x30);
+        assert_eq!(backup.read8(0x0E000100), 0xFF);
+        assert_eq!(backup.read8(0x0E001000), 0x34);
+
+        flash_command(&mut backup, 0x80);
+        flash_command(&mut backup, 0x10);
+        assert!(backup.data().iter().all(|byte| *byte == 0xFF));
+    }
+}
@@ -0,0 +1,38 @@
+//! High level emulation of the BIOS functions called with SWI
+
+/// BIOS Div, returns the quotient, remainder and absolute value of the quotient.
+///
+/// Division by zero hangs the real BIOS when `abs(num) > 1`,
+/// instead the quotient is 1 with the sign of `num` and the remainder is `num`.
+pub fn bios_div(num: i32, den: i32) -> (i32, i32, i32) {
+    if den == 0 {
+        let quot = if num < 0 { -1 } else { 1 };
+        return (quot, num, 1);
+    }
+
+    // i32::MIN / -1 overflows, hardware wraps it back to i32::MIN
+    let quot = num.wrapping_div(den);
+    let rem = num.wrapping_rem(den);
+    (quot, rem, quot.wrapping_abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bios_div() {
+        assert_eq!(bios_div(7, 2), (3, 1, 3));
+        assert_eq!(bios_div(-7, 2), (-3, -1, 3));
+        assert_eq!(bios_div(7, -2), (-3, 1, 3));
+        assert_eq!(bios_div(-7, -2), (3, -1, 3));
+        assert_eq!(bios_div(i32::MIN, -1), (i32::MIN, 0, i32::MIN));
+    }
+
+    #[test]
+    fn test_bios_div_by_zero() {
+        assert_eq!(bios_div(5, 0), (1, 5, 1));
+        assert_eq!(bios_div(-5, 0), (-1, -5, 1));
+        assert_eq!(bios_div(0, 0), (1, 0, 1));
+    }
+}
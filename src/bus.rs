@@ -0,0 +1,444 @@
+//! Memory bus between the cpu and the memory regions of the GBA
+
+use std::fmt::Debug;
+
+use crate::{
+    backup::Backup,
+    gba_file::{detect_save_type, SaveType},
+    mem::{
+        classify, Region, BIOS_SIZE, EWRAM_SIZE, IO_SIZE, IWRAM_SIZE, OAM_SIZE, PALETTE_SIZE,
+        ROM_MAX_SIZE, VRAM_SIZE,
+    },
+};
+
+/// Little-endian memory seen by the cpu. Only the byte accesses are required,
+/// wider accesses are made of bytes unless the implementation has a faster way.
+pub trait Bus: Debug {
+    fn read8(&self, addr: u32) -> u8;
+
+    fn write8(&mut self, addr: u32, value: u8);
+
+    fn read16(&self, addr: u32) -> u16 {
+        u16::from_le_bytes([self.read8(addr), self.read8(addr.wrapping_add(1))])
+    }
+
+    fn read32(&self, addr: u32) -> u32 {
+        u32::from_le_bytes([
+            self.read8(addr),
+            self.read8(addr.wrapping_add(1)),
+            self.read8(addr.wrapping_add(2)),
+            self.read8(addr.wrapping_add(3)),
+        ])
+    }
+
+    fn write16(&mut self, addr: u32, value: u16) {
+        for (idx, byte) in value.to_le_bytes().into_iter().enumerate() {
+            self.write8(addr.wrapping_add(idx as u32), byte);
+        }
+    }
+
+    fn write32(&mut self, addr: u32, value: u32) {
+        for (idx, byte) in value.to_le_bytes().into_iter().enumerate() {
+            self.write8(addr.wrapping_add(idx as u32), byte);
+        }
+    }
+
+    /// Write `value` into `words` consecutive words from `start`
+    fn fill32(&mut self, start: u32, value: u32, words: usize) {
+        for idx in 0..words {
+            self.write32(start.wrapping_add(idx as u32 * 4), value);
+        }
+    }
+
+    /// Is there readable memory or ROM at `addr`
+    fn is_mapped(&self, _addr: u32) -> bool {
+        true
+    }
+
+    /// Last opcode fetched by the cpu, Thumb opcodes in both halves.
+    /// Reads from unmapped addresses see it on the real hardware.
+    fn set_open_bus(&mut self, _value: u32) {}
+
+    /// Map the cartridge ROM
+    fn load_rom(&mut self, _rom: &[u8]) {}
+
+    /// The loaded cartridge ROM
+    fn rom(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Clear the RAM and I/O like a power cycle does, the ROM and the backup
+    /// memory are kept
+    fn reset(&mut self) {}
+
+    /// Restore the cartridge backup memory, for example from a save file
+    fn load_backup(&mut self, _data: &[u8]) {}
+
+    /// Contents of the cartridge backup memory
+    fn backup(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Type of the cartridge backup memory
+    fn save_type(&self) -> SaveType {
+        SaveType::None
+    }
+}
+
+/// RAM regions of the memory map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ram {
+    /// BIOS area, there's no BIOS image so it's plain memory for now
+    Bios,
+    Ewram,
+    Iwram,
+    Io,
+    Palette,
+    Vram,
+    Oam,
+}
+
+/// Memory map of the GBA, dispatches the accesses to RAM, I/O, ROM, backup or the open bus.
+/// Each region has its own memory sized like on the real hardware.
+#[derive(Debug)]
+pub struct GbaBus {
+    /// BIOS area, 16KB
+    bios: Vec<u8>,
+    /// On-board work RAM, 256KB
+    ewram: Vec<u8>,
+    /// On-chip work RAM, 32KB
+    iwram: Vec<u8>,
+    io: Vec<u8>,
+    /// Palette RAM, 1KB
+    palette: Vec<u8>,
+    /// 96KB of VRAM
+    vram: Vec<u8>,
+    /// Object attribute memory, 1KB
+    oam: Vec<u8>,
+    /// Cartridge backup memory, its type is detected from the ROM
+    backup: Backup,
+    /// Cartridge ROM, mapped to 0x08000000 and mirrored at 0x0A000000 and 0x0C000000
+    rom: Vec<u8>,
+    /// Last fetched opcode, read back from unmapped addresses
+    open_bus: u32,
+}
+
+impl GbaBus {
+    pub fn new() -> Self {
+        Self {
+            bios: vec![0; BIOS_SIZE as usize],
+            ewram: vec![0; EWRAM_SIZE as usize],
+            iwram: vec![0; IWRAM_SIZE as usize],
+            io: vec![0; IO_SIZE as usize],
+            palette: vec![0; PALETTE_SIZE as usize],
+            vram: vec![0; VRAM_SIZE as usize],
+            oam: vec![0; OAM_SIZE as usize],
+            backup: Backup::default(),
+            rom: Vec::new(),
+            open_bus: 0,
+        }
+    }
+
+    /// Bytes allocated for the memory regions and the ROM
+    pub fn allocated_size(&self) -> usize {
+        [
+            Ram::Bios,
+            Ram::Ewram,
+            Ram::Iwram,
+            Ram::Io,
+            Ram::Palette,
+            Ram::Vram,
+            Ram::Oam,
+        ]
+        .map(|ram| self.ram(ram).len())
+        .iter()
+        .sum::<usize>()
+            + self.backup.data().len()
+            + self.rom.len()
+    }
+
+    /// Offset into the ROM of an address in the 0x08, 0x0A or 0x0C wait state window,
+    /// all three of them map the same 32MB of the cartridge
+    fn rom_offset(addr: u32) -> usize {
+        (addr & (ROM_MAX_SIZE - 1)) as usize
+    }
+
+    /// RAM region of `addr` and the offset into it with the mirrors applied,
+    /// None for ROM, backup and unmapped addresses
+    fn ram_offset(addr: u32) -> Option<(Ram, usize)> {
+        let offset = addr & 0xFFFFFF;
+        let location = match classify(addr) {
+            Region::Bios => (Ram::Bios, offset),
+            Region::Ewram => (Ram::Ewram, offset & (EWRAM_SIZE - 1)),
+            Region::Iwram => (Ram::Iwram, offset & (IWRAM_SIZE - 1)),
+            Region::Io => (Ram::Io, offset),
+            Region::Palette => (Ram::Palette, offset & (PALETTE_SIZE - 1)),
+            // 128KB mirrors, the last 32KB mirror the previous 32KB
+            Region::Vram => match offset & 0x1FFFF {
+                offset @ VRAM_SIZE.. => (Ram::Vram, offset - 0x8000),
+                offset => (Ram::Vram, offset),
+            },
+            Region::Oam => (Ram::Oam, offset & (OAM_SIZE - 1)),
+            Region::Rom | Region::Backup | Region::Unmapped => return None,
+        };
+        Some((location.0, location.1 as usize))
+    }
+
+    fn ram(&self, ram: Ram) -> &[u8] {
+        match ram {
+            Ram::Bios => &self.bios,
+            Ram::Ewram => &self.ewram,
+            Ram::Iwram => &self.iwram,
+            Ram::Io => &self.io,
+            Ram::Palette => &self.palette,
+            Ram::Vram => &self.vram,
+            Ram::Oam => &self.oam,
+        }
+    }
+
+    fn ram_mut(&mut self, ram: Ram) -> &mut [u8] {
+        match ram {
+            Ram::Bios => &mut self.bios,
+            Ram::Ewram => &mut self.ewram,
+            Ram::Iwram => &mut self.iwram,
+            Ram::Io => &mut self.io,
+            Ram::Palette => &mut self.palette,
+            Ram::Vram => &mut self.vram,
+            Ram::Oam => &mut self.oam,
+        }
+    }
+}
+
+impl Default for GbaBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for GbaBus {
+    fn reset(&mut self) {
+        *self = Self {
+            rom: std::mem::take(&mut self.rom),
+            backup: std::mem::take(&mut self.backup),
+            ..Self::new()
+        };
+    }
+
+    fn read8(&self, addr: u32) -> u8 {
+        if let Some((ram, offset)) = Self::ram_offset(addr) {
+            return self.ram(ram)[offset];
+        }
+
+        match classify(addr) {
+            // cartridge ROM and its wait state mirrors
+            Region::Rom => self.rom.get(Self::rom_offset(addr)).copied().unwrap_or(0),
+            Region::Backup if self.backup.is_mapped() => self.backup.read8(addr),
+            // unmapped I/O and BIOS, the unused 0x01 region and beyond the address bus
+            _ => (self.open_bus >> ((addr & 3) * 8)) as u8,
+        }
+    }
+
+    fn write8(&mut self, addr: u32, value: u8) {
+        // ROM is read only and writes to unmapped addresses are ignored
+        if let Some((ram, offset)) = Self::ram_offset(addr) {
+            self.ram_mut(ram)[offset] = value;
+        } else if classify(addr) == Region::Backup {
+            self.backup.write8(addr, value);
+        }
+    }
+
+    /// Fill as much of a region at a time as possible instead of word by word.
+    /// Words in ROM and unmapped memory are skipped, backup memory is written word by word.
+    fn fill32(&mut self, start: u32, value: u32, words: usize) {
+        let bytes = value.to_le_bytes();
+        let mut addr = start;
+        let mut left = words;
+        while left > 0 {
+            if classify(addr) == Region::Backup {
+                self.write32(addr, value);
+                addr = addr.wrapping_add(4);
+                left -= 1;
+                continue;
+            }
+
+            // words until the end of the region or its mirror
+            let region_end = ((addr >> 24) as u64 + 1) << 24;
+            let (run, ram) = match Self::ram_offset(addr) {
+                Some((ram, offset)) => {
+                    let mirror_end = (self.ram(ram).len() - offset) as u64;
+                    let run = mirror_end.min(region_end - addr as u64) / 4;
+                    (run as usize, Some((ram, offset)))
+                }
+                None => (((region_end - addr as u64) / 4) as usize, None),
+            };
+            let run = run.min(left);
+
+            if run == 0 {
+                // unaligned word across the end of the region
+                self.write32(addr, value);
+                addr = addr.wrapping_add(4);
+                left -= 1;
+                continue;
+            }
+
+            if let Some((ram, offset)) = ram {
+                let memory = &mut self.ram_mut(ram)[offset..offset + run * 4];
+                for word in memory.chunks_exact_mut(4) {
+                    word.copy_from_slice(&bytes);
+                }
+            }
+
+            addr = addr.wrapping_add((run * 4) as u32);
+            left -= run;
+        }
+    }
+
+    fn is_mapped(&self, addr: u32) -> bool {
+        match classify(addr) {
+            Region::Rom => Self::rom_offset(addr) < self.rom.len(),
+            Region::Backup => self.backup.is_mapped(),
+            _ => Self::ram_offset(addr).is_some(),
+        }
+    }
+
+    fn set_open_bus(&mut self, value: u32) {
+        self.open_bus = value;
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) {
+        self.rom = rom.to_vec();
+        self.backup.set_save_type(detect_save_type(rom));
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn load_backup(&mut self, data: &[u8]) {
+        self.backup.load(data);
+    }
+
+    fn backup(&self) -> &[u8] {
+        self.backup.data()
+    }
+
+    fn save_type(&self) -> SaveType {
+        self.backup.save_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gba_bus_regions() {
+        let mut bus = GbaBus::new();
+        bus.load_rom(&[0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(bus.read32(0x08000000), 0x12345678);
+        // wait state mirror
+        assert_eq!(bus.read16(0x0A000002), 0x1234);
+        // ROM is read only and reads past its end as 0
+        bus.write32(0x08000000, 0);
+        assert_eq!(bus.read32(0x08000000), 0x12345678);
+        assert_eq!(bus.read32(0x08000004), 0);
+        assert!(!bus.is_mapped(0x08000004));
+
+        bus.write16(0x03000002, 0xBEEF);
+        assert_eq!(bus.read32(0x03000000), 0xBEEF0000);
+
+        bus.set_open_bus(0xE5910000);
+        assert_eq!(bus.read32(0x04000400), 0xE5910000);
+        assert_eq!(bus.read8(0x10000001), 0x00);
+        assert_eq!(bus.read8(0x10000003), 0xE5);
+    }
+
+    #[test]
+    fn test_rom_wait_state_mirrors() {
+        let rom: Vec<u8> = (0..0x100).map(|idx| idx as u8).collect();
+        let mut bus = GbaBus::new();
+        bus.load_rom(&rom);
+        for offset in [0, 0x41, 0xFC] {
+            let expected = bus.read32(0x08000000 + offset);
+            for window in [0x0A000000, 0x0C000000] {
+                assert_eq!(bus.read32(window + offset), expected, "{window:08x}");
+                assert_eq!(bus.read8(window + offset), rom[offset as usize]);
+                assert!(bus.is_mapped(window + offset));
+            }
+        }
+        // each window is 32MB and reads past the end of the ROM as 0
+        assert_eq!(bus.read8(0x0B000000), 0);
+        assert!(!bus.is_mapped(0x0D000000));
+    }
+
+    #[test]
+    fn test_sram_restored_from_backup() {
+        let mut rom = vec![0; 0x200];
+        rom[0x100..0x109].copy_from_slice(b"SRAM_V113");
+
+        let mut bus = GbaBus::new();
+        bus.load_rom(&rom);
+        assert_eq!(bus.save_type(), SaveType::Sram);
+        bus.write8(0x0E000000, 0x12);
+        bus.write8(0x0E007FFF, 0x34);
+        let save = bus.backup().to_vec();
+        assert_eq!(save.len(), 0x8000);
+
+        // save file is loaded before the ROM
+        let mut bus = GbaBus::new();
+        bus.load_backup(&save);
+        bus.load_rom(&rom);
+        assert_eq!(bus.read8(0x0E000000), 0x12);
+        assert_eq!(bus.read8(0x0E007FFF), 0x34);
+        assert_eq!(bus.read8(0x0E008000), 0x12);
+
+        // no backup without the ID string
+        let mut bus = GbaBus::new();
+        bus.load_rom(&[0; 0x200]);
+        bus.set_open_bus(0xE5910000);
+        bus.write8(0x0E000003, 0x12);
+        assert!(!bus.is_mapped(0x0E000000));
+        assert_eq!(bus.read8(0x0E000003), 0xE5);
+    }
+
+    #[test]
+    fn test_gba_bus_region_sizes_and_mirrors() {
+        let mut bus = GbaBus::new();
+        // far below the old flat 256MB
+        assert!(bus.allocated_size() < 0x80000);
+
+        // (start, size) of every region, the first byte after it is a mirror
+        let regions = [
+            (0x00000000, 0x4000),
+            (0x02000000, 0x40000),
+            (0x03000000, 0x8000),
+            (0x04000000, IO_SIZE),
+            (0x05000000, 0x400),
+            (0x06000000, 0x18000),
+            (0x07000000, 0x400),
+        ];
+        for (idx, (start, size)) in regions.into_iter().enumerate() {
+            let value = 0x11111111 * (idx as u32 + 1);
+            bus.write32(start, value);
+            bus.write32(start + size - 4, !value);
+            assert_eq!(bus.read32(start), value, "{start:08x}");
+            assert_eq!(bus.read32(start + size - 4), !value, "{start:08x}");
+            assert!(bus.is_mapped(start + size - 1));
+        }
+
+        assert_eq!(bus.read32(0x02040000), 0x22222222);
+        assert_eq!(bus.read32(0x03FF8000), 0x33333333);
+        assert_eq!(bus.read32(0x05000400), 0x55555555);
+        assert_eq!(bus.read32(0x07000400), 0x77777777);
+        // upper 32KB of the 128KB VRAM mirror
+        bus.write32(0x06010000, 0xCAFEBABE);
+        assert_eq!(bus.read32(0x06018000), 0xCAFEBABE);
+        assert_eq!(bus.read32(0x06020000), 0x66666666);
+
+        // past the BIOS and I/O is unmapped
+        assert!(!bus.is_mapped(0x00004000));
+        assert!(!bus.is_mapped(0x04000400));
+        bus.write32(0x00004000, 0x12345678);
+        assert_eq!(bus.read32(0x00004000), 0);
+    }
+}
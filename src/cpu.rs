@@ -1,21 +1,40 @@
-use std::fmt::Display;
+use std::{cell::RefCell, collections::VecDeque, fmt::Display, time::Duration};
 
 use crate::{
-    gba_file::GBAHeader,
+    bios::bios_div,
+    bus::{Bus, GbaBus},
+    disasm::disassemble_step,
+    gba_file::{GBAHeader, SaveType},
     instr::{
-        arm::{Alu, AluOp, Branch, BranchExchange, Instruction, Sdt},
+        arm::{
+            Alu, AluOp, Branch, BranchExchange, BranchLinkExchange, Condition, Instruction, Sdt,
+            Swap, Swi,
+        },
         common::{EResult, ExecErr, Register},
         thumb::{
             ThumbAddSub, ThumbAlu, ThumbAluOp, ThumbBranch, ThumbBranchOp, ThumbHiReg,
             ThumbHiRegOp, ThumbInstr, ThumbLongBranch, ThumbLsh, ThumbLshOp, ThumbLsi, ThumbLsiOp,
             ThumbMcas, ThumbMcasOp, ThumbMls, ThumbMlsOp, ThumbMultLS, ThumbMultLSOp, ThumbPushPop,
-            ThumbPushPopOp, ThumbRegShift, ThumbRegShiftOp, ThumbUBranch,
+            ThumbPushPopOp, ThumbRegShift, ThumbRegShiftOp, ThumbSwi, ThumbUBranch,
         },
+        DecodedInstr, Executable,
+    },
+    keypad::{keypad_irq, Key, KEYCNT, KEYINPUT, KEYS_RELEASED},
+    lcd::{
+        LcdState, DISPSTAT, DISPSTAT_HBLANK_IRQ, DISPSTAT_VBLANK_IRQ, DISPSTAT_VCOUNT,
+        DISPSTAT_VCOUNT_IRQ, FRAME_CYCLES, VCOUNT,
     },
+    logger::{LogCategory, Logger},
     logging,
+    mem::{
+        EWRAM_SIZE, EWRAM_START, IO_SIZE, IO_START, IWRAM_SIZE, IWRAM_START, OAM_SIZE, OAM_START,
+        PALETTE_SIZE, PALETTE_START, ROM_MAX_SIZE, ROM_START, VRAM_SIZE, VRAM_START,
+    },
+    prefetch::{is_cacheable, Prefetch},
+    stats::Stats,
 };
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Cpu {
     pub r0: u32,
     pub r1: u32,
@@ -25,12 +44,23 @@ pub struct Cpu {
     pub r5: u32,
     pub r6: u32,
     pub r7: u32,
+    pub r8: u32,
+    pub r9: u32,
+    pub r10: u32,
+    pub r11: u32,
+    pub r12: u32,
     /// R13
     pub sp: u32,
     /// R14
     pub lr: u32,
     /// R15
     pub pc: u32,
+    /// Banked R13 of IRQ mode, TODO: switched in when processor modes are emulated
+    sp_irq: u32,
+    /// Banked R13 of Supervisor mode
+    sp_svc: u32,
+    /// Saved CPSR of the current exception mode, TODO: banked per mode
+    spsr: u32,
     /// N - Sign Flag (false(0)=Not Signed, true(1)=Signed)
     sign_flag: bool,
     /// Z - Zero Flag (false(0)=Not Zero, true(1)=Zero)
@@ -40,11 +70,349 @@ pub struct Cpu {
     /// V - Overflow Flag (false(0)=No Overflow, true(1)=Overflow)
     overflow_flag: bool,
     thumb: bool,
+    /// M4-M0 of CPSR, TODO: registers aren't banked per mode yet
+    mode: ProcessorMode,
+    /// Take the Undefined Instruction exception on undefined encodings
+    /// instead of returning `ExecErr::Undefined`
+    undefined_exceptions: bool,
+
+    /// Did the last executed instruction branch into itself
+    halted: bool,
+    /// Number of executed calls (BL) minus returns (BX, MOV PC and POP {PC}),
+    /// used by the debugger to find where subroutines return
+    call_depth: i64,
+    /// Cycles executed since the start, drives the LCD timing
+    cycles: u64,
+    logger: Logger,
+    /// Inclusive address range of memory accesses recorded in `trace`
+    trace_range: Option<(u32, u32)>,
+    /// Recorded memory accesses, RefCell since reads are recorded too
+    trace: RefCell<Vec<TraceEntry>>,
+    /// Watched addresses and if they're watched for writes or reads
+    access_watches: Vec<(u32, bool)>,
+    /// Accesses that hit the watched addresses since the last `take_watch_hits`
+    watch_hits: RefCell<Vec<TraceEntry>>,
+    /// Memory, I/O and the cartridge ROM
+    bus: Box<dyn Bus>,
+    /// How the ROM was booted, used again on reset
+    boot: BootConfig,
+    /// Last `HISTORY_SIZE` executed instructions, oldest first
+    history: VecDeque<HistoryEntry>,
+    /// Emulation speed since the start or the last reset
+    stats: Stats,
+    /// Code around PC, invalidated by writes into it
+    prefetch: Prefetch,
+}
+
+/// Instruction executed by `Cpu::step`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepInfo {
+    /// Address of the instruction
+    pub pc: u32,
+    /// ARM word or Thumb halfword, like in `HistoryEntry`
+    pub opcode: u32,
+    pub instr: DecodedInstr,
+    /// PC after the instruction
+    pub next_pc: u32,
+}
+
+/// Names of the registers in the order of `Cpu::register_values`
+pub const REGISTER_NAMES: [&str; 16] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "sp", "lr",
+    "pc",
+];
+
+/// Number of executed instructions kept in the instruction history
+pub const HISTORY_SIZE: usize = 32;
+
+/// Instruction recorded in the history of executed instructions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub pc: u32,
+    /// ARM word or Thumb halfword, Thumb long branches have the second halfword
+    /// in the upper half
+    pub opcode: u32,
+    pub thumb: bool,
+}
+
+/// Exception vector of undefined instructions
+pub const UNDEFINED_VECTOR: u32 = 0x04;
+
+/// Processor mode in the mode bits of CPSR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProcessorMode {
+    User = 0x10,
+    Fiq = 0x11,
+    Irq = 0x12,
+    Supervisor = 0x13,
+    Abort = 0x17,
+    Undefined = 0x1B,
+    #[default]
+    System = 0x1F,
+}
+
+impl ProcessorMode {
+    /// Mode of the mode bits, None for the reserved values
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits & 0x1F {
+            0x10 => Some(Self::User),
+            0x11 => Some(Self::Fiq),
+            0x12 => Some(Self::Irq),
+            0x13 => Some(Self::Supervisor),
+            0x17 => Some(Self::Abort),
+            0x1B => Some(Self::Undefined),
+            0x1F => Some(Self::System),
+            _ => None,
+        }
+    }
+}
+
+/// How the cpu starts running a ROM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootConfig {
+    /// Address of the first executed instruction when the BIOS is skipped,
+    /// the ROM start or for example 0x02000000 for multiboot images in EWRAM
+    pub entry: u32,
+    /// Start from the state the BIOS leaves behind when it jumps to the entry point.
+    /// Otherwise the cpu starts from the BIOS reset vector with zeroed registers.
+    pub skip_bios: bool,
+    /// Initial stack pointer of User and System mode
+    pub sp_usr: u32,
+    /// Initial stack pointer of IRQ mode
+    pub sp_irq: u32,
+    /// Initial stack pointer of Supervisor mode
+    pub sp_svc: u32,
+    /// Copy the image into EWRAM like a multiboot transfer does. The image is
+    /// mapped as the cartridge ROM too, so reset can copy it again.
+    pub multiboot: bool,
+}
+
+impl Default for BootConfig {
+    /// Direct boot from the ROM with the stack pointers set up by the BIOS
+    fn default() -> Self {
+        Self {
+            entry: ROM_START,
+            skip_bios: true,
+            sp_usr: 0x03007F00,
+            sp_irq: 0x03007FA0,
+            sp_svc: 0x03007FE0,
+            multiboot: false,
+        }
+    }
+}
+
+/// Offset of the multiboot entry point in the header of the image
+const RAM_ENTRY_OFFSET: u32 = 0xC0;
+
+impl BootConfig {
+    /// Direct boot of a multiboot image from its RAM entry point in EWRAM
+    pub fn multiboot() -> Self {
+        Self {
+            entry: EWRAM_START + RAM_ENTRY_OFFSET,
+            multiboot: true,
+            ..Self::default()
+        }
+    }
+
+    /// Multiboot if the image fits in EWRAM, the header has the boot mode set by
+    /// the multiboot transfer and the RAM entry point is a `B` opcode,
+    /// otherwise the default ROM boot
+    pub fn detect(bytes: &[u8]) -> Self {
+        let Ok(header) = GBAHeader::from_file(bytes) else {
+            return Self::default();
+        };
+        if bytes.len() <= EWRAM_SIZE as usize
+            && (1..=3).contains(&header.boot_mode)
+            && header.ram_entry_point >> 24 == 0xEA
+        {
+            Self::multiboot()
+        } else {
+            Self::default()
+        }
+    }
+}
+
+/// Interrupt request flags register
+const IF: u32 = 0x04000202;
+
+/// Cycles of the `B .` a halted cpu keeps repeating
+const HALTED_CYCLES: u64 = 3;
+/// IF bit 0, vertical blank
+const IRQ_VBLANK: u16 = 1 << 0;
+/// IF bit 1, horizontal blank
+const IRQ_HBLANK: u16 = 1 << 1;
+/// IF bit 2, VCount match
+const IRQ_VCOUNT: u16 = 1 << 2;
+/// IF bit 12, keypad
+const IRQ_KEYPAD: u16 = 1 << 12;
+
+/// Memory access recorded by the memory trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    /// Address of the instruction that accessed the memory
+    pub pc: u32,
+    pub addr: u32,
+    pub value: u32,
+    pub is_write: bool,
+    /// Size of the access in bytes
+    pub size: u8,
+}
+
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.is_write { "write" } else { "read" };
+        let width = self.size as usize * 2;
+        write!(
+            f,
+            "{:08x}: {kind} {:08x} = {:0width$x}",
+            self.pc, self.addr, self.value
+        )
+    }
+}
+
+/// The address bus is 28 bits wide, the upper 4 bits of an address are ignored
+const ADDRESS_MASK: u32 = 0x0FFFFFFF;
+
+/// Identifies the save state format
+const STATE_MAGIC: &[u8; 4] = b"GBAS";
+
+/// Bytes of the cpu state in save states: r0-r15, CPSR, SPSR, the banked
+/// SP_irq and SP_svc, the cycle count and the halted flag
+const STATE_CPU_SIZE: usize = 20 * 4 + 8 + 1;
+
+/// Memory regions (start address, size) included in save states.
+/// The ROM is not saved and the backup memory is saved after the regions.
+const STATE_REGIONS: [(u32, u32); 6] = [
+    (EWRAM_START, EWRAM_SIZE),
+    (IWRAM_START, IWRAM_SIZE),
+    (IO_START, IO_SIZE),
+    (PALETTE_START, PALETTE_SIZE),
+    (VRAM_START, VRAM_SIZE),
+    (OAM_START, OAM_SIZE),
+];
+
+/// Cycles taken by `instr` without memory wait states, so sequential and
+/// non-sequential accesses both take a cycle. Instructions that `branched`
+/// take two more cycles to refill the pipeline.
+fn instr_cycles(instr: &DecodedInstr, branched: bool) -> u64 {
+    let base = match instr {
+        DecodedInstr::Arm(instr) => match instr {
+            // shift by register takes an internal cycle
+            Instruction::Alu(alu) => 1 + (!alu.immediate && (alu.operand >> 4) & 1 == 1) as u64,
+            // loads take an internal cycle, stores are two non-sequential accesses
+            Instruction::Sdt(sdt) => 2 + sdt.load_memory as u64,
+            Instruction::Swap(_) => 4,
+            // the BIOS calls are emulated, count only the exception entry
+            Instruction::Swi(_) => 3,
+            Instruction::Branch(_)
+            | Instruction::BranchExchange(_)
+            | Instruction::BranchLinkExchange(_)
+            | Instruction::Pld(_)
+            | Instruction::Psr => 1,
+        },
+        DecodedInstr::Thumb(instr) => match instr {
+            ThumbInstr::Alu(alu) => match alu.op {
+                ThumbAluOp::Ror | ThumbAluOp::Mul => 2,
+                _ => 1,
+            },
+            ThumbInstr::Mls(_) => 3,
+            ThumbInstr::Lsi(lsi) => match lsi.op {
+                ThumbLsiOp::Ldr | ThumbLsiOp::Ldrb => 3,
+                ThumbLsiOp::Str | ThumbLsiOp::Strb => 2,
+            },
+            ThumbInstr::Lsh(lsh) => match lsh.op {
+                ThumbLshOp::Ldrh => 3,
+                ThumbLshOp::Strh => 2,
+            },
+            ThumbInstr::PushPop(push_pop) => {
+                let count = push_pop.rlist.len() as u64;
+                match push_pop.op {
+                    ThumbPushPopOp::Push => count + 1,
+                    ThumbPushPopOp::Pop => count + 2,
+                }
+            }
+            ThumbInstr::MultLS(mult_ls) => {
+                let count = mult_ls.rlist.len() as u64;
+                match mult_ls.op {
+                    ThumbMultLSOp::Stmia => count + 1,
+                    ThumbMultLSOp::Ldmia => count + 2,
+                }
+            }
+            ThumbInstr::Swi(_) => 3,
+            // both halves of the long branch
+            ThumbInstr::LongBranch(_) => 2,
+            ThumbInstr::HiReg(_)
+            | ThumbInstr::Mcas(_)
+            | ThumbInstr::AddSub(_)
+            | ThumbInstr::Branch(_)
+            | ThumbInstr::UBranch(_)
+            | ThumbInstr::RegShift(_) => 1,
+        },
+        DecodedInstr::Undefined { .. } => 1,
+    };
+
+    base + if branched { 2 } else { 0 }
+}
+
+/// `a + b` with the carry and overflow flags of ARM ADD
+fn add_flags(a: u32, b: u32) -> (u32, bool, bool) {
+    let (result, carry) = a.overflowing_add(b);
+    let (_, overflow) = (a as i32).overflowing_add(b as i32);
+    (result, carry, overflow)
+}
+
+/// `a - b` with the carry (no borrow) and overflow flags of ARM SUB and CMP
+fn sub_flags(a: u32, b: u32) -> (u32, bool, bool) {
+    let (result, borrow) = a.overflowing_sub(b);
+    let (_, overflow) = (a as i32).overflowing_sub(b as i32);
+    (result, !borrow, overflow)
+}
+
+/// Shift `value` with the ARM barrel shifter by a register amount,
+/// returns the result and the carry out. `shift` is the shift type in bits 5-6
+/// of the operand: 0 LSL, 1 LSR, 2 ASR and 3 ROR.
+/// Shifting by 0 keeps the value and the current `carry`.
+fn barrel_shift(value: u32, shift: u32, amount: u32, carry: bool) -> (u32, bool) {
+    if amount == 0 {
+        return (value, carry);
+    }
+
+    let bit = |n: u32| (value >> n) & 1 == 1;
+    match shift {
+        // LSL
+        0 => match amount {
+            1..=31 => (value << amount, bit(32 - amount)),
+            32 => (0, bit(0)),
+            _ => (0, false),
+        },
+        // LSR
+        1 => match amount {
+            1..=31 => (value >> amount, bit(amount - 1)),
+            32 => (0, bit(31)),
+            _ => (0, false),
+        },
+        // ASR
+        2 => match amount {
+            1..=31 => (((value as i32) >> amount) as u32, bit(amount - 1)),
+            _ => (((value as i32) >> 31) as u32, bit(31)),
+        },
+        // ROR
+        _ => {
+            let value = value.rotate_right(amount % 32);
+            (value, (value >> 31) & 1 == 1)
+        }
+    }
+}
 
-    /// Has cpu encountered a state where branch jumps into itself
-    loop_detected: bool,
-    logging: bool,
-    memory: Vec<u8>,
+/// Shift `value` with the ARM barrel shifter by an immediate `amount` (0-31).
+/// LSR #0 and ASR #0 shift by 32 and ROR #0 is RRX, a 33 bit rotate through the carry.
+fn immediate_shift(value: u32, shift: u32, amount: u32, carry: bool) -> (u32, bool) {
+    match (shift, amount) {
+        (1 | 2, 0) => barrel_shift(value, shift, 32, carry),
+        // RRX
+        (3, 0) => (((carry as u32) << 31) | (value >> 1), value & 1 == 1),
+        _ => barrel_shift(value, shift, amount, carry),
+    }
 }
 
 impl Display for Cpu {
@@ -58,30 +426,131 @@ impl Display for Cpu {
         writeln!(f, "    r5: 0x{:08x},", self.r5)?;
         writeln!(f, "    r6: 0x{:08x},", self.r6)?;
         writeln!(f, "    r7: 0x{:08x},", self.r7)?;
+        writeln!(f, "    r8: 0x{:08x},", self.r8)?;
+        writeln!(f, "    r9: 0x{:08x},", self.r9)?;
+        writeln!(f, "    r10: 0x{:08x},", self.r10)?;
+        writeln!(f, "    r11: 0x{:08x},", self.r11)?;
+        writeln!(f, "    r12: 0x{:08x},", self.r12)?;
         writeln!(f, "    r13/sp: 0x{:08x},", self.sp)?;
+        writeln!(f, "    r13_irq: 0x{:08x},", self.sp_irq)?;
+        writeln!(f, "    r13_svc: 0x{:08x},", self.sp_svc)?;
         writeln!(f, "    r14/lr: 0x{:08x},", self.lr)?;
         writeln!(f, "    r15/pc: 0x{:08x},", self.pc)?;
+        writeln!(f, "    cpsr: 0x{:08x},", self.cpsr())?;
+        writeln!(f, "    flags: {},", self.flags())?;
         writeln!(f, "    sign_flag: {},", self.sign_flag)?;
         writeln!(f, "    zero_flag: {},", self.zero_flag)?;
         writeln!(f, "    carry_flag: {},", self.carry_flag)?;
         writeln!(f, "    overflow_flag: {},", self.overflow_flag)?;
         writeln!(f, "    thumb: {},", self.thumb)?;
+        writeln!(f, "    cycles: {},", self.cycles)?;
         writeln!(f, "}}")?;
         Ok(())
     }
 }
 
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::with_bus(Box::new(GbaBus::new()))
+    }
+}
+
+/// Stands in for the bus while `Cpu::reset` moves it into the new state
+#[derive(Debug)]
+struct NoBus;
+
+impl Bus for NoBus {
+    fn read8(&self, _addr: u32) -> u8 {
+        0
+    }
+
+    fn write8(&mut self, _addr: u32, _value: u8) {}
+}
+
 impl Cpu {
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cpu accessing the memory through `bus` instead of the GBA memory map
+    pub fn with_bus(bus: Box<dyn Bus>) -> Self {
         Self {
-            // TODO: actual memory mapping for smaller allocations
-            memory: vec![0; 0x10000000],
-            ..Default::default()
+            r0: 0,
+            r1: 0,
+            r2: 0,
+            r3: 0,
+            r4: 0,
+            r5: 0,
+            r6: 0,
+            r7: 0,
+            r8: 0,
+            r9: 0,
+            r10: 0,
+            r11: 0,
+            r12: 0,
+            sp: 0,
+            lr: 0,
+            pc: 0,
+            sp_irq: 0,
+            sp_svc: 0,
+            spsr: 0,
+            sign_flag: false,
+            zero_flag: false,
+            carry_flag: false,
+            overflow_flag: false,
+            thumb: false,
+            mode: ProcessorMode::default(),
+            undefined_exceptions: false,
+            halted: false,
+            call_depth: 0,
+            cycles: 0,
+            logger: Logger::default(),
+            trace_range: None,
+            trace: RefCell::default(),
+            access_watches: Vec::new(),
+            watch_hits: RefCell::default(),
+            bus,
+            boot: BootConfig::default(),
+            history: VecDeque::new(),
+            stats: Stats::default(),
+            prefetch: Prefetch::default(),
         }
     }
 
+    pub fn call_depth(&self) -> i64 {
+        self.call_depth
+    }
+
     pub fn set_logging(&mut self, logging: bool) {
-        self.logging = logging;
+        self.logger.set_enabled(logging);
+    }
+
+    pub fn logger_mut(&mut self) -> &mut Logger {
+        &mut self.logger
+    }
+
+    /// r0-r12, SP, LR and PC in register number order
+    pub fn register_values(&self) -> [u32; 16] {
+        [
+            self.r0, self.r1, self.r2, self.r3, self.r4, self.r5, self.r6, self.r7, self.r8,
+            self.r9, self.r10, self.r11, self.r12, self.sp, self.lr, self.pc,
+        ]
+    }
+
+    /// Log the executed instruction as `PC: assembly` followed by the registers
+    /// that changed from `before`
+    fn log_asm(&self, step: &StepInfo, before: &[u32; 16]) {
+        let mut line = format!("{:08X}: {}", step.pc, disassemble_step(step));
+        let after = self.register_values();
+        // PC is in the line already
+        let registers = REGISTER_NAMES[..15].iter().zip(before.iter().zip(after));
+        for (name, (old, new)) in registers {
+            if *old != new {
+                line.push_str(&format!(" {name}={new:08X}"));
+            }
+        }
+
+        self.logger.write_line(format_args!("{line}"));
     }
 
     fn get_register(&self, reg: Register) -> EResult<u32> {
@@ -94,15 +563,92 @@ impl Cpu {
             Register::R5 => Ok(self.r5),
             Register::R6 => Ok(self.r6),
             Register::R7 => Ok(self.r7),
+            Register::R8 => Ok(self.r8),
+            Register::R9 => Ok(self.r9),
+            Register::R10 => Ok(self.r10),
+            Register::R11 => Ok(self.r11),
+            Register::R12 => Ok(self.r12),
             Register::R13 => Ok(self.sp),
             Register::R14 => Ok(self.lr),
             Register::R15 => Ok(self.pc),
-            _ => Err(ExecErr::UnimplementedInstr(format!(
-                "Register {reg:?} not implmented"
-            ))),
         }
     }
 
+    /// Read a register used as an operand of an ARM instruction.
+    /// Because of the pipeline, R15 reads as PC+12 when the
+    /// instruction shifts by a register and PC+8 otherwise.
+    fn read_operand_register(&self, reg: Register, shift_by_register: bool) -> EResult<u32> {
+        let value = self.get_register(reg)?;
+        if reg != Register::R15 {
+            return Ok(value);
+        }
+
+        let offset = if shift_by_register { 12 } else { 8 };
+        Ok(value.wrapping_add(offset))
+    }
+
+    /// Read a register used as an operand of a Thumb instruction,
+    /// R15 reads as PC+4 because of the pipeline
+    fn read_thumb_operand_register(&self, reg: Register) -> EResult<u32> {
+        let value = self.get_register(reg)?;
+        if reg != Register::R15 {
+            return Ok(value);
+        }
+
+        Ok(value.wrapping_add(4))
+    }
+
+    /// Second operand of an ALU instruction and the carry out of the shifter.
+    /// The operand is a rotated immediate or a register shifted by an immediate
+    /// or by the bottom byte of another register.
+    fn alu_operand2(&self, alu: &Alu) -> EResult<(u32, bool)> {
+        if alu.immediate {
+            let rors = (alu.operand >> 8) & 0b1111;
+            let nn = alu.operand & 0b11111111;
+            let value = nn.rotate_right(rors * 2);
+            let carry = if rors == 0 {
+                self.carry_flag
+            } else {
+                (value >> 31) & 1 == 1
+            };
+            return Ok((value, carry));
+        }
+
+        let shift_by_register = (alu.operand >> 4) & 1 == 1;
+        let rm = Register::from(alu.operand & 0b1111);
+        let value = self.read_operand_register(rm, shift_by_register)?;
+        let shift = (alu.operand >> 5) & 0b11;
+
+        if shift_by_register {
+            let rs = Register::from((alu.operand >> 8) & 0b1111);
+            let amount = self.get_register(rs)? & 0xFF;
+            Ok(barrel_shift(value, shift, amount, self.carry_flag))
+        } else {
+            let amount = (alu.operand >> 7) & 0b11111;
+            Ok(immediate_shift(value, shift, amount, self.carry_flag))
+        }
+    }
+
+    /// First operand of an ALU instruction, shifting by a register
+    /// delays the read of Rn as well
+    fn alu_rn(&self, alu: &Alu) -> EResult<u32> {
+        let shift_by_register = !alu.immediate && (alu.operand >> 4) & 1 == 1;
+        self.read_operand_register(alu.rn, shift_by_register)
+    }
+
+    /// Offset of a single data transfer, either a 12 bit immediate
+    /// or a register shifted by an immediate
+    fn sdt_offset(&self, sdt: &Sdt) -> EResult<u32> {
+        if sdt.immediate {
+            return Ok(sdt.operand);
+        }
+
+        let value = self.read_operand_register(Register::from(sdt.operand & 0b1111), false)?;
+        let shift = (sdt.operand >> 5) & 0b11;
+        let amount = (sdt.operand >> 7) & 0b11111;
+        Ok(immediate_shift(value, shift, amount, self.carry_flag).0)
+    }
+
     fn set_register(&mut self, reg: Register, value: u32) -> EResult<()> {
         match reg {
             Register::R0 => self.r0 = value,
@@ -113,51 +659,339 @@ impl Cpu {
             Register::R5 => self.r5 = value,
             Register::R6 => self.r6 = value,
             Register::R7 => self.r7 = value,
+            Register::R8 => self.r8 = value,
+            Register::R9 => self.r9 = value,
+            Register::R10 => self.r10 = value,
+            Register::R11 => self.r11 = value,
+            Register::R12 => self.r12 = value,
             Register::R13 => self.sp = value,
             Register::R14 => self.lr = value,
             Register::R15 => self.pc = value,
-            _ => {
-                return Err(ExecErr::UnimplementedInstr(format!(
-                    "Register {reg:?} not implmented"
-                )))
-            }
         }
 
         Ok(())
     }
 
+    /// Current Program Status Register
+    pub fn cpsr(&self) -> u32 {
+        let mut cpsr = self.mode as u32;
+        cpsr |= (self.sign_flag as u32) << 31;
+        cpsr |= (self.zero_flag as u32) << 30;
+        cpsr |= (self.carry_flag as u32) << 29;
+        cpsr |= (self.overflow_flag as u32) << 28;
+        cpsr |= (self.thumb as u32) << 5;
+        cpsr
+    }
+
+    /// Condition flags as NZCV, set flags are uppercase letters and clear flags are `-`
+    pub fn flags(&self) -> String {
+        [
+            (self.sign_flag, 'N'),
+            (self.zero_flag, 'Z'),
+            (self.carry_flag, 'C'),
+            (self.overflow_flag, 'V'),
+        ]
+        .iter()
+        .map(|(set, name)| if *set { *name } else { '-' })
+        .collect()
+    }
+
+    pub fn set_cpsr(&mut self, value: u32) {
+        self.sign_flag = (value >> 31) & 1 == 1;
+        self.zero_flag = (value >> 30) & 1 == 1;
+        self.carry_flag = (value >> 29) & 1 == 1;
+        self.overflow_flag = (value >> 28) & 1 == 1;
+        self.thumb = (value >> 5) & 1 == 1;
+        // reserved modes keep the current one
+        if let Some(mode) = ProcessorMode::from_bits(value) {
+            self.mode = mode;
+        }
+    }
+
+    pub fn mode(&self) -> ProcessorMode {
+        self.mode
+    }
+
+    /// Take the Undefined Instruction exception on undefined encodings, or stop with
+    /// an error like by default
+    pub fn set_undefined_exceptions(&mut self, enabled: bool) {
+        self.undefined_exceptions = enabled;
+    }
+
+    /// Enter Undefined mode and jump to its vector. LR is the next instruction,
+    /// so the handler returns with `MOVS PC, LR`.
+    fn undefined_exception(&mut self) {
+        self.spsr = self.cpsr();
+        self.lr = self.pc.wrapping_add(if self.thumb { 2 } else { 4 });
+        self.mode = ProcessorMode::Undefined;
+        self.thumb = false;
+        self.pc = UNDEFINED_VECTOR;
+    }
+
+    /// Register of the name r0-r15, sp, lr or pc, None for cpsr
+    fn register_by_name(name: &str) -> Result<Option<Register>, String> {
+        let name = name.to_lowercase();
+        let reg = match name.as_str() {
+            "sp" => Register::R13,
+            "lr" => Register::R14,
+            "pc" => Register::R15,
+            "cpsr" => return Ok(None),
+            _ => match name.strip_prefix('r').map(str::parse::<u32>) {
+                Some(Ok(idx)) if idx <= 15 => Register::from(idx),
+                _ => return Err(format!("Unknown register '{name}'")),
+            },
+        };
+
+        Ok(Some(reg))
+    }
+
+    /// Value of a register by its name, see `set_register_by_name`
+    pub fn get_register_by_name(&self, name: &str) -> Result<u32, String> {
+        match Self::register_by_name(name)? {
+            Some(reg) => self
+                .get_register(reg)
+                .map_err(|e| format!("Failed to get register {reg:?}: {e}")),
+            None => Ok(self.cpsr()),
+        }
+    }
+
+    /// Set register by its name, r0-r15, sp, lr, pc or cpsr
+    pub fn set_register_by_name(&mut self, name: &str, value: u32) -> Result<(), String> {
+        let Some(reg) = Self::register_by_name(name)? else {
+            self.set_cpsr(value);
+            return Ok(());
+        };
+
+        self.set_register(reg, value)
+            .map_err(|e| format!("Failed to set register {reg:?}: {e}"))
+    }
+
+    /// Read a byte without logging it as a memory access
+    fn read_byte(&self, offset: u32) -> u8 {
+        self.bus.read8(offset)
+    }
+
+    /// `size` bytes of memory from `start`, without logging or tracing the reads
+    pub fn read_region(&self, start: u32, size: u32) -> Vec<u8> {
+        (0..size)
+            .map(|offset| self.read_byte(start.wrapping_add(offset)))
+            .collect()
+    }
+
+    /// Write `bytes` into memory from `start`, without logging or tracing the writes
+    pub fn write_region(&mut self, start: u32, bytes: &[u8]) {
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.write_byte(start.wrapping_add(offset as u32), *byte);
+        }
+    }
+
+    /// Record the instruction fetched from PC in the history
+    fn record_history(&mut self, opcode: u32) {
+        if self.history.len() == HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(HistoryEntry {
+            pc: self.pc,
+            opcode,
+            thumb: self.thumb,
+        });
+    }
+
+    /// Recently executed instructions, oldest first.
+    /// Failed instructions are included since they are recorded before executing them.
+    pub fn history(&self) -> &VecDeque<HistoryEntry> {
+        &self.history
+    }
+
+    /// Write a byte without logging it as a memory access
+    fn write_byte(&mut self, offset: u32, value: u8) {
+        self.prefetch.invalidate(offset, 1);
+        self.bus.write8(offset, value);
+    }
+
+    /// Fetch the instruction word at `addr` through the prefetch buffer
+    fn fetch_u32(&mut self, addr: u32) -> u32 {
+        if let Some(word) = self.prefetch.word(addr) {
+            return word;
+        }
+        if !is_cacheable(addr) {
+            return self.read_u32(addr);
+        }
+
+        let bus = &self.bus;
+        self.prefetch.fill(addr, |addr| bus.read32(addr))
+    }
+
+    /// Fetch the Thumb instruction at `addr` through the prefetch buffer
+    fn fetch_u16(&mut self, addr: u32) -> u16 {
+        if self.prefetch.word(addr & !3).is_none() && !is_cacheable(addr) {
+            return self.read_u16(addr);
+        }
+
+        (self.fetch_u32(addr & !3) >> ((addr & 2) * 8)) as u16
+    }
+
+    /// Read a word without logging it as a memory access
+    fn read_u32(&self, offset: u32) -> u32 {
+        self.bus.read32(offset)
+    }
+
+    /// Read a half word without logging it as a memory access
+    fn read_u16(&self, offset: u32) -> u16 {
+        self.bus.read16(offset)
+    }
+
+    /// Write a half word without logging it as a memory access
+    fn write_u16(&mut self, offset: u32, value: u16) {
+        self.prefetch.invalidate(offset, 2);
+        self.bus.write16(offset, value);
+    }
+
+    /// Record only memory accesses inside the inclusive range, or none if `range` is None
+    pub fn set_trace_range(&mut self, range: Option<(u32, u32)>) {
+        self.trace_range = range;
+    }
+
+    /// Get the recorded memory accesses and clear the trace
+    pub fn take_trace(&mut self) -> Vec<TraceEntry> {
+        self.trace.take()
+    }
+
+    /// Break on writes to `addr` if `is_write`, otherwise on reads from it.
+    /// Instruction fetches aren't memory accesses.
+    pub fn add_access_watch(&mut self, addr: u32, is_write: bool) {
+        self.access_watches.push((addr, is_write));
+    }
+
+    /// Get the accesses to the watched addresses and clear them
+    pub fn take_watch_hits(&mut self) -> Vec<TraceEntry> {
+        self.watch_hits.take()
+    }
+
+    fn trace_access(&self, addr: u32, value: u32, is_write: bool, size: u8) {
+        let entry = || TraceEntry {
+            pc: self.pc,
+            addr,
+            value,
+            is_write,
+            size,
+        };
+
+        let accessed = addr..addr.wrapping_add(size as u32);
+        if self
+            .access_watches
+            .iter()
+            .any(|(watch, on_write)| *on_write == is_write && accessed.contains(watch))
+        {
+            self.watch_hits.borrow_mut().push(entry());
+        }
+
+        let Some((low, high)) = self.trace_range else {
+            return;
+        };
+
+        if (low..=high).contains(&addr) {
+            self.trace.borrow_mut().push(entry());
+        }
+    }
+
+    /// Read a little-endian word, unmapped bytes read as 0
     pub fn get_memory(&self, offset: u32) -> u32 {
-        u32::from_le_bytes(
-            self.memory[offset as usize..offset as usize + 4]
-                .try_into()
-                .unwrap(),
-        )
+        let value = self.read_u32(offset);
+        logging!(
+            self.logger,
+            LogCategory::Memory,
+            "Read word {value:08X} from addr: {offset:08X}"
+        );
+        self.trace_access(offset, value, false, 4);
+        value
     }
 
+    /// Word load of LDR, an unaligned `addr` reads the aligned word rotated
+    /// so that the addressed byte is the lowest one
+    fn get_memory_rotated(&self, addr: u32) -> u32 {
+        self.get_memory(addr & !3).rotate_right((addr & 3) * 8)
+    }
+
+    /// Read a little-endian half word, unmapped bytes read as 0
     pub fn get_memory_u16(&self, offset: u32) -> u16 {
-        u16::from_le_bytes(
-            self.memory[offset as usize..offset as usize + 2]
-                .try_into()
-                .unwrap(),
-        )
+        let value = self.read_u16(offset);
+        logging!(
+            self.logger,
+            LogCategory::Memory,
+            "Read half word {value:04X} from addr: {offset:08X}"
+        );
+        self.trace_access(offset, value as u32, false, 2);
+        value
+    }
+
+    /// Write a little-endian word, writes to ROM and unmapped bytes are ignored
+    pub fn set_memory(&mut self, offset: u32, value: u32) {
+        logging!(
+            self.logger,
+            LogCategory::Memory,
+            "Write word {value:08X} to addr: {offset:08X}"
+        );
+        self.trace_access(offset, value, true, 4);
+        self.prefetch.invalidate(offset, 4);
+        self.bus.write32(offset, value);
+    }
+
+    /// Write `value` into `words` consecutive words from `start` like a loop of `set_memory`,
+    /// but with a single `Bus::fill32`. Words in ROM and unmapped memory are skipped.
+    pub fn fill_memory(&mut self, start: u32, value: u32, words: usize) {
+        logging!(
+            self.logger,
+            LogCategory::Memory,
+            "Fill {words} words with {value:08X} from addr: {start:08X}"
+        );
+        if self.trace_range.is_some() || !self.access_watches.is_empty() {
+            // every write needs its own trace entry
+            for idx in 0..words {
+                self.set_memory(start.wrapping_add(idx as u32 * 4), value);
+            }
+            return;
+        }
+
+        self.prefetch.clear();
+        self.bus.fill32(start, value, words);
+    }
+
+    /// Write a little-endian half word, writes to ROM and unmapped bytes are ignored
+    pub fn set_memory_u16(&mut self, offset: u32, value: u16) {
+        logging!(
+            self.logger,
+            LogCategory::Memory,
+            "Write half word {value:04X} to addr: {offset:08X}"
+        );
+        self.trace_access(offset, value as u32, true, 2);
+        self.write_u16(offset, value);
     }
 
-    fn set_memory(&mut self, offset: u32, value: u32) {
-        let bytes = value.to_le_bytes();
-        self.memory[offset as usize] = bytes[0];
-        self.memory[offset as usize + 1] = bytes[1];
-        self.memory[offset as usize + 2] = bytes[2];
-        self.memory[offset as usize + 3] = bytes[3];
+    /// Read a byte, unmapped bytes read as 0
+    pub fn get_memory_u8(&self, offset: u32) -> u8 {
+        let value = self.read_byte(offset);
+        logging!(
+            self.logger,
+            LogCategory::Memory,
+            "Read byte {value:02X} from addr: {offset:08X}"
+        );
+        self.trace_access(offset, value as u32, false, 1);
+        value
     }
 
-    fn set_memory_u16(&mut self, offset: u32, value: u16) {
-        let bytes = value.to_le_bytes();
-        self.memory[offset as usize] = bytes[0];
-        self.memory[offset as usize + 1] = bytes[1];
+    /// Write a byte, writes to ROM and unmapped bytes are ignored
+    pub fn set_memory_u8(&mut self, offset: u32, value: u8) {
+        logging!(
+            self.logger,
+            LogCategory::Memory,
+            "Write byte {value:02X} to addr: {offset:08X}"
+        );
+        self.trace_access(offset, value as u32, true, 1);
+        self.write_byte(offset, value);
     }
 
     fn run_branch(&mut self, branch: Branch) -> EResult<()> {
-        // TODO: properly handle nn being signed
         // TODO: handle BL
         if branch.is_link {
             return Err(ExecErr::UnimplementedInstr(
@@ -165,20 +999,95 @@ impl Cpu {
             ));
         }
 
-        self.pc = self.pc + 8 + branch.nn * 4;
+        if !self.condition_passed(&branch.condition) {
+            self.pc = self.pc.wrapping_add(4);
+            return Ok(());
+        }
+
+        // sign extend the 24 bit offset and multiply it by 4
+        let offset = ((branch.nn << 8) as i32) >> 6;
+        self.pc = self.pc.wrapping_add(8).wrapping_add_signed(offset);
         Ok(())
     }
 
+    /// BLX to a Thumb subroutine, returns to the next ARM instruction
+    fn run_branch_link_exchange(&mut self, branch: BranchLinkExchange) -> EResult<()> {
+        self.call_depth += 1;
+        self.lr = self.pc.wrapping_add(4);
+        let target = self.pc.wrapping_add(8).wrapping_add_signed(branch.offset);
+        self.set_pc_with_mode(target | 1);
+        Ok(())
+    }
+
+    /// Jump to `target` and switch to Thumb mode if bit 0 is set or to ARM mode if it's not.
+    /// PC is aligned to the instruction width of the new mode.
+    fn set_pc_with_mode(&mut self, target: u32) {
+        self.thumb = target & 1 == 1;
+        self.pc = if self.thumb { target & !1 } else { target & !3 };
+    }
+
+    /// Do the flags satisfy the instruction `condition`
+    fn condition_passed(&self, condition: &Condition) -> bool {
+        let (n, z, c, v) = (
+            self.sign_flag,
+            self.zero_flag,
+            self.carry_flag,
+            self.overflow_flag,
+        );
+
+        match condition {
+            Condition::Eq => z,
+            Condition::Ne => !z,
+            Condition::Cs => c,
+            Condition::Cc => !c,
+            Condition::Mi => n,
+            Condition::Pl => !n,
+            Condition::Vs => v,
+            Condition::Vc => !v,
+            Condition::Hi => c && !z,
+            Condition::Ls => !c || z,
+            Condition::Ge => n == v,
+            Condition::Lt => n != v,
+            Condition::Gt => !z && n == v,
+            Condition::Le => z || n != v,
+            Condition::Al => true,
+            // never is reserved on ARMv4
+            Condition::Nv => false,
+        }
+    }
+
+    /// Set the sign and zero flags from the result
+    fn set_nz_flags(&mut self, result: u32) {
+        self.sign_flag = (result as i32) < 0;
+        self.zero_flag = result == 0;
+    }
+
+    /// Set all the condition flags from the result of `add_flags` or `sub_flags`
+    fn set_arith_flags(&mut self, (result, carry, overflow): (u32, bool, bool)) -> u32 {
+        self.set_nz_flags(result);
+        self.carry_flag = carry;
+        self.overflow_flag = overflow;
+        result
+    }
+
     fn run_branch_exhange(&mut self, branch: BranchExchange) -> EResult<()> {
-        let reg_value = self.get_register(branch.rn)?;
-        let target = (reg_value | 1) - 1;
-        self.pc = target;
-        self.thumb = true;
+        if !self.condition_passed(&branch.condition) {
+            self.pc = self.pc.wrapping_add(4);
+            return Ok(());
+        }
+
+        self.call_depth -= 1;
+        let reg_value = self.read_operand_register(branch.rn, false)?;
+        self.set_pc_with_mode(reg_value);
         Ok(())
     }
 
     fn run_alu(&mut self, alu: Alu) -> EResult<()> {
-        // TODO: condition codes
+        if !self.condition_passed(&alu.condition) {
+            self.pc = self.pc.wrapping_add(4);
+            return Ok(());
+        }
+
         match alu.op {
             AluOp::And => Err(ExecErr::UnimplementedInstr(
                 "AluOp::And not implemented".into(),
@@ -186,32 +1095,26 @@ impl Cpu {
             AluOp::Eor => Err(ExecErr::UnimplementedInstr(
                 "AluOp::Eor not implemented".into(),
             )),
-            AluOp::Sub => Err(ExecErr::UnimplementedInstr(
-                "AluOp::Sub not implemented".into(),
-            )),
+            AluOp::Sub => {
+                let (op2, _) = self.alu_operand2(&alu)?;
+                let reg = self.alu_rn(&alu)?;
+                let result = sub_flags(reg, op2);
+                if alu.s {
+                    self.set_arith_flags(result);
+                }
+                self.write_alu_result(&alu, result.0)
+            }
             AluOp::Rsb => Err(ExecErr::UnimplementedInstr(
                 "AluOp::Rsb not implemented".into(),
             )),
             AluOp::Add => {
-                // TODO: move register
-                if !alu.immediate {
-                    return Err(ExecErr::UnimplementedInstr(
-                        "AluOp::Add register value not supported".into(),
-                    ));
+                let (op2, _) = self.alu_operand2(&alu)?;
+                let reg = self.alu_rn(&alu)?;
+                let result = add_flags(reg, op2);
+                if alu.s {
+                    self.set_arith_flags(result);
                 }
-
-                let rors = (alu.operand >> 8) & 0b1111;
-                let nn = alu.operand & 0b11111111;
-                let op2 = nn.rotate_right(rors * 2);
-                // When using R15 as operand (Rm or Rn), the returned value depends
-                // on the instruction: PC+12 if I=0,R=1 (shift by register),
-                // otherwise PC+8 (shift by immediate).
-                let mut reg = if alu.rn == Register::R15 { 8 } else { 0 };
-
-                reg += self.get_register(alu.rn)?;
-                self.set_register(alu.rd, reg + op2)?;
-                self.pc += 4;
-                Ok(())
+                self.write_alu_result(&alu, result.0)
             }
             AluOp::Adc => Err(ExecErr::UnimplementedInstr(
                 "AluOp::Adc not implemented".into(),
@@ -238,18 +1141,12 @@ impl Cpu {
                 "AluOp::Orr not implemented".into(),
             )),
             AluOp::Mov => {
-                // TODO: move register
-                if !alu.immediate {
-                    return Err(ExecErr::UnimplementedInstr(
-                        "AluOp::Mov register value not supported".into(),
-                    ));
+                let (op2, carry) = self.alu_operand2(&alu)?;
+                if alu.s {
+                    self.set_nz_flags(op2);
+                    self.carry_flag = carry;
                 }
-
-                let rors = (alu.operand >> 8) & 0b1111;
-                let nn = alu.operand & 0b11111111;
-                self.set_register(alu.rd, nn.rotate_right(rors * 2))?;
-                self.pc += 4;
-                Ok(())
+                self.write_alu_result(&alu, op2)
             }
             AluOp::Bic => Err(ExecErr::UnimplementedInstr(
                 "AluOp::Bic not implemented".into(),
@@ -260,67 +1157,206 @@ impl Cpu {
         }
     }
 
-    fn run_sdt(&mut self, sdt: Sdt) -> EResult<()> {
-        // TODO: properly handle condition
-        // TODO: properly handle tw (bit 21)
-
-        if !sdt.immediate {
-            return Err(ExecErr::UnimplementedInstr(
-                "Runnin SDT register offset not implemented".into(),
-            ));
+    /// Write `result` into rd of `alu` and move to the next instruction unless rd is PC.
+    /// With the S bit and PC as rd, CPSR is restored from SPSR to return from an exception.
+    /// TODO: LDM with the S bit and PC in the list does the same once it's implemented
+    fn write_alu_result(&mut self, alu: &Alu, result: u32) -> EResult<()> {
+        self.set_register(alu.rd, result)?;
+        if alu.rd != Register::R15 {
+            self.pc = self.pc.wrapping_add(4);
+            return Ok(());
         }
 
-        if sdt.load_memory {
-            let base_addr = self.get_register(sdt.rn)?;
-            // TODO: proper unsigned addition
-            let addr = base_addr + sdt.operand;
-            let addr = match sdt.rn {
-                Register::R15 => addr + 8,
-                _ => addr,
-            };
-            self.set_register(sdt.rd, self.get_memory(addr))?;
-        } else {
-            self.set_memory(self.get_register(sdt.rn)? + sdt.operand, self.r0);
+        if alu.s {
+            self.set_cpsr(self.spsr);
+            // the restored state may return to Thumb code
+            self.pc &= if self.thumb { !1 } else { !3 };
         }
-
-        self.pc += 4;
-
         Ok(())
     }
 
-    fn run_next_instruction(&mut self) -> EResult<()> {
-        let word = u32::from_le_bytes(
-            self.memory[self.pc as usize..self.pc as usize + 4]
-                .try_into()
-                .unwrap(),
-        );
-
-        if self.thumb {
-            unimplemented!("Cannot run in thumb mode");
+    fn run_sdt(&mut self, sdt: Sdt) -> EResult<()> {
+        if !self.condition_passed(&sdt.condition) {
+            self.pc = self.pc.wrapping_add(4);
+            return Ok(());
         }
 
-        let fmt = format!("Trying from word: {word:08X} addr: {:08X}", self.pc);
-        logging!(self.logging, "{}", fmt);
-
-        let instr: Instruction = word.try_into()?;
+        let offset = self.sdt_offset(&sdt)?;
+        let base_addr = self.read_operand_register(sdt.rn, false)?;
+        let offset_addr = if sdt.up {
+            base_addr.wrapping_add(offset)
+        } else {
+            base_addr.wrapping_sub(offset)
+        };
+        // post-indexing accesses the base and always writes back,
+        // W then selects a user mode access which is the same thing without modes
+        let addr = if sdt.pre { offset_addr } else { base_addr };
+        let addr = addr & ADDRESS_MASK;
+        // writing back to PC is unpredictable, ignore it
+        let write_back = (!sdt.pre || sdt.tw) && sdt.rn != Register::R15;
+
+        if sdt.load_memory {
+            // B selects a zero extended byte, unaligned words are rotated
+            let value = if sdt.bit {
+                self.get_memory_u8(addr) as u32
+            } else {
+                self.get_memory_rotated(addr)
+            };
+            // the loaded value wins when rd is the base
+            if write_back {
+                self.set_register(sdt.rn, offset_addr)?;
+            }
+            self.set_register(sdt.rd, value)?;
+            if sdt.rd == Register::R15 {
+                return Ok(());
+            }
+        } else {
+            // stored PC is PC+12, one stage later than the operands
+            let value = match sdt.rd {
+                Register::R15 => self.pc.wrapping_add(12),
+                rd => self.get_register(rd)?,
+            };
+            if sdt.bit {
+                self.set_memory_u8(addr, value as u8);
+            } else {
+                self.set_memory(addr & !3, value);
+            }
+            if write_back {
+                self.set_register(sdt.rn, offset_addr)?;
+            }
+        }
 
-        let fmt = format!("Executing: {instr:?}");
-        logging!(self.logging, "{}", fmt);
+        self.pc = self.pc.wrapping_add(4);
 
-        match instr {
-            Instruction::Branch(b) => self.run_branch(b)?,
-            Instruction::BranchExchange(b) => self.run_branch_exhange(b)?,
-            Instruction::Alu(a) => self.run_alu(a)?,
-            Instruction::Sdt(sdt) => self.run_sdt(sdt)?,
-            Instruction::Psr => {
-                logging!(self.logging, "{}", "Ignoring Psr instructions");
-                self.pc += 4;
+        Ok(())
+    }
+
+    fn run_swap(&mut self, swap: Swap) -> EResult<()> {
+        if !self.condition_passed(&swap.condition) {
+            self.pc = self.pc.wrapping_add(4);
+            return Ok(());
+        }
+
+        let addr = self.get_register(swap.rn)?;
+        // read Rm first since it can be the same register as Rd
+        let source = self.get_register(swap.rm)?;
+
+        if swap.byte {
+            let value = self.get_memory_u8(addr);
+            self.set_memory_u8(addr, source as u8);
+            self.set_register(swap.rd, value as u32)?;
+        } else {
+            let value = self.get_memory_rotated(addr);
+            self.set_memory(addr & !3, source);
+            self.set_register(swap.rd, value)?;
+        }
+
+        self.pc = self.pc.wrapping_add(4);
+        Ok(())
+    }
+
+    /// BIOS CpuSet and CpuFastSet, copy or fill (bit 24 of r2) `r2 & 0x1FFFFF` units from r0 to r1.
+    /// CpuSet copies halfwords unless bit 26 of r2 is set, CpuFastSet always copies words
+    /// in blocks of 8 words.
+    fn cpu_set(&mut self, fast: bool) {
+        let fill = (self.r2 >> 24) & 1 == 1;
+        let words = fast || (self.r2 >> 26) & 1 == 1;
+        let mut count = self.r2 & 0x1FFFFF;
+        if fast {
+            // rounded up to full blocks
+            count = (count + 7) & !7;
+        }
+
+        let (unit, align) = if words { (4, !3) } else { (2, !1) };
+        let mut src = self.r0 & align;
+        let mut dst = self.r1 & align;
+        if fill {
+            if words {
+                let value = self.get_memory(src);
+                self.fill_memory(dst, value, count as usize);
+            } else {
+                let value = self.get_memory_u16(src);
+                self.fill_memory(dst, value as u32 * 0x10001, count as usize / 2);
+                if count & 1 == 1 {
+                    self.set_memory_u16(dst.wrapping_add((count - 1) * 2), value);
+                }
+            }
+            return;
+        }
+
+        for _ in 0..count {
+            if words {
+                let value = self.get_memory(src);
+                self.set_memory(dst, value);
+            } else {
+                let value = self.get_memory_u16(src);
+                self.set_memory_u16(dst, value);
+            }
+
+            src = src.wrapping_add(unit);
+            dst = dst.wrapping_add(unit);
+        }
+    }
+
+    /// Run the BIOS function `number` in place of the BIOS
+    fn run_swi(&mut self, number: u8) -> EResult<()> {
+        match number {
+            // Div
+            0x06 => {
+                let (quot, rem, abs) = bios_div(self.r0 as i32, self.r1 as i32);
+                self.r0 = quot as u32;
+                self.r1 = rem as u32;
+                self.r3 = abs as u32;
+            }
+            // DivArm, Div with swapped arguments
+            0x07 => {
+                let (quot, rem, abs) = bios_div(self.r1 as i32, self.r0 as i32);
+                self.r0 = quot as u32;
+                self.r1 = rem as u32;
+                self.r3 = abs as u32;
+            }
+            // CpuSet
+            0x0B => self.cpu_set(false),
+            // CpuFastSet
+            0x0C => self.cpu_set(true),
+            _ => {
+                return Err(ExecErr::UnimplementedInstr(format!(
+                    "SWI {number:02X} not implemented"
+                )))
             }
         }
 
         Ok(())
     }
 
+    fn run_next_instruction(&mut self) -> EResult<Instruction> {
+        if self.pc & 3 != 0 {
+            return Err(ExecErr::UnalignedPc(self.pc));
+        }
+
+        let word = self.fetch_u32(self.pc);
+        self.bus.set_open_bus(word);
+        self.record_history(word);
+
+        if self.thumb {
+            unimplemented!("Cannot run in thumb mode");
+        }
+
+        logging!(
+            self.logger,
+            LogCategory::Decode,
+            "Trying from word: {word:08X} addr: {:08X}",
+            self.pc
+        );
+
+        let instr: Instruction = word.try_into()?;
+
+        logging!(self.logger, LogCategory::Decode, "Executing: {instr:?}");
+
+        instr.execute(self)?;
+        Ok(instr)
+    }
+
     fn run_thumb_alu(&mut self, alu: ThumbAlu) -> EResult<()> {
         match alu.op {
             ThumbAluOp::Bic => {
@@ -330,80 +1366,129 @@ impl Cpu {
                 self.zero_flag = self.get_register(alu.rd)? == 0;
             }
             ThumbAluOp::Cmp => {
-                // TODO: other flags too
-                self.zero_flag = self.get_register(alu.rd)? - self.get_register(alu.rs)? == 0;
+                let result = sub_flags(self.get_register(alu.rd)?, self.get_register(alu.rs)?);
+                self.set_arith_flags(result);
+            }
+            ThumbAluOp::Ror => {
+                // only the lowest byte of rs is used, C is the last bit rotated out
+                let amount = self.get_register(alu.rs)? & 0xFF;
+                let (value, carry) =
+                    barrel_shift(self.get_register(alu.rd)?, 3, amount, self.carry_flag);
+                self.carry_flag = carry;
+                self.set_nz_flags(value);
+                self.set_register(alu.rd, value)?;
+            }
+            ThumbAluOp::Neg => {
+                // flags like in SUBS rd, 0, rs
+                let result = sub_flags(0, self.get_register(alu.rs)?);
+                let value = self.set_arith_flags(result);
+                self.set_register(alu.rd, value)?;
+            }
+            ThumbAluOp::Mul => {
+                let value = self
+                    .get_register(alu.rd)?
+                    .wrapping_mul(self.get_register(alu.rs)?);
+                self.set_nz_flags(value);
+                // C is destroyed on ARMv4, cleared here
+                self.carry_flag = false;
+                self.set_register(alu.rd, value)?;
             }
         }
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
     fn run_thumb_lsi(&mut self, lsi: ThumbLsi) -> EResult<()> {
+        let base_addr = self.get_register(lsi.rb)?;
         match lsi.op {
             ThumbLsiOp::Str => {
-                let base_addr = self.get_register(lsi.rb)?;
-                let addr = base_addr + lsi.nn as u32;
-                self.set_memory(addr, self.get_register(lsi.rd)?);
+                let addr = base_addr.wrapping_add(lsi.nn as u32 * 4);
+                self.set_memory(addr & !3, self.get_register(lsi.rd)?);
+            }
+            ThumbLsiOp::Ldr => {
+                let addr = base_addr.wrapping_add(lsi.nn as u32 * 4);
+                self.set_register(lsi.rd, self.get_memory_rotated(addr))?;
+            }
+            ThumbLsiOp::Strb => {
+                let addr = base_addr.wrapping_add(lsi.nn as u32);
+                self.set_memory_u8(addr, self.get_register(lsi.rd)? as u8);
+            }
+            ThumbLsiOp::Ldrb => {
+                let addr = base_addr.wrapping_add(lsi.nn as u32);
+                let value = self.get_memory_u8(addr);
+                self.set_register(lsi.rd, value as u32)?;
             }
         }
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
     fn run_thumb_lsh(&mut self, lsh: ThumbLsh) -> EResult<()> {
+        let base_addr = self.get_register(lsh.rb)?;
+        let mem_address = base_addr.wrapping_add(lsh.nn as u32 * 2);
         match lsh.op {
             ThumbLshOp::Strh => {
-                let base_addr = self.get_register(lsh.rb)?;
-                let mem_address = base_addr + (lsh.nn as u32) * 2;
-
                 let value_u32 = self.get_register(lsh.rd)?;
 
                 // get the two less significant bytes
                 // NOTE: does this work on big-endian machine?
                 let value_u16 = value_u32 as u16;
-                self.set_memory_u16(mem_address, value_u16);
+                self.set_memory_u16(mem_address & !1, value_u16);
+            }
+            ThumbLshOp::Ldrh => {
+                // unaligned halfword reads are rotated into the upper bits
+                let value = self.get_memory_u16(mem_address & !1) as u32;
+                let value = value.rotate_right((mem_address & 1) * 8);
+                self.set_register(lsh.rd, value)?;
             }
         }
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
     fn run_thumb_hireg(&mut self, hireg: ThumbHiReg) -> EResult<()> {
         match hireg.op {
             ThumbHiRegOp::Bx => {
-                let source = self.get_register(hireg.rs)?;
-                // not completely sure why 1 is anded to lr/R14 in long jump
-                // but now we have be sure it's removed
-                self.pc = source ^ 1;
+                self.call_depth -= 1;
+                let source = self.read_thumb_operand_register(hireg.rs)?;
+                self.set_pc_with_mode(source);
                 return Ok(());
             }
             ThumbHiRegOp::Mov => {
-                let value = self.get_register(hireg.rs)?;
+                let value = self.read_thumb_operand_register(hireg.rs)?;
+                // MOV PC stays in Thumb state, bit 0 is ignored
+                if hireg.rd == Register::R15 {
+                    self.call_depth -= 1;
+                    self.pc = value & !1;
+                    return Ok(());
+                }
                 self.set_register(hireg.rd, value)?;
             }
             // don't do anything on nop
             ThumbHiRegOp::Nop => {}
         }
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
     fn run_thumb_mls(&mut self, mls: ThumbMls) -> EResult<()> {
         match mls.op {
             ThumbMlsOp::Ldr => {
-                let mem_offset = self.get_register(mls.rb)? + mls.nn as u32;
-                // take (PC and not 2) into account
-                let mem_offset = mem_offset & !2;
-                let value = self.get_memory(mem_offset);
+                // PC-relative loads read PC+4 with bit 1 cleared to stay word aligned
+                let base = match mls.rb {
+                    Register::R15 => self.pc.wrapping_add(4) & !2,
+                    rb => self.get_register(rb)?,
+                };
+                let value = self.get_memory_rotated(base.wrapping_add(mls.nn as u32));
                 self.set_register(mls.rd, value)?
             }
         }
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
@@ -418,14 +1503,25 @@ impl Cpu {
                     set_carry = false;
                 }
             }
-            ThumbRegShiftOp::Lsr => todo!(),
+            ThumbRegShiftOp::Lsr => {
+                return Err(ExecErr::UnimplementedInstr(
+                    "ThumbRegShiftOp::Lsr not implemented".into(),
+                ))
+            }
             ThumbRegShiftOp::Asr => {
-                let value = self.get_register(reg_shift.rs)?;
-                let value = (value & 0x80000000) | ((value & 0x7fffffff) >> reg_shift.nn);
-                self.set_register(reg_shift.rd, value)?;
-                if reg_shift.nn == 0 {
-                    set_carry = false;
-                }
+                let value = self.get_register(reg_shift.rs)? as i32;
+                // ASR #0 encodes ASR #32, every bit is the sign bit
+                let nn = if reg_shift.nn == 0 {
+                    32
+                } else {
+                    reg_shift.nn as u32
+                };
+                let result = (value >> nn.min(31)) as u32;
+                self.set_register(reg_shift.rd, result)?;
+                self.set_nz_flags(result);
+                self.carry_flag = (value >> (nn - 1).min(31)) & 1 == 1;
+                self.pc = self.pc.wrapping_add(2);
+                return Ok(());
             }
         }
 
@@ -436,7 +1532,7 @@ impl Cpu {
             self.carry_flag = true;
         }
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
@@ -444,44 +1540,53 @@ impl Cpu {
         match mcas.op {
             ThumbMcasOp::Mov => {
                 self.set_register(mcas.rd, mcas.nn as u32)?;
-                self.zero_flag = self.get_register(mcas.rd)? == 0;
+                self.set_nz_flags(mcas.nn as u32);
             }
             ThumbMcasOp::Cmp => {
-                // TODO: set other flags
-                self.zero_flag = self.get_register(mcas.rd)? == mcas.nn as u32;
+                let result = sub_flags(self.get_register(mcas.rd)?, mcas.nn as u32);
+                self.set_arith_flags(result);
             }
             ThumbMcasOp::Add => {
-                self.set_register(mcas.rd, self.get_register(mcas.rd)? + mcas.nn as u32)?;
-                self.zero_flag = self.get_register(mcas.rd)? == 0;
+                let result = add_flags(self.get_register(mcas.rd)?, mcas.nn as u32);
+                let value = self.set_arith_flags(result);
+                self.set_register(mcas.rd, value)?;
             }
             ThumbMcasOp::Sub => {
-                // TODO: handle underflow
-                self.set_register(mcas.rd, self.get_register(mcas.rd)? - mcas.nn as u32)?;
-                self.zero_flag = self.get_register(mcas.rd)? == 0;
+                let result = sub_flags(self.get_register(mcas.rd)?, mcas.nn as u32);
+                let value = self.set_arith_flags(result);
+                self.set_register(mcas.rd, value)?;
             }
         }
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
     fn run_add_sub(&mut self, add_sub: ThumbAddSub) -> EResult<()> {
         match add_sub {
             ThumbAddSub::Addr(op) => {
-                // TODO: handle overflows
-                let value = self.get_register(op.rs)? + self.get_register(op.rn)?;
+                let result = add_flags(self.get_register(op.rs)?, self.get_register(op.rn)?);
+                let value = self.set_arith_flags(result);
                 self.set_register(op.rd, value)?;
             }
             ThumbAddSub::Subr(op) => {
-                // TODO: handle undeflows
-                let value = self.get_register(op.rs)? - self.get_register(op.rn)?;
+                let result = sub_flags(self.get_register(op.rs)?, self.get_register(op.rn)?);
+                let value = self.set_arith_flags(result);
                 self.set_register(op.rd, value)?;
             }
-            ThumbAddSub::Addi(_) => todo!(),
-            ThumbAddSub::Subi(_) => todo!(),
+            ThumbAddSub::Addi(_) => {
+                return Err(ExecErr::UnimplementedInstr(
+                    "ThumbAddSub::Addi not implemented".into(),
+                ))
+            }
+            ThumbAddSub::Subi(_) => {
+                return Err(ExecErr::UnimplementedInstr(
+                    "ThumbAddSub::Subi not implemented".into(),
+                ))
+            }
         }
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
@@ -489,7 +1594,7 @@ impl Cpu {
         match push_pop.op {
             ThumbPushPopOp::Push => {
                 for register in push_pop.rlist.iter().rev() {
-                    let memaddr = self.get_register(Register::R13)? - 4;
+                    let memaddr = self.get_register(Register::R13)?.wrapping_sub(4);
                     let value = self.get_register(*register)?;
                     self.set_memory(memaddr, value);
                     self.set_register(Register::R13, memaddr)?;
@@ -498,176 +1603,609 @@ impl Cpu {
             ThumbPushPopOp::Pop => {
                 for register in push_pop.rlist {
                     let memaddr = self.get_register(Register::R13)?;
-                    self.set_register(register, self.get_memory(memaddr))?;
-                    self.set_register(Register::R13, memaddr + 4)?;
+                    let value = self.get_memory(memaddr);
+                    self.set_register(Register::R13, memaddr.wrapping_add(4))?;
+                    // PC is always the last register, returns to the mode of its bit 0
+                    if register == Register::R15 {
+                        self.call_depth -= 1;
+                        self.set_pc_with_mode(value);
+                        return Ok(());
+                    }
+                    self.set_register(register, value)?;
                 }
             }
         }
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
     fn run_thumb_multiple_load_store(&mut self, multls: ThumbMultLS) -> EResult<()> {
+        let base = self.get_register(multls.rb)?;
+
+        // ARMv4 transfers PC with an empty list and steps the base past all 16 registers
+        if multls.rlist.is_empty() {
+            self.set_register(multls.rb, base.wrapping_add(0x40))?;
+            match multls.op {
+                ThumbMultLSOp::Stmia => self.set_memory(base, self.pc.wrapping_add(6)),
+                ThumbMultLSOp::Ldmia => {
+                    self.pc = self.get_memory(base) & !1;
+                    return Ok(());
+                }
+            }
+            self.pc = self.pc.wrapping_add(2);
+            return Ok(());
+        }
+
+        let end = base.wrapping_add(multls.rlist.len() as u32 * 4);
+        let mut memaddr = base;
         match multls.op {
             ThumbMultLSOp::Stmia => {
-                for register in multls.rlist {
-                    let memaddr = self.get_register(multls.rb)?;
-                    self.set_memory(memaddr, self.get_register(register)?);
-                    self.set_register(multls.rb, memaddr + 4)?
+                for (idx, register) in multls.rlist.iter().enumerate() {
+                    // base is written back after the first store
+                    let value = if *register == multls.rb && idx > 0 {
+                        end
+                    } else {
+                        self.get_register(*register)?
+                    };
+                    self.set_memory(memaddr, value);
+                    memaddr = memaddr.wrapping_add(4);
                 }
+                self.set_register(multls.rb, end)?;
             }
             ThumbMultLSOp::Ldmia => {
-                for register in multls.rlist {
-                    let memaddr = self.get_register(multls.rb)?;
-                    self.set_register(register, self.get_memory(memaddr))?;
-                    self.set_register(multls.rb, memaddr + 4)?
+                for register in &multls.rlist {
+                    self.set_register(*register, self.get_memory(memaddr))?;
+                    memaddr = memaddr.wrapping_add(4);
+                }
+                // loaded base isn't overwritten by the write back
+                if !multls.rlist.contains(&multls.rb) {
+                    self.set_register(multls.rb, end)?;
                 }
             }
         }
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 
-    fn run_thumb_ubranch(&mut self, ubranch: ThumbUBranch) -> EResult<()> {
-        let offset = (ubranch.offset * 2 + 4) as u32;
-        if offset == 0 {
-            self.loop_detected = true;
-        }
+    /// Target of a Thumb branch with a signed byte `offset` from PC+4
+    fn thumb_branch_target(&self, offset: i32) -> u32 {
+        self.pc.wrapping_add(4).wrapping_add_signed(offset)
+    }
 
-        self.pc += offset;
+    fn run_thumb_ubranch(&mut self, ubranch: ThumbUBranch) -> EResult<()> {
+        self.pc = self.thumb_branch_target(ubranch.offset as i32);
         Ok(())
     }
 
     fn run_thumb_branch(&mut self, branch: ThumbBranch) -> EResult<()> {
-        match branch.op {
-            ThumbBranchOp::Beq => {
-                if self.zero_flag {
-                    // TODO: handle signed offsets
-                    self.pc += (branch.offset * 2 + 4) as u32;
-                    return Ok(());
-                }
-            }
-            ThumbBranchOp::Bne => {
-                if !self.zero_flag {
-                    // NOTE: is it save to treat pc as i32?
-                    self.pc = (self.pc as i32 + (branch.offset as i32 * 2 + 4)) as u32;
-                    return Ok(());
-                }
-            }
-            ThumbBranchOp::Bcs => {
-                if self.carry_flag {
-                    // TODO: handle signed offsets
-                    self.pc += (branch.offset * 2 + 4) as u32;
-                    return Ok(());
-                }
-            }
-            ThumbBranchOp::Bhi => {
-                if self.carry_flag && !self.zero_flag {
-                    // NOTE: is it save to treat pc as i32?
-                    self.pc = (self.pc as i32 + (branch.offset as i32 * 2 + 4)) as u32;
-                    return Ok(());
-                }
-            }
-        }
+        let taken = match branch.op {
+            ThumbBranchOp::Beq => self.zero_flag,
+            ThumbBranchOp::Bne => !self.zero_flag,
+            ThumbBranchOp::Bcs => self.carry_flag,
+            ThumbBranchOp::Bhi => self.carry_flag && !self.zero_flag,
+        };
 
-        self.pc += 2;
+        if taken {
+            self.pc = self.thumb_branch_target(branch.offset as i32 * 2);
+        } else {
+            self.pc = self.pc.wrapping_add(2);
+        }
         Ok(())
     }
 
     fn run_thumb_long_branch(&mut self, branch: ThumbLongBranch) -> EResult<()> {
-        self.lr = (self.pc + 4) | 1;
-        let val = (self.pc as i32) + 4 + branch.target;
-        self.pc = val as u32;
+        self.call_depth += 1;
+        self.lr = self.pc.wrapping_add(4) | 1;
+        self.pc = self.pc.wrapping_add(4).wrapping_add_signed(branch.target);
         Ok(())
     }
 
-    fn run_next_thumb_instr(&mut self) -> EResult<()> {
-        let half_word = u16::from_le_bytes(
-            self.memory[self.pc as usize..self.pc as usize + 2]
-                .try_into()
-                .unwrap(),
-        );
+    fn run_next_thumb_instr(&mut self) -> EResult<ThumbInstr> {
+        if self.pc & 1 != 0 {
+            return Err(ExecErr::UnalignedPc(self.pc));
+        }
 
-        let fmt = format!(
+        let half_word = self.fetch_u16(self.pc);
+        self.bus.set_open_bus(half_word as u32 * 0x10001);
+        self.record_history(half_word as u32);
+
+        logging!(
+            self.logger,
+            LogCategory::Decode,
             "Trying from half word: {half_word:04X} addr: {:08X}",
             self.pc
         );
-        logging!(self.logging, "{}", fmt);
 
         let instr: EResult<ThumbInstr> = half_word.try_into();
         let instr = match instr {
             Ok(instr) => instr,
             Err(ExecErr::LongInstruction) => {
-                let half_word2 = u16::from_le_bytes(
-                    self.memory[self.pc as usize + 2..self.pc as usize + 4]
-                        .try_into()
-                        .unwrap(),
-                );
+                // second half is missing at the end of the ROM
+                let suffix = self.pc.wrapping_add(2);
+                if !self.bus.is_mapped(suffix.wrapping_add(1)) {
+                    return Err(ExecErr::LongInstruction);
+                }
+                let half_word2 = self.fetch_u16(suffix);
+                if let Some(entry) = self.history.back_mut() {
+                    entry.opcode |= (half_word2 as u32) << 16;
+                }
 
                 ThumbInstr::try_from_long(half_word, half_word2)?
             }
             Err(err) => return Err(err),
         };
 
-        let fmt = format!("Executing: {instr:?}");
-        logging!(self.logging, "{}", fmt);
-
-        match instr {
-            ThumbInstr::Alu(alu) => self.run_thumb_alu(alu)?,
-            ThumbInstr::Lsh(lsh) => self.run_thumb_lsh(lsh)?,
-            ThumbInstr::Lsi(lsi) => self.run_thumb_lsi(lsi)?,
-            ThumbInstr::HiReg(hireg) => self.run_thumb_hireg(hireg)?,
-            ThumbInstr::Mls(mls) => self.run_thumb_mls(mls)?,
-            ThumbInstr::Mcas(mcas) => self.run_thumb_mcas(mcas)?,
-            ThumbInstr::AddSub(add_sub) => self.run_add_sub(add_sub)?,
-            ThumbInstr::MultLS(multls) => self.run_thumb_multiple_load_store(multls)?,
-            ThumbInstr::PushPop(push_pop) => self.run_thumb_push_pop(push_pop)?,
-            ThumbInstr::Branch(branch) => self.run_thumb_branch(branch)?,
-            ThumbInstr::UBranch(ubranch) => self.run_thumb_ubranch(ubranch)?,
-            ThumbInstr::LongBranch(branch) => self.run_thumb_long_branch(branch)?,
-            ThumbInstr::RegShift(reg_shift) => self.run_thumb_reg_shift(reg_shift)?,
+        logging!(self.logger, LogCategory::Decode, "Executing: {instr:?}");
+
+        instr.execute(self)?;
+        Ok(instr)
+    }
+
+    /// Serialize registers, flags, the timing state, the RAM regions and the backup memory into bytes
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = STATE_MAGIC.to_vec();
+        for idx in 0..16_u32 {
+            // all registers are implemented so this can't fail
+            let value = self.get_register(Register::from(idx)).unwrap_or(0);
+            state.extend(value.to_le_bytes());
+        }
+        for value in [self.cpsr(), self.spsr, self.sp_irq, self.sp_svc] {
+            state.extend(value.to_le_bytes());
+        }
+        state.extend(self.cycles.to_le_bytes());
+        state.push(self.halted as u8);
+
+        for (start, size) in STATE_REGIONS {
+            state.extend(self.read_region(start, size));
+        }
+        state.extend(self.bus.backup());
+
+        state
+    }
+
+    /// Restore a state created with [`Cpu::save_state`], ROM is kept as is
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let regions_len: usize = STATE_REGIONS.iter().map(|(_, size)| *size as usize).sum();
+        let expected_len =
+            STATE_MAGIC.len() + STATE_CPU_SIZE + regions_len + self.bus.backup().len();
+        if !state.starts_with(STATE_MAGIC) {
+            return Err("Not a save state".into());
+        }
+        if state.len() != expected_len {
+            return Err(format!(
+                "Invalid save state size {}, expected {expected_len}",
+                state.len()
+            ));
+        }
+
+        let mut words = state[STATE_MAGIC.len()..]
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()));
+        for idx in 0..16_u32 {
+            let value = words.next().unwrap();
+            self.set_register(Register::from(idx), value)
+                .map_err(|e| e.to_string())?;
+        }
+        self.set_cpsr(words.next().unwrap());
+        self.spsr = words.next().unwrap();
+        self.sp_irq = words.next().unwrap();
+        self.sp_svc = words.next().unwrap();
+
+        let cpu_end = STATE_MAGIC.len() + STATE_CPU_SIZE;
+        self.cycles = u64::from_le_bytes(state[cpu_end - 9..cpu_end - 1].try_into().unwrap());
+        self.halted = state[cpu_end - 1] != 0;
+
+        let mut bytes = &state[cpu_end..];
+        for (start, size) in STATE_REGIONS {
+            let (region, rest) = bytes.split_at(size as usize);
+            self.write_region(start, region);
+            bytes = rest;
+        }
+        self.bus.load_backup(bytes);
+
+        Ok(())
+    }
+
+    /// Load the ROM `bytes` and set up the registers for booting it as `boot` describes
+    pub fn initialize_cpu(&mut self, bytes: &[u8], boot: BootConfig) -> EResult<()> {
+        if bytes.len() > ROM_MAX_SIZE as usize
+            || (boot.multiboot && bytes.len() > EWRAM_SIZE as usize)
+        {
+            return Err(ExecErr::RomTooLarge(bytes.len()));
+        }
+
+        let rom = GBAHeader::from_file(bytes)?;
+        if !rom.verify(bytes) {
+            logging!(
+                self.logger,
+                LogCategory::Decode,
+                "ROM header complement check doesn't match"
+            );
+        }
+
+        if boot.skip_bios {
+            self.pc = boot.entry;
+            self.lr = boot.entry;
+            self.sp = boot.sp_usr;
+            self.sp_irq = boot.sp_irq;
+            self.sp_svc = boot.sp_svc;
+        } else {
+            // BIOS reset vector, the BIOS sets up the stacks itself
+            self.pc = 0;
+            self.lr = 0;
+            self.sp = 0;
+            self.sp_irq = 0;
+            self.sp_svc = 0;
         }
 
+        self.boot = boot;
+        self.write_u16(KEYINPUT, KEYS_RELEASED);
+        self.prefetch.clear();
+        self.bus.load_rom(bytes);
+        if boot.multiboot {
+            self.write_region(EWRAM_START, bytes);
+        }
         Ok(())
     }
 
-    pub fn initialize_cpu(&mut self, bytes: &[u8]) {
-        let _rom = GBAHeader::from_file(bytes);
-        self.pc = 0x8000000;
-        self.lr = 0x8000000;
+    /// Clear registers, flags and RAM, and initialize the Cpu again with the loaded ROM.
+    /// The bus is kept, saved games in its backup memory survive the reset.
+    pub fn reset(&mut self) -> EResult<()> {
+        let rom = self.bus.rom().to_vec();
+        let boot = self.boot;
+        self.bus.reset();
+        let bus = std::mem::replace(&mut self.bus, Box::new(NoBus));
+        *self = Self {
+            logger: std::mem::take(&mut self.logger),
+            undefined_exceptions: self.undefined_exceptions,
+            ..Self::with_bus(bus)
+        };
+        self.initialize_cpu(&rom, boot)
+    }
+
+    /// Decode the instruction at `addr` without running it. Unlike executing it,
+    /// the fetch doesn't change PC, the open bus, the history or traced accesses.
+    pub fn peek_instruction(&self, addr: u32, thumb: bool) -> EResult<DecodedInstr> {
+        if !thumb {
+            return Instruction::try_from(self.read_u32(addr)).map(DecodedInstr::Arm);
+        }
 
-        for (idx, b) in bytes.iter().enumerate() {
-            self.memory[self.pc as usize + idx] = *b;
+        let half_word = self.read_u16(addr);
+        match ThumbInstr::try_from(half_word) {
+            Err(ExecErr::LongInstruction) => {
+                let suffix = addr.wrapping_add(2);
+                if !self.bus.is_mapped(suffix.wrapping_add(1)) {
+                    return Err(ExecErr::LongInstruction);
+                }
+                ThumbInstr::try_from_long(half_word, self.read_u16(suffix)).map(DecodedInstr::Thumb)
+            }
+            instr => instr.map(DecodedInstr::Thumb),
         }
     }
 
     pub fn execute_next(&mut self) -> EResult<()> {
-        if self.thumb {
-            self.run_next_thumb_instr()?;
+        self.step().map(|_| ())
+    }
+
+    /// Execute the instruction at PC and return what was executed
+    pub fn step(&mut self) -> EResult<StepInfo> {
+        let pc = self.pc;
+        let before = self.logger.is_asm_enabled().then(|| self.register_values());
+        let thumb = self.thumb;
+        let (result, next_pc) = if thumb {
+            (
+                self.run_next_thumb_instr().map(DecodedInstr::Thumb),
+                pc.wrapping_add(2),
+            )
         } else {
-            self.run_next_instruction()?;
+            (
+                self.run_next_instruction().map(DecodedInstr::Arm),
+                pc.wrapping_add(4),
+            )
+        };
+        let instr = match result {
+            Ok(instr) => instr,
+            Err(ExecErr::Undefined(_)) if self.undefined_exceptions => {
+                self.undefined_exception();
+                DecodedInstr::Undefined { thumb }
+            }
+            Err(e) => return Err(e),
+        };
+
+        if self.pc != next_pc {
+            logging!(
+                self.logger,
+                LogCategory::Branch,
+                "Branch from addr: {pc:08X} to addr: {:08X}",
+                self.pc
+            );
         }
+        self.halted = self.pc == pc;
+        self.stats.instructions += 1;
+        self.advance_cycles(instr_cycles(&instr, self.pc != next_pc));
 
-        Ok(())
+        // recorded when the instruction was fetched
+        let opcode = self.history.back().map_or(0, |entry| entry.opcode);
+        let info = StepInfo {
+            pc,
+            opcode,
+            instr,
+            next_pc: self.pc,
+        };
+        if let Some(before) = before {
+            self.log_asm(&info, &before);
+        }
+        Ok(info)
+    }
+
+    /// Restore the cartridge backup memory from a save file,
+    /// can be done before or after loading the ROM
+    pub fn load_save(&mut self, data: &[u8]) {
+        self.bus.load_backup(data);
+    }
+
+    /// Contents of the cartridge backup memory to write into a save file
+    pub fn save_data(&self) -> &[u8] {
+        self.bus.backup()
+    }
+
+    /// Backup memory type detected from the ROM
+    pub fn save_type(&self) -> SaveType {
+        self.bus.save_type()
+    }
+
+    /// Cycles executed since the start
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Set the interrupt request bits in IF
+    fn request_irq(&mut self, irq: u16) {
+        let flags = self.read_u16(IF);
+        self.write_u16(IF, flags | irq);
+    }
+
+    /// Press or release a key in KEYINPUT and request the keypad interrupt
+    /// if the pressed keys match KEYCNT
+    pub fn set_key(&mut self, key: Key, pressed: bool) {
+        let keys = self.read_u16(KEYINPUT);
+        let keys = if pressed {
+            keys & !key.bit()
+        } else {
+            keys | key.bit()
+        };
+        self.write_u16(KEYINPUT, keys);
+
+        if keypad_irq(keys, self.read_u16(KEYCNT)) {
+            self.request_irq(IRQ_KEYPAD);
+        }
+    }
+
+    /// Advance the cycle counter and update the LCD status in DISPSTAT and VCOUNT
+    fn advance_cycles(&mut self, cycles: u64) {
+        let before = LcdState::at(self.cycles);
+        self.cycles += cycles;
+        let now = LcdState::at(self.cycles);
+
+        let dispstat = self.read_u16(DISPSTAT);
+        let flags = now.dispstat_flags(dispstat);
+        let vcount_before = before.dispstat_flags(dispstat) & DISPSTAT_VCOUNT != 0;
+
+        // interrupts are requested when the state is entered
+        if now.vblank && !before.vblank && dispstat & DISPSTAT_VBLANK_IRQ != 0 {
+            self.request_irq(IRQ_VBLANK);
+        }
+        if now.hblank && !before.hblank && dispstat & DISPSTAT_HBLANK_IRQ != 0 {
+            self.request_irq(IRQ_HBLANK);
+        }
+        if flags & DISPSTAT_VCOUNT != 0 && !vcount_before && dispstat & DISPSTAT_VCOUNT_IRQ != 0 {
+            self.request_irq(IRQ_VCOUNT);
+        }
+
+        // bits 0-2 are read only status flags
+        self.write_u16(DISPSTAT, (dispstat & !0b111) | flags);
+        self.write_u16(VCOUNT, now.line as u16);
+    }
+
+    /// The loaded cartridge ROM
+    pub fn rom(&self) -> &[u8] {
+        self.bus.rom()
+    }
+
+    /// Size of the loaded ROM in bytes
+    pub fn rom_size(&self) -> usize {
+        self.bus.rom().len()
+    }
+
+    /// Is the cpu executing Thumb instructions
+    pub fn is_thumb(&self) -> bool {
+        self.thumb
+    }
+
+    /// Execute Thumb or ARM instructions from the current PC
+    pub fn set_thumb(&mut self, thumb: bool) {
+        self.thumb = thumb;
+    }
+
+    /// Is the cpu stuck in an instruction that branches into itself
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Execute instructions until at least `budget` cycles have passed. The LCD status
+    /// advances with the cycles of every instruction, also while the cpu is halted.
+    /// Returns the executed cycles, the last instruction can go over the budget.
+    pub fn step_cycles(&mut self, budget: u64) -> EResult<u64> {
+        let start = self.cycles;
+        while self.cycles - start < budget {
+            if self.halted {
+                // the branch into itself would only pass the time again
+                self.advance_cycles(HALTED_CYCLES);
+            } else {
+                self.execute_next()?;
+            }
+        }
+
+        Ok(self.cycles - start)
+    }
+
+    /// Execute until the start of the next frame
+    pub fn run_frame(&mut self) -> EResult<()> {
+        let frame_end = (self.cycles / FRAME_CYCLES + 1) * FRAME_CYCLES;
+        let result = self.step_cycles(frame_end - self.cycles);
+        self.stats.frames += 1;
+        result.map(|_| ())
     }
 
-    pub fn run_rom(&mut self, bytes: &[u8], breakloop: bool) -> EResult<()> {
-        self.initialize_cpu(bytes);
+    /// Executed instructions and frames and the time spent running them
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Count `elapsed` wall time as emulation time. Callers of `run_frame` add the
+    /// time between frames, so rendering and frame pacing are included.
+    pub fn add_run_time(&mut self, elapsed: Duration) {
+        self.stats.elapsed += elapsed;
+    }
+
+    pub fn run_rom(&mut self, bytes: &[u8], boot: BootConfig, breakloop: bool) -> EResult<()> {
+        self.initialize_cpu(bytes, boot)?;
 
-        while !breakloop || !self.loop_detected {
+        while !breakloop || !self.halted {
             self.execute_next()?
         }
 
         println!("Loop detected. Done with running the rom");
         Ok(())
     }
+
+    /// Initialize the cpu with `bytes` and execute up to `max_steps` instructions,
+    /// stopping early if the cpu halts. Returns the amount of executed instructions.
+    /// Multiboot images are detected from the header.
+    pub fn run_rom_steps(&mut self, bytes: &[u8], max_steps: u64) -> EResult<u64> {
+        self.initialize_cpu(bytes, BootConfig::detect(bytes))?;
+
+        for step in 0..max_steps {
+            self.execute_next()?;
+            if self.halted {
+                return Ok(step + 1);
+            }
+        }
+
+        Ok(max_steps)
+    }
+}
+
+/// Implement `Executable` for instructions that are run by a `Cpu` method
+/// taking the instruction
+macro_rules! executable {
+    ($($instr:ty => $run:ident,)*) => {
+        $(
+            impl Executable for $instr {
+                fn execute(&self, cpu: &mut Cpu) -> EResult<()> {
+                    cpu.$run(self.clone())
+                }
+            }
+        )*
+    };
+}
+
+executable! {
+    Branch => run_branch,
+    BranchLinkExchange => run_branch_link_exchange,
+    BranchExchange => run_branch_exhange,
+    Alu => run_alu,
+    Sdt => run_sdt,
+    Swap => run_swap,
+    ThumbAlu => run_thumb_alu,
+    ThumbLsh => run_thumb_lsh,
+    ThumbLsi => run_thumb_lsi,
+    ThumbHiReg => run_thumb_hireg,
+    ThumbMls => run_thumb_mls,
+    ThumbMcas => run_thumb_mcas,
+    ThumbAddSub => run_add_sub,
+    ThumbMultLS => run_thumb_multiple_load_store,
+    ThumbPushPop => run_thumb_push_pop,
+    ThumbBranch => run_thumb_branch,
+    ThumbUBranch => run_thumb_ubranch,
+    ThumbLongBranch => run_thumb_long_branch,
+    ThumbRegShift => run_thumb_reg_shift,
+}
+
+impl Executable for Swi {
+    fn execute(&self, cpu: &mut Cpu) -> EResult<()> {
+        if cpu.condition_passed(&self.condition) {
+            cpu.run_swi((self.comment >> 16) as u8)?;
+        }
+        cpu.pc = cpu.pc.wrapping_add(4);
+        Ok(())
+    }
+}
+
+impl Executable for ThumbSwi {
+    fn execute(&self, cpu: &mut Cpu) -> EResult<()> {
+        cpu.run_swi(self.comment)?;
+        cpu.pc = cpu.pc.wrapping_add(2);
+        Ok(())
+    }
+}
+
+impl Executable for Instruction {
+    fn execute(&self, cpu: &mut Cpu) -> EResult<()> {
+        match self {
+            Instruction::Branch(b) => b.execute(cpu),
+            Instruction::BranchLinkExchange(b) => b.execute(cpu),
+            Instruction::BranchExchange(b) => b.execute(cpu),
+            Instruction::Alu(alu) => alu.execute(cpu),
+            Instruction::Sdt(sdt) => sdt.execute(cpu),
+            Instruction::Swap(swap) => swap.execute(cpu),
+            Instruction::Swi(swi) => swi.execute(cpu),
+            Instruction::Psr => {
+                logging!(cpu.logger, LogCategory::Decode, "Ignoring Psr instructions");
+                cpu.pc = cpu.pc.wrapping_add(4);
+                Ok(())
+            }
+            // there's no cache to preload into
+            Instruction::Pld(_) => {
+                cpu.pc = cpu.pc.wrapping_add(4);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Executable for ThumbInstr {
+    fn execute(&self, cpu: &mut Cpu) -> EResult<()> {
+        match self {
+            ThumbInstr::Alu(alu) => alu.execute(cpu),
+            ThumbInstr::Lsh(lsh) => lsh.execute(cpu),
+            ThumbInstr::Lsi(lsi) => lsi.execute(cpu),
+            ThumbInstr::HiReg(hireg) => hireg.execute(cpu),
+            ThumbInstr::Mls(mls) => mls.execute(cpu),
+            ThumbInstr::Mcas(mcas) => mcas.execute(cpu),
+            ThumbInstr::AddSub(add_sub) => add_sub.execute(cpu),
+            ThumbInstr::MultLS(multls) => multls.execute(cpu),
+            ThumbInstr::PushPop(push_pop) => push_pop.execute(cpu),
+            ThumbInstr::Branch(branch) => branch.execute(cpu),
+            ThumbInstr::Swi(swi) => swi.execute(cpu),
+            ThumbInstr::UBranch(ubranch) => ubranch.execute(cpu),
+            ThumbInstr::LongBranch(branch) => branch.execute(cpu),
+            ThumbInstr::RegShift(reg_shift) => reg_shift.execute(cpu),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{collections::HashMap, rc::Rc};
+
     use super::*;
+    use crate::{
+        lcd::{DISPSTAT_HBLANK, DISPSTAT_VBLANK, HDRAW_CYCLES, LINE_CYCLES},
+        test_rom::{arm, interworking_rom, rom_bytes, thumb},
+    };
 
     #[test]
     fn test_thumb_push_pop() {
@@ -677,7 +2215,7 @@ mod tests {
             .map(u16::to_le_bytes)
             .iter()
             .flatten()
-            .map(|c| *c)
+            .copied()
             .collect();
         let mut cpu = Cpu::new();
         cpu.r0 = 0x02040000;
@@ -693,9 +2231,9 @@ mod tests {
         cpu.pc = 0x08000210;
         cpu.thumb = true;
 
-        for (idx, instr) in isntrs.iter().enumerate() {
-            cpu.memory[cpu.pc as usize + idx] = *instr;
-        }
+        let mut rom = vec![0; 0x210];
+        rom.extend(isntrs);
+        cpu.bus.load_rom(&rom);
 
         // push r3-r7,r14
         cpu.execute_next().unwrap();
@@ -730,4 +2268,2278 @@ mod tests {
         assert_eq!(cpu.lr, 0x080002bd);
         assert_eq!(cpu.sp, 0x03007ef0);
     }
+
+    #[test]
+    fn test_set_register_by_name() {
+        let mut cpu = Cpu::new();
+        cpu.set_register_by_name("r3", 3).unwrap();
+        cpu.set_register_by_name("R10", 10).unwrap();
+        cpu.set_register_by_name("r13", 13).unwrap();
+        cpu.set_register_by_name("lr", 14).unwrap();
+        cpu.set_register_by_name("pc", 0x08000000).unwrap();
+        assert_eq!(cpu.r3, 3);
+        assert_eq!(cpu.r10, 10);
+        assert_eq!(cpu.sp, 13);
+        assert_eq!(cpu.lr, 14);
+        assert_eq!(cpu.pc, 0x08000000);
+
+        cpu.set_register_by_name("cpsr", 0x6000003F).unwrap();
+        assert!(cpu.zero_flag);
+        assert!(cpu.carry_flag);
+        assert!(!cpu.sign_flag);
+        assert!(cpu.thumb);
+        assert_eq!(cpu.cpsr(), 0x6000003F);
+
+        assert!(cpu.set_register_by_name("r16", 0).is_err());
+        assert!(cpu.set_register_by_name("x1", 0).is_err());
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut cpu = Cpu::new();
+        cpu.r0 = 0x12345678;
+        cpu.r12 = 12;
+        cpu.sp = 0x03007f00;
+        cpu.pc = 0x08000100;
+        cpu.carry_flag = true;
+        cpu.thumb = true;
+        cpu.spsr = 0x6000001F;
+        cpu.sp_irq = 0x03007FA0;
+        cpu.sp_svc = 0x03007FE0;
+        cpu.cycles = 0x1_0000_1234;
+        cpu.halted = true;
+        cpu.set_memory(0x02000000, 0xdeadbeef);
+        cpu.set_memory(0x06017ffc, 0x0a0b0c0d);
+        let state = cpu.save_state();
+
+        cpu.r0 = 0;
+        cpu.r12 = 0;
+        cpu.sp = 0;
+        cpu.pc = 0;
+        cpu.carry_flag = false;
+        cpu.zero_flag = true;
+        cpu.thumb = false;
+        cpu.spsr = 0;
+        cpu.sp_irq = 0;
+        cpu.sp_svc = 0;
+        cpu.cycles = 0;
+        cpu.halted = false;
+        cpu.set_memory(0x02000000, 0);
+        cpu.set_memory(0x06017ffc, 0);
+
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.r0, 0x12345678);
+        assert_eq!(cpu.r12, 12);
+        assert_eq!(cpu.sp, 0x03007f00);
+        assert_eq!(cpu.pc, 0x08000100);
+        assert!(cpu.carry_flag);
+        assert!(!cpu.zero_flag);
+        assert!(cpu.thumb);
+        assert_eq!(cpu.spsr, 0x6000001F);
+        assert_eq!(cpu.sp_irq, 0x03007FA0);
+        assert_eq!(cpu.sp_svc, 0x03007FE0);
+        assert_eq!(cpu.cycles, 0x1_0000_1234);
+        assert!(cpu.halted);
+        assert_eq!(cpu.get_memory(0x02000000), 0xdeadbeef);
+        assert_eq!(cpu.get_memory(0x06017ffc), 0x0a0b0c0d);
+        assert!(cpu.save_state() == state);
+    }
+
+    #[test]
+    fn test_load_invalid_state() {
+        let mut cpu = Cpu::new();
+        assert!(cpu.load_state(b"nope").is_err());
+        let mut state = cpu.save_state();
+        state.pop();
+        assert!(cpu.load_state(&state).is_err());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut rom = rom_bytes(0x200);
+        // mov r0, #5
+        rom[0..4].copy_from_slice(&0xE3A00005_u32.to_le_bytes());
+        // mov r1, #0x03000000
+        rom[4..8].copy_from_slice(&0xE3A01403_u32.to_le_bytes());
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.execute_next().unwrap();
+        cpu.execute_next().unwrap();
+        cpu.set_memory(0x03000000, 0x1234);
+        cpu.zero_flag = true;
+        assert_eq!(cpu.pc, 0x08000008);
+
+        cpu.reset().unwrap();
+        assert_eq!(cpu.pc, 0x08000000);
+        assert_eq!(cpu.r0, 0);
+        assert_eq!(cpu.r1, 0);
+        assert!(!cpu.zero_flag);
+        assert_eq!(cpu.get_memory(0x03000000), 0);
+        assert_eq!(cpu.get_memory(0x08000000), 0xE3A00005);
+    }
+
+    #[test]
+    fn test_boot_config() {
+        let rom = rom_bytes(0x200);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        assert_eq!(cpu.pc, 0x08000000);
+        assert_eq!(cpu.lr, 0x08000000);
+        assert_eq!(cpu.sp, 0x03007F00);
+        assert_eq!(cpu.sp_irq, 0x03007FA0);
+        assert_eq!(cpu.sp_svc, 0x03007FE0);
+
+        // multiboot image in EWRAM, entry point is kept over a reset
+        let boot = BootConfig {
+            entry: 0x02000000,
+            ..Default::default()
+        };
+        cpu.initialize_cpu(&rom, boot).unwrap();
+        cpu.pc = 0x02000010;
+        cpu.reset().unwrap();
+        assert_eq!(cpu.pc, 0x02000000);
+        assert_eq!(cpu.sp, 0x03007F00);
+
+        // BIOS reset vector
+        let boot = BootConfig {
+            skip_bios: false,
+            ..Default::default()
+        };
+        cpu.initialize_cpu(&rom, boot).unwrap();
+        assert_eq!(cpu.pc, 0);
+        assert_eq!(cpu.sp, 0);
+        assert_eq!(cpu.sp_irq, 0);
+    }
+
+    #[test]
+    fn test_multiboot() {
+        let mut image = rom_bytes(0x200);
+        // b 0x020000E0 at the RAM entry point
+        image[0xC0..0xC4].copy_from_slice(&0xEA000006_u32.to_le_bytes());
+        // normal mode transfer
+        image[0xC4] = 2;
+        // add r1, pc, #0
+        image[0xE0..0xE4].copy_from_slice(&0xE28F1000_u32.to_le_bytes());
+        // b .
+        image[0xE4..0xE8].copy_from_slice(&0xEAFFFFFE_u32.to_le_bytes());
+        assert_eq!(BootConfig::detect(&image), BootConfig::multiboot());
+        assert_eq!(BootConfig::detect(&rom_bytes(0x200)), BootConfig::default());
+        // ROMs with garbage in the boot mode byte
+        let mut rom = rom_bytes(0x200);
+        rom[0xC4] = 2;
+        assert_eq!(BootConfig::detect(&rom), BootConfig::default());
+        let mut rom = image.clone();
+        rom[0xC4] = 0xFF;
+        assert_eq!(BootConfig::detect(&rom), BootConfig::default());
+        let mut rom = image.clone();
+        rom.resize(EWRAM_SIZE as usize + 4, 0);
+        assert_eq!(BootConfig::detect(&rom), BootConfig::default());
+
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.run_rom_steps(&image, 10).unwrap(), 3);
+        assert_eq!(cpu.pc, 0x020000E4);
+        assert_eq!(cpu.r1, 0x020000E8);
+        assert_eq!(cpu.get_memory(0x020000C0), 0xEA000006);
+
+        // reset copies the image into EWRAM again
+        cpu.set_memory(0x020000E0, 0);
+        cpu.reset().unwrap();
+        assert_eq!(cpu.pc, 0x020000C0);
+        assert_eq!(cpu.get_memory(0x020000E0), 0xE28F1000);
+
+        let image = rom_bytes(EWRAM_SIZE as usize + 4);
+        assert_eq!(
+            cpu.initialize_cpu(&image, BootConfig::multiboot()),
+            Err(ExecErr::RomTooLarge(EWRAM_SIZE as usize + 4))
+        );
+    }
+
+    #[test]
+    fn test_boot_stack_push_pop() {
+        let rom = interworking_rom(&[(
+            0x10,
+            thumb(&[
+                0x242A, // mov r4, #0x2A
+                0xB410, // push {r4}
+                0x2400, // mov r4, #0
+                0xBC20, // pop {r5}
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        assert_eq!(cpu.sp, 0x03007F00);
+        cpu.thumb = true;
+        cpu.pc = 0x08000010;
+
+        cpu.execute_next().unwrap();
+        cpu.execute_next().unwrap();
+        // pushed into IWRAM instead of address 0
+        assert_eq!(cpu.sp, 0x03007EFC);
+        assert_eq!(cpu.get_memory(0x03007EFC), 0x2A);
+        assert_eq!(cpu.get_memory(0), 0);
+
+        cpu.execute_next().unwrap();
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r5, 0x2A);
+        assert_eq!(cpu.sp, 0x03007F00);
+    }
+
+    #[test]
+    fn test_log_categories() {
+        let mut rom = rom_bytes(0x200);
+        // mov r0, #5
+        rom[0..4].copy_from_slice(&0xE3A00005_u32.to_le_bytes());
+        // b 0x08000010
+        rom[4..8].copy_from_slice(&0xEA000001_u32.to_le_bytes());
+
+        let path = std::env::temp_dir().join("gba_emu_test_log_categories.log");
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.set_logging(true);
+        cpu.logger_mut()
+            .set_file(Some(std::fs::File::create(&path).unwrap()));
+        cpu.logger_mut().set_categories(&[LogCategory::Branch]);
+        cpu.execute_next().unwrap();
+        cpu.execute_next().unwrap();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(log.lines().count(), 1);
+        assert!(log.contains("Branch from addr: 08000004 to addr: 08000010"));
+        assert!(!log.contains("Executing"));
+    }
+
+    #[test]
+    fn test_log_asm() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE3A00005, // mov r0, #5
+                0xE2801001, // add r1, r0, #1
+            ]),
+        )]);
+        let path = std::env::temp_dir().join("gba_emu_test_log_asm.log");
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.logger_mut().set_asm(true);
+        cpu.logger_mut()
+            .set_file(Some(std::fs::File::create(&path).unwrap()));
+        cpu.execute_next().unwrap();
+        cpu.execute_next().unwrap();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "08000000: MOV r0, #0x5 r0=00000005",
+                "08000004: ADD r1, r0, #0x1 r1=00000006"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_memory_trace() {
+        let mut rom = rom_bytes(0x200);
+        for (idx, instr) in [
+            0xE3A00005_u32, // mov r0, #5
+            0xE3A01403,     // mov r1, #0x03000000
+            0xE5810000,     // str r0, [r1]
+            0xE5912000,     // ldr r2, [r1]
+        ]
+        .iter()
+        .enumerate()
+        {
+            rom[idx * 4..idx * 4 + 4].copy_from_slice(&instr.to_le_bytes());
+        }
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.set_trace_range(Some((0x03000000, 0x030000ff)));
+        for _ in 0..4 {
+            cpu.execute_next().unwrap();
+        }
+        // outside of the traced range
+        cpu.set_memory_u16(0x03000100, 1);
+        cpu.get_memory(0x02000000);
+
+        let trace = cpu.take_trace();
+        assert_eq!(
+            trace,
+            vec![
+                TraceEntry {
+                    pc: 0x08000008,
+                    addr: 0x03000000,
+                    value: 5,
+                    is_write: true,
+                    size: 4,
+                },
+                TraceEntry {
+                    pc: 0x0800000C,
+                    addr: 0x03000000,
+                    value: 5,
+                    is_write: false,
+                    size: 4,
+                },
+            ]
+        );
+        assert_eq!(trace[0].to_string(), "08000008: write 03000000 = 00000005");
+        assert!(cpu.take_trace().is_empty());
+
+        cpu.set_trace_range(None);
+        cpu.set_memory_u16(0x03000000, 1);
+        assert!(cpu.take_trace().is_empty());
+    }
+
+    #[test]
+    fn test_run_rom_steps() {
+        let mut rom = rom_bytes(0x200);
+        for (idx, instr) in [
+            0xE3A00005_u32, // mov r0, #5
+            0xE3A01403,     // mov r1, #0x03000000
+            0xE5810000,     // str r0, [r1]
+            0xE3A00007,     // mov r0, #7
+        ]
+        .iter()
+        .enumerate()
+        {
+            rom[idx * 4..idx * 4 + 4].copy_from_slice(&instr.to_le_bytes());
+        }
+
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.run_rom_steps(&rom, 3), Ok(3));
+        assert_eq!(cpu.r0, 5);
+        assert_eq!(cpu.get_memory(0x03000000), 5);
+        assert_eq!(cpu.pc, 0x0800000C);
+
+        // running again starts from the beginning of the rom
+        assert_eq!(cpu.run_rom_steps(&rom, 2), Ok(2));
+        assert_eq!(cpu.pc, 0x08000008);
+
+        assert_eq!(cpu.run_rom_steps(&rom, 0), Ok(0));
+        assert_eq!(cpu.pc, 0x08000000);
+    }
+
+    #[test]
+    fn test_self_branch_halts() {
+        let mut rom = rom_bytes(0x200);
+        // mov r0, #5
+        rom[0..4].copy_from_slice(&0xE3A00005_u32.to_le_bytes());
+        // b .
+        rom[4..8].copy_from_slice(&0xEAFFFFFE_u32.to_le_bytes());
+
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.run_rom_steps(&rom, 1000), Ok(2));
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.pc, 0x08000004);
+
+        cpu.reset().unwrap();
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.run_rom(&rom, BootConfig::default(), true), Ok(()));
+        assert_eq!(cpu.pc, 0x08000004);
+    }
+
+    #[test]
+    fn test_backward_branch() {
+        let mut rom = rom_bytes(0x200);
+        // b 0x08000010
+        rom[0..4].copy_from_slice(&0xEA000002_u32.to_le_bytes());
+        // b 0x08000008
+        rom[0x10..0x14].copy_from_slice(&0xEAFFFFFC_u32.to_le_bytes());
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.pc, 0x08000010);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.pc, 0x08000008);
+        assert!(!cpu.is_halted());
+    }
+
+    #[test]
+    fn test_subs_bne_countdown() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE3A00005, // mov r0, #5
+                0xE3A01000, // mov r1, #0
+                0xE2811001, // loop: add r1, r1, #1
+                0xE2500001, // subs r0, r0, #1
+                0x1AFFFFFC, // bne loop
+                0xEAFFFFFE, // b .
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        // 2 movs, 5 iterations of 3 instructions and the final b
+        assert_eq!(cpu.run_rom_steps(&rom, 100).unwrap(), 18);
+        assert!(cpu.is_halted());
+        assert_eq!((cpu.r0, cpu.r1), (0, 5));
+        assert_eq!(cpu.flags(), "-ZC-");
+    }
+
+    #[test]
+    fn test_arm_subs_adds_flags() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE3A00000, // mov r0, #0
+                0xE2501001, // subs r1, r0, #1
+                0xE2912001, // adds r2, r1, #1
+                0xE3A03102, // mov r3, #0x80000000
+                0xE2534001, // subs r4, r3, #1
+                0xE2945001, // adds r5, r4, #1
+                0x5AFFFFFE, // bpl .
+                0xE2916000, // adds r6, r1, #0
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.execute_next().unwrap();
+
+        // borrow clears C
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r1, 0xFFFFFFFF);
+        assert_eq!(cpu.flags(), "N---");
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r2, 0);
+        assert_eq!(cpu.flags(), "-ZC-");
+
+        cpu.execute_next().unwrap();
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r4, 0x7FFFFFFF);
+        assert_eq!(cpu.flags(), "--CV");
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r5, 0x80000000);
+        assert_eq!(cpu.flags(), "N--V");
+
+        // N is set so BPL falls through
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.pc, 0x0800001C);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.flags(), "N---");
+    }
+
+    #[test]
+    fn test_swap_word() {
+        let mut rom = rom_bytes(0x200);
+        // swp r0, r2, [r1]
+        rom[0..4].copy_from_slice(&0xE1010092_u32.to_le_bytes());
+        // swp r3, r3, [r1]
+        rom[4..8].copy_from_slice(&0xE1013093_u32.to_le_bytes());
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r1 = 0x03000000;
+        cpu.r2 = 0xCAFEBABE;
+        cpu.r3 = 0x12345678;
+        cpu.set_memory(0x03000000, 0xDEADBEEF);
+
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0xDEADBEEF);
+        assert_eq!(cpu.get_memory(0x03000000), 0xCAFEBABE);
+
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r3, 0xCAFEBABE);
+        assert_eq!(cpu.get_memory(0x03000000), 0x12345678);
+        assert_eq!(cpu.pc, 0x08000008);
+    }
+
+    #[test]
+    fn test_swap_byte() {
+        let mut rom = rom_bytes(0x200);
+        // swpb r0, r2, [r1]
+        rom[0..4].copy_from_slice(&0xE1410092_u32.to_le_bytes());
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r1 = 0x03000001;
+        cpu.r2 = 0xCAFEBABE;
+        cpu.set_memory(0x03000000, 0x11223344);
+
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0x33);
+        assert_eq!(cpu.get_memory(0x03000000), 0x1122BE44);
+        assert_eq!(cpu.pc, 0x08000004);
+    }
+
+    #[test]
+    fn test_flags_display() {
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.flags(), "----");
+
+        cpu.sign_flag = true;
+        cpu.carry_flag = true;
+        assert_eq!(cpu.flags(), "N-C-");
+        assert!(cpu.to_string().contains("    flags: N-C-,\n"));
+        assert!(cpu.to_string().contains("    zero_flag: false,\n"));
+
+        cpu.zero_flag = true;
+        cpu.overflow_flag = true;
+        assert_eq!(cpu.flags(), "NZCV");
+    }
+
+    #[test]
+    fn test_add_flags() {
+        assert_eq!(add_flags(1, 2), (3, false, false));
+        assert_eq!(add_flags(0xFFFFFFFF, 1), (0, true, false));
+        assert_eq!(add_flags(0x7FFFFFFF, 1), (0x80000000, false, true));
+        assert_eq!(add_flags(0x80000000, 0x80000000), (0, true, true));
+        assert_eq!(add_flags(0xFFFFFFFF, 0xFFFFFFFF), (0xFFFFFFFE, true, false));
+    }
+
+    #[test]
+    fn test_sub_flags() {
+        assert_eq!(sub_flags(3, 2), (1, true, false));
+        assert_eq!(sub_flags(2, 2), (0, true, false));
+        assert_eq!(sub_flags(0, 1), (0xFFFFFFFF, false, false));
+        assert_eq!(sub_flags(0x80000000, 1), (0x7FFFFFFF, true, true));
+        assert_eq!(sub_flags(0x7FFFFFFF, 0xFFFFFFFF), (0x80000000, false, true));
+    }
+
+    #[test]
+    fn test_thumb_ldr_literal_and_register_base() {
+        let rom = interworking_rom(&[
+            (
+                0x00,
+                thumb(&[
+                    0x4801, // ldr r0, [pc, #0x4]
+                    0x4801, // ldr r0, [pc, #0x4]
+                ]),
+            ),
+            (0x08, 0x11111111_u32.to_le_bytes().to_vec()),
+            (0x0C, 0x22222222_u32.to_le_bytes().to_vec()),
+        ]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.thumb = true;
+
+        // (0x08000000 + 4) & !2 + 4
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0x11111111);
+        // (0x08000002 + 4) & !2 + 4, same word
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0x11111111);
+
+        // register base isn't aligned or offset by the pipeline
+        cpu.r1 = 0x08000008;
+        cpu.run_thumb_mls(ThumbMls {
+            op: ThumbMlsOp::Ldr,
+            rd: Register::R0,
+            rb: Register::R1,
+            nn: 4,
+        })
+        .unwrap();
+        assert_eq!(cpu.r0, 0x22222222);
+    }
+
+    #[test]
+    fn test_thumb_lsi() {
+        let rom = interworking_rom(&[(
+            0x00,
+            thumb(&[
+                0x6048, // str r0, [r1, #0x4]
+                0x684A, // ldr r2, [r1, #0x4]
+                0x704B, // strb r3, [r1, #0x1]
+                0x79CC, // ldrb r4, [r1, #0x7]
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.thumb = true;
+        cpu.r0 = 0xAABBCCDD;
+        cpu.r1 = 0x03000000;
+        cpu.r3 = 0x12345678;
+
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.get_memory(0x03000004), 0xAABBCCDD);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r2, 0xAABBCCDD);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.get_memory(0x03000000), 0x00007800);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r4, 0xAA);
+        assert_eq!(cpu.pc, 0x08000008);
+    }
+
+    #[test]
+    fn test_thumb_lsh() {
+        let rom = interworking_rom(&[(
+            0x00,
+            thumb(&[
+                0x8048, // strh r0, [r1, #0x2]
+                0x884A, // ldrh r2, [r1, #0x2]
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.thumb = true;
+        cpu.r0 = 0x1234BEEF;
+        cpu.r1 = 0x03000000;
+        cpu.r2 = 0xFFFFFFFF;
+
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.get_memory(0x03000000), 0xBEEF0000);
+        cpu.execute_next().unwrap();
+        // upper bits are zeroed
+        assert_eq!(cpu.r2, 0x0000BEEF);
+        assert_eq!(cpu.pc, 0x08000004);
+    }
+
+    #[test]
+    fn test_thumb_branches_forward_and_backward() {
+        // (instruction, zero flag, carry flag) that takes the branch
+        let branches = [
+            (0xD000, true, false),  // beq
+            (0xD100, false, false), // bne
+            (0xD200, false, true),  // bcs
+            (0xD800, false, true),  // bhi
+        ];
+        for (instr, zero, carry) in branches {
+            // forward by 4 from PC+4 at 0x08000010, backward by 8 at 0x08000020
+            let rom = interworking_rom(&[
+                (0x10, thumb(&[instr | 0x02])),
+                (0x20, thumb(&[instr | 0xFC])),
+            ]);
+            let mut cpu = Cpu::new();
+            cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+            cpu.thumb = true;
+            cpu.zero_flag = zero;
+            cpu.carry_flag = carry;
+
+            cpu.pc = 0x08000010;
+            cpu.execute_next().unwrap();
+            assert_eq!(cpu.pc, 0x08000018, "{instr:04x} forward");
+
+            cpu.pc = 0x08000020;
+            cpu.execute_next().unwrap();
+            assert_eq!(cpu.pc, 0x0800001C, "{instr:04x} backward");
+
+            // not taken
+            cpu.zero_flag = !zero;
+            cpu.carry_flag = !carry;
+            cpu.pc = 0x08000020;
+            cpu.execute_next().unwrap();
+            assert_eq!(cpu.pc, 0x08000022, "{instr:04x} not taken");
+        }
+    }
+
+    #[test]
+    fn test_thumb_unconditional_branch_offsets() {
+        // (11 bit offset, byte offset from PC+4)
+        for (imm, offset) in [(0x200, 0x400), (0x3FF, 0x7FE), (0x400, -0x800), (0x7FF, -2)] {
+            let ubranch = ThumbUBranch::try_from(0xE000 | imm).unwrap();
+            assert_eq!(ubranch.offset, offset, "{imm:03x}");
+
+            let mut cpu = Cpu::new();
+            cpu.thumb = true;
+            cpu.pc = 0x08001000;
+            cpu.run_thumb_ubranch(ubranch).unwrap();
+            assert_eq!(cpu.pc, (0x08001004 + offset as i32) as u32, "{imm:03x}");
+        }
+    }
+
+    #[test]
+    fn test_thumb_long_branch_errors() {
+        // bl prefix followed by a nop
+        let rom = interworking_rom(&[(0x10, thumb(&[0xF000, 0x46C0])), (0x1FE, thumb(&[0xF000]))]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.thumb = true;
+        cpu.pc = 0x08000010;
+        assert_eq!(
+            cpu.execute_next(),
+            Err(ExecErr::InvalidLongInstr(0xF000, 0x46C0))
+        );
+
+        // bl prefix as the last half word of the ROM
+        cpu.pc = 0x080001FE;
+        assert_eq!(cpu.execute_next(), Err(ExecErr::LongInstruction));
+        assert_eq!(cpu.pc, 0x080001FE);
+    }
+
+    #[test]
+    fn test_thumb_unconditional_branch_backward() {
+        // b $-0x4, back to itself
+        let rom = interworking_rom(&[(0x10, thumb(&[0xE7FE]))]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.thumb = true;
+        cpu.pc = 0x08000010;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.pc, 0x08000010);
+        assert!(cpu.is_halted());
+    }
+
+    #[test]
+    fn test_thumb_push_lr_pop_pc() {
+        let rom = interworking_rom(&[(
+            0x00,
+            thumb(&[
+                0xB501, // push {r0, lr}
+                0xBD02, // pop {r1, pc}
+            ]),
+        )]);
+        for (lr, pc, thumb) in [
+            (0x08000021, 0x08000020, true),
+            (0x08000040, 0x08000040, false),
+        ] {
+            let mut cpu = Cpu::new();
+            cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+            cpu.thumb = true;
+            cpu.r0 = 0x1234;
+            cpu.lr = lr;
+            let sp = cpu.sp;
+
+            cpu.execute_next().unwrap();
+            assert_eq!(cpu.sp, sp - 8);
+            assert_eq!(cpu.get_memory(sp - 4), lr);
+            assert_eq!(cpu.get_memory(sp - 8), 0x1234);
+
+            cpu.execute_next().unwrap();
+            assert_eq!(cpu.r1, 0x1234);
+            assert_eq!(cpu.sp, sp);
+            assert_eq!(cpu.pc, pc);
+            assert_eq!(cpu.thumb, thumb);
+        }
+    }
+
+    #[test]
+    fn test_thumb_multiple_load_store_edge_cases() {
+        let rom = interworking_rom(&[(
+            0x00,
+            thumb(&[
+                0xC000, // stmia r0!, {}
+                0xC807, // ldmia r0!, {r0-r2}
+                0xC103, // stmia r1!, {r0, r1}
+                0xC20C, // stmia r2!, {r2, r3}
+                0xC800, // ldmia r0!, {}
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.thumb = true;
+
+        // empty list stores PC+6 and adds 0x40 to the base
+        cpu.r0 = 0x03000000;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.get_memory(0x03000000), 0x08000006);
+        assert_eq!(cpu.r0, 0x03000040);
+
+        // loaded base wins over the write back
+        cpu.set_memory(0x03000040, 0x03000100);
+        cpu.set_memory(0x03000044, 0x03000200);
+        cpu.set_memory(0x03000048, 0x03000300);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0x03000100);
+        assert_eq!(cpu.r1, 0x03000200);
+        assert_eq!(cpu.r2, 0x03000300);
+        assert_eq!(cpu.pc, 0x08000004);
+
+        // base later in the list stores the written back base
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.get_memory(0x03000200), 0x03000100);
+        assert_eq!(cpu.get_memory(0x03000204), 0x03000208);
+        assert_eq!(cpu.r1, 0x03000208);
+
+        // base first in the list stores the old base
+        cpu.r3 = 0x1234;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.get_memory(0x03000300), 0x03000300);
+        assert_eq!(cpu.get_memory(0x03000304), 0x1234);
+        assert_eq!(cpu.r2, 0x03000308);
+
+        // empty list loads PC
+        cpu.set_memory(0x03000100, 0x08000021);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.pc, 0x08000020);
+        assert_eq!(cpu.r0, 0x03000140);
+        assert!(cpu.thumb);
+    }
+
+    #[test]
+    fn test_execute_alu() {
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x08000000;
+        cpu.r0 = 0xFFFFFFFF;
+        let alu = Alu {
+            condition: Condition::Al,
+            immediate: true,
+            op: AluOp::Add,
+            operand: 1,
+            s: true,
+            rn: Register::R0,
+            rd: Register::R1,
+        };
+        alu.execute(&mut cpu).unwrap();
+        assert_eq!(cpu.r1, 0);
+        assert_eq!(cpu.flags(), "-ZC-");
+        assert_eq!(cpu.pc, 0x08000004);
+
+        // same through the instruction enum, without the S bit
+        Instruction::Alu(Alu { s: false, ..alu })
+            .execute(&mut cpu)
+            .unwrap();
+        assert_eq!(cpu.pc, 0x08000008);
+
+        let mov = ThumbInstr::try_from(0x2107).unwrap(); // mov r1, #7
+        mov.execute(&mut cpu).unwrap();
+        assert_eq!(cpu.r1, 7);
+        assert_eq!(cpu.pc, 0x0800000A);
+    }
+
+    #[test]
+    fn test_thumb_unimplemented_errors() {
+        let rom = interworking_rom(&[(
+            0x00,
+            thumb(&[
+                0x0848, // lsr r0, r1, #1
+                0x1C48, // add r0, r1, #1
+                0x1E48, // sub r0, r1, #1
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.thumb = true;
+        for (pc, op) in [
+            (0x08000000, "ThumbRegShiftOp::Lsr"),
+            (0x08000002, "ThumbAddSub::Addi"),
+            (0x08000004, "ThumbAddSub::Subi"),
+        ] {
+            cpu.pc = pc;
+            assert_eq!(
+                cpu.execute_next(),
+                Err(ExecErr::UnimplementedInstr(format!("{op} not implemented")))
+            );
+            // the failed instruction isn't skipped
+            assert_eq!(cpu.pc, pc);
+        }
+    }
+
+    #[test]
+    fn test_unaligned_pc() {
+        let rom = interworking_rom(&[(0x00, arm(&[0xE1A00000, 0xE1A00000]))]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+
+        for pc in [0x08000001, 0x08000002, 0x08000003] {
+            cpu.pc = pc;
+            assert_eq!(cpu.execute_next(), Err(ExecErr::UnalignedPc(pc)));
+            assert_eq!(cpu.pc, pc);
+        }
+
+        // Thumb only needs halfword alignment
+        cpu.thumb = true;
+        cpu.pc = 0x08000001;
+        assert_eq!(cpu.execute_next(), Err(ExecErr::UnalignedPc(0x08000001)));
+        cpu.pc = 0x08000002;
+        assert!(cpu.execute_next().is_ok());
+    }
+
+    #[test]
+    fn test_thumb_sub_flags() {
+        let rom = interworking_rom(&[(
+            0x00,
+            thumb(&[
+                0x3801, // sub r0, #1
+                0x2800, // cmp r0, #0
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.thumb = true;
+
+        cpu.r0 = 0;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0xFFFFFFFF);
+        assert!(cpu.sign_flag && !cpu.zero_flag && !cpu.carry_flag && !cpu.overflow_flag);
+
+        cpu.execute_next().unwrap();
+        assert!(cpu.sign_flag && !cpu.zero_flag && cpu.carry_flag && !cpu.overflow_flag);
+    }
+
+    #[test]
+    fn test_thumb_asr_sign_propagation() {
+        let rom = interworking_rom(&[(
+            0x00,
+            thumb(&[
+                0x1048, // asr r0, r1, #1
+                0x1108, // asr r0, r1, #4
+                0x1008, // asr r0, r1, #32
+            ]),
+        )]);
+        let run = |r1: u32, instr: u32| {
+            let mut cpu = Cpu::new();
+            cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+            cpu.thumb = true;
+            cpu.r1 = r1;
+            cpu.pc = 0x08000000 + instr * 2;
+            cpu.execute_next().unwrap();
+            cpu
+        };
+
+        let cpu = run(0x80000011, 0);
+        assert_eq!(cpu.r0, 0xC0000008);
+        assert_eq!(cpu.flags(), "N-C-");
+        let cpu = run(0x80000018, 1);
+        assert_eq!(cpu.r0, 0xF8000001);
+        assert_eq!(cpu.flags(), "N-C-");
+        let cpu = run(0x80000010, 1);
+        assert_eq!(cpu.r0, 0xF8000001);
+        assert_eq!(cpu.flags(), "N---");
+
+        // #0 shifts by 32, the result and C are the sign bit
+        let cpu = run(0x80000000, 2);
+        assert_eq!(cpu.r0, 0xFFFFFFFF);
+        assert_eq!(cpu.flags(), "N-C-");
+        let cpu = run(0x7FFFFFFF, 2);
+        assert_eq!(cpu.r0, 0);
+        assert_eq!(cpu.flags(), "-Z--");
+    }
+
+    #[test]
+    fn test_thumb_neg_ror_mul() {
+        let rom = interworking_rom(&[(
+            0x00,
+            thumb(&[
+                0x4248, // neg r0, r1
+                0x41D0, // ror r0, r2
+                0x4358, // mul r0, r3
+            ]),
+        )]);
+        let run = |r0: u32, r1: u32, r2: u32, r3: u32, instr: u32| {
+            let mut cpu = Cpu::new();
+            cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+            cpu.thumb = true;
+            cpu.carry_flag = true;
+            (cpu.r0, cpu.r1, cpu.r2, cpu.r3) = (r0, r1, r2, r3);
+            cpu.pc = 0x08000000 + instr * 2;
+            cpu.execute_next().unwrap();
+            cpu
+        };
+
+        // negating INT_MIN overflows back to itself
+        let cpu = run(0, 0x80000000, 0, 0, 0);
+        assert_eq!(cpu.r0, 0x80000000);
+        assert_eq!(cpu.flags(), "N--V");
+        let cpu = run(0, 0, 0, 0, 0);
+        assert_eq!(cpu.r0, 0);
+        assert_eq!(cpu.flags(), "-ZC-");
+        let cpu = run(0, 5, 0, 0, 0);
+        assert_eq!(cpu.r0, -5_i32 as u32);
+        assert_eq!(cpu.flags(), "N---");
+
+        // rotating by a multiple of 32 keeps the value, C is bit 31
+        let cpu = run(0x80000001, 0, 64, 0, 1);
+        assert_eq!(cpu.r0, 0x80000001);
+        assert_eq!(cpu.flags(), "N-C-");
+        // only the lowest byte is used, 0x100 doesn't rotate or change C
+        let cpu = run(0x00000001, 0, 0x100, 0, 1);
+        assert_eq!(cpu.r0, 1);
+        assert_eq!(cpu.flags(), "--C-");
+        let cpu = run(0x00000003, 0, 1, 0, 1);
+        assert_eq!(cpu.r0, 0x80000001);
+        assert_eq!(cpu.flags(), "N-C-");
+
+        // multiplying large values keeps the lowest 32 bits
+        let cpu = run(0x12345678, 0, 0, 0x9ABCDEF0, 2);
+        assert_eq!(cpu.r0, 0x12345678_u32.wrapping_mul(0x9ABCDEF0));
+        assert_eq!(cpu.r0, 0x242D2080);
+        assert_eq!(cpu.flags(), "----");
+        let cpu = run(0x10000, 0, 0, 0x10000, 2);
+        assert_eq!(cpu.r0, 0);
+        assert_eq!(cpu.flags(), "-Z--");
+    }
+
+    #[test]
+    fn test_undefined_exception() {
+        let rom = interworking_rom(&[
+            (0x00, arm(&[0xE6000010])), // undefined
+            (0x10, thumb(&[0xDE00])),   // undefined
+            (0x20, arm(&[0xE1B0F00E])), // movs pc, lr
+            (0x30, arm(&[0xE8BD0003])), // ldmia sp!, {r0, r1}
+            (0x40, thumb(&[0xDBFE])),   // blt .
+        ]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        assert_eq!(cpu.execute_next(), Err(ExecErr::Undefined(0xE6000010)));
+        assert_eq!(cpu.pc, 0x08000000);
+
+        cpu.set_undefined_exceptions(true);
+        cpu.zero_flag = true;
+        let step = cpu.step().unwrap();
+        assert_eq!(step.instr, DecodedInstr::Undefined { thumb: false });
+        assert_eq!(cpu.pc, UNDEFINED_VECTOR);
+        assert_eq!(cpu.lr, 0x08000004);
+        assert_eq!(cpu.mode(), ProcessorMode::Undefined);
+        assert_eq!(cpu.cpsr() & 0x1F, 0x1B);
+        assert_eq!(cpu.spsr, 0x4000001F);
+
+        // handler returns to the Thumb code after the undefined instruction
+        cpu.set_cpsr(0x1F);
+        cpu.thumb = true;
+        cpu.pc = 0x08000010;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.pc, UNDEFINED_VECTOR);
+        assert_eq!(cpu.lr, 0x08000012);
+        assert!(!cpu.is_thumb());
+        assert_eq!(cpu.spsr, 0x3F);
+
+        cpu.pc = 0x08000020;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.pc, 0x08000012);
+        assert!(cpu.is_thumb());
+        assert_eq!(cpu.mode(), ProcessorMode::System);
+
+        // valid instructions that aren't decoded yet aren't undefined
+        cpu.thumb = false;
+        cpu.pc = 0x08000030;
+        assert_eq!(cpu.execute_next(), Err(ExecErr::UnknownInstr(0xE8BD0003)));
+        cpu.thumb = true;
+        cpu.pc = 0x08000040;
+        assert_eq!(cpu.execute_next(), Err(ExecErr::UnknownThumbInstr(0xDBFE)));
+        assert_eq!(cpu.pc, 0x08000040);
+        assert_eq!(cpu.mode(), ProcessorMode::System);
+    }
+
+    #[test]
+    fn test_unconditional_instructions() {
+        // NV condition isn't a branch that's never taken but BLX
+        assert!(matches!(
+            Instruction::try_from(0xFA000001),
+            Ok(Instruction::BranchLinkExchange(_))
+        ));
+        assert!(matches!(
+            Instruction::try_from(0xF5D1F000),
+            Ok(Instruction::Pld(_))
+        ));
+        for word in [0xF3A00005, 0xFF000000] {
+            assert_eq!(Instruction::try_from(word), Err(ExecErr::Undefined(word)));
+        }
+
+        let rom = interworking_rom(&[(0x00, arm(&[0xF5D1F000]))]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        let before = cpu.register_values();
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.pc, 0x08000004);
+        assert_eq!(cpu.register_values()[..15], before[..15]);
+    }
+
+    #[test]
+    fn test_subs_pc_returns_from_exception() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE2410001, // sub r0, r1, #1
+                0xE25EF004, // subs pc, lr, #4
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r1 = 10;
+        cpu.carry_flag = true;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 9);
+        // flags are kept without the S bit
+        assert_eq!(cpu.flags(), "--C-");
+        assert_eq!(cpu.pc, 0x08000004);
+
+        // zero and overflow flags were set before the exception
+        cpu.spsr = 0x5000001F;
+        cpu.lr = 0x08000104;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.pc, 0x08000100);
+        assert_eq!(cpu.flags(), "-Z-V");
+        assert!(!cpu.is_thumb());
+
+        // exception from Thumb code
+        cpu.spsr = 0x8000003F;
+        cpu.lr = 0x08000106;
+        cpu.pc = 0x08000004;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.pc, 0x08000102);
+        assert_eq!(cpu.flags(), "N---");
+        assert!(cpu.is_thumb());
+    }
+
+    #[test]
+    fn test_arm_blx_immediate() {
+        let rom = interworking_rom(&[
+            (
+                0x00,
+                arm(&[
+                    0xFA000002, // blx 0x08000010
+                    0xFB000002, // blx 0x08000016, H bit set
+                ]),
+            ),
+            (0x10, thumb(&[0x2001, 0x2002, 0x2003, 0x2004])),
+        ]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.pc, 0x08000010);
+        assert_eq!(cpu.lr, 0x08000004);
+        assert!(cpu.is_thumb());
+        assert_eq!(cpu.call_depth(), 1);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 1);
+
+        cpu.set_thumb(false);
+        cpu.pc = 0x08000004;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.pc, 0x08000016);
+        assert_eq!(cpu.lr, 0x08000008);
+        assert!(cpu.is_thumb());
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 4);
+    }
+
+    #[test]
+    fn test_sdt_pre_and_post_indexing() {
+        // (instruction, accessed address, r1 after)
+        let cases = [
+            (0xE5910004, 0x03000004, 0x03000000), // ldr r0, [r1, #4]
+            (0xE5B10004, 0x03000004, 0x03000004), // ldr r0, [r1, #4]!
+            (0xE4910004, 0x03000000, 0x03000004), // ldr r0, [r1], #4
+            (0xE4B10004, 0x03000000, 0x03000004), // ldrt r0, [r1], #4
+        ];
+        for (instr, addr, base) in cases {
+            let rom = interworking_rom(&[(0x00, arm(&[instr]))]);
+            let mut cpu = Cpu::new();
+            cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+            cpu.set_memory(0x03000000, 0x11111111);
+            cpu.set_memory(0x03000004, 0x22222222);
+            cpu.r1 = 0x03000000;
+            cpu.execute_next().unwrap();
+            assert_eq!(cpu.r0, cpu.get_memory(addr), "{instr:08x}");
+            assert_eq!(cpu.r1, base, "{instr:08x}");
+            assert_eq!(cpu.pc, 0x08000004, "{instr:08x}");
+        }
+
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE4010004, // str r0, [r1], #-4
+                0xE5A10008, // str r0, [r1, #8]!
+                0xE4911004, // ldr r1, [r1], #4
+                0xE49F0004, // ldr r0, [pc], #4
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r0 = 0xCAFEBABE;
+        cpu.r1 = 0x03000010;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.get_memory(0x03000010), 0xCAFEBABE);
+        assert_eq!(cpu.r1, 0x0300000C);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.get_memory(0x03000014), 0xCAFEBABE);
+        assert_eq!(cpu.r1, 0x03000014);
+
+        // loaded value wins over the write back
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r1, 0xCAFEBABE);
+
+        // PC base reads as PC+8 and isn't written back
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, cpu.get_memory(0x08000014));
+        assert_eq!(cpu.pc, 0x08000010);
+    }
+
+    #[test]
+    fn test_read_operand_register_pc() {
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x08000100;
+        cpu.r3 = 5;
+        assert_eq!(
+            cpu.read_operand_register(Register::R15, false),
+            Ok(0x08000108)
+        );
+        assert_eq!(
+            cpu.read_operand_register(Register::R15, true),
+            Ok(0x0800010C)
+        );
+        assert_eq!(cpu.read_operand_register(Register::R3, true), Ok(5));
+    }
+
+    #[test]
+    fn test_alu_pc_operand() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE08F000F, // add r0, pc, pc
+                0xE1A0110F, // mov r1, pc, lsl #2
+                0xE1A02F2F, // mov r2, pc, lsr #30
+                0xE1A0301F, // mov r3, pc, lsl r0
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0x10000010);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r1, 0x0800000C << 2);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r2, 0);
+        // shift by r0 & 0xFF = 16, pc reads as pc+12
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r3, 0x08000018 << 16);
+    }
+
+    #[test]
+    fn test_alu_shift_by_register() {
+        let rom = interworking_rom(&[(0x00, arm(&[0xE1B00211; 4]))]); // movs r0, r1, lsl r2
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r1 = 0x80000001;
+
+        // shift by 0 keeps the carry
+        cpu.carry_flag = true;
+        cpu.r2 = 0;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0x80000001);
+        assert!(cpu.carry_flag && cpu.sign_flag);
+
+        cpu.r2 = 1;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 2);
+        assert!(cpu.carry_flag && !cpu.sign_flag);
+
+        cpu.r2 = 32;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0);
+        assert!(cpu.carry_flag && cpu.zero_flag);
+
+        cpu.r2 = 40;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0);
+        assert!(!cpu.carry_flag);
+    }
+
+    #[test]
+    fn test_sdt_byte_and_unaligned_word() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE5812000, // str r2, [r1]
+                0xE5D13000, // ldrb r3, [r1]
+                0xE5C14005, // strb r4, [r1, #5]
+                0xE5915004, // ldr r5, [r1, #4]
+                0xE5916001, // ldr r6, [r1, #1]
+                0xE5817006, // str r7, [r1, #6]
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r1 = 0x03000000;
+        cpu.r2 = 0x112233FF;
+        cpu.r4 = 0x1AB;
+        cpu.r7 = 0xCAFEBABE;
+        for _ in 0..6 {
+            cpu.execute_next().unwrap();
+        }
+
+        assert_eq!(cpu.r3, 0xFF);
+        assert_eq!(cpu.r5, 0x0000AB00);
+        assert_eq!(cpu.r6, 0xFF112233);
+        // unaligned stores are force aligned
+        assert_eq!(cpu.get_memory(0x03000004), 0xCAFEBABE);
+    }
+
+    #[test]
+    fn test_failed_conditions() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE0501000, // subs r1, r0, r0
+                0x13A02007, // movne r2, #7
+                0x15943000, // ldrne r3, [r4]
+                0x15845000, // strne r5, [r4]
+                0x11046096, // swpne r6, r6, [r4]
+                0x1F060000, // swine 0x06
+                0x03A02009, // moveq r2, #9
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r0 = 5;
+        cpu.r4 = 0x03000000;
+        cpu.r5 = 0xAB;
+        cpu.r6 = 0xCD;
+        cpu.set_memory(0x03000000, 0x1234);
+        for _ in 0..6 {
+            cpu.execute_next().unwrap();
+        }
+        assert!(cpu.zero_flag);
+        assert_eq!(cpu.pc, 0x08000018);
+        assert_eq!((cpu.r0, cpu.r1, cpu.r2, cpu.r3), (5, 0, 0, 0));
+        assert_eq!(cpu.r6, 0xCD);
+        assert_eq!(cpu.get_memory(0x03000000), 0x1234);
+
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r2, 9);
+    }
+
+    #[test]
+    fn test_pc_operands() {
+        let rom = interworking_rom(&[
+            (
+                0x00,
+                arm(&[
+                    0xE791000F, // ldr r0, [r1, pc]
+                    0xE581F008, // str pc, [r1, #8]
+                ]),
+            ),
+            (0x10, thumb(&[0x4678])), // mov r0, pc
+        ]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r1 = 0x03000000u32.wrapping_sub(0x08000008);
+        cpu.set_memory(0x03000000, 0x1234);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0x1234);
+
+        cpu.r1 = 0x03000000;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.get_memory(0x03000008), 0x08000004 + 12);
+
+        cpu.set_thumb(true);
+        cpu.pc = 0x08000010;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0x08000014);
+    }
+
+    #[test]
+    fn test_pc_wraps_at_end_of_address_space() {
+        let rom = interworking_rom(&[(0x00, arm(&[0xE3A00005]))]); // mov r0, #5
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.execute_next().unwrap();
+
+        // open bus still has the last fetched mov
+        cpu.pc = 0xFFFFFFFC;
+        cpu.r0 = 0;
+        let step = cpu.step().unwrap();
+        assert_eq!(step.next_pc, 0);
+        assert_eq!(cpu.pc, 0);
+        assert_eq!(cpu.r0, 5);
+
+        let rom = interworking_rom(&[(0x00, thumb(&[0x2005]))]); // mov r0, #5
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.set_thumb(true);
+        cpu.execute_next().unwrap();
+
+        cpu.pc = 0xFFFFFFFE;
+        cpu.r0 = 0;
+        let step = cpu.step().unwrap();
+        assert_eq!(step.next_pc, 0);
+        assert_eq!(cpu.pc, 0);
+        assert_eq!(cpu.r0, 5);
+    }
+
+    #[test]
+    fn test_alu_pc_rn_shift_by_register() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE08F0211, // add r0, pc, r1, lsl r2
+                0xE08F0101, // add r0, pc, r1, lsl #2
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r1 = 1;
+        cpu.r2 = 2;
+
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0x08000000 + 12 + 4);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0x08000004 + 8 + 4);
+    }
+
+    #[test]
+    fn test_alu_rrx() {
+        let rom = interworking_rom(&[(0x00, arm(&[0xE1B00061; 2]))]); // movs r0, r1, rrx
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+
+        cpu.carry_flag = true;
+        cpu.r1 = 0x00000003;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0x80000001);
+        assert!(cpu.carry_flag && cpu.sign_flag);
+
+        cpu.carry_flag = false;
+        cpu.r1 = 0x00000002;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0x00000001);
+        assert!(!cpu.carry_flag && !cpu.sign_flag);
+    }
+
+    #[test]
+    fn test_sdt_address_wraps_and_offset_down() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE5912008, // ldr r2, [r1, #0x8]
+                0xE5113004, // ldr r3, [r1, #-0x4]
+                0xE5014008, // str r4, [r1, #-0x8]
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.set_memory(0x03000004, 0x11111111);
+        cpu.set_memory(0x02FFFFFC, 0x22222222);
+
+        // 0xF2FFFFFC + 8 wraps into the 28 bit address space
+        cpu.r1 = 0xF2FFFFFC;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r2, 0x11111111);
+
+        cpu.r1 = 0x03000000;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r3, 0x22222222);
+
+        // stores the source register, not r0
+        cpu.r0 = 0;
+        cpu.r4 = 0xCAFEBABE;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.get_memory(0x02FFFFF8), 0xCAFEBABE);
+    }
+
+    #[test]
+    fn test_sdt_rrx_offset() {
+        let rom = interworking_rom(&[(0x00, arm(&[0xE7910062]))]); // ldr r0, [r1, r2, rrx]
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.set_memory(0x03000004, 0xCAFEBABE);
+
+        cpu.carry_flag = false;
+        cpu.r1 = 0x03000000;
+        cpu.r2 = 9;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0xCAFEBABE);
+        // the shifter carry isn't written to the flags
+        assert!(!cpu.carry_flag);
+    }
+
+    #[test]
+    fn test_barrel_shift_edge_cases() {
+        assert_eq!(barrel_shift(0x80000000, 1, 32, false), (0, true));
+        assert_eq!(barrel_shift(0x80000000, 1, 33, true), (0, false));
+        assert_eq!(barrel_shift(0x80000000, 2, 40, false), (0xFFFFFFFF, true));
+        assert_eq!(barrel_shift(0x80000001, 3, 32, false), (0x80000001, true));
+        assert_eq!(barrel_shift(0x00000003, 3, 1, false), (0x80000001, true));
+    }
+
+    #[test]
+    fn test_swi_div() {
+        let mut rom = rom_bytes(0x200);
+        // swi 0x060000
+        rom[0..4].copy_from_slice(&0xEF060000_u32.to_le_bytes());
+        // swi 0x070000
+        rom[4..8].copy_from_slice(&0xEF070000_u32.to_le_bytes());
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r0 = -7_i32 as u32;
+        cpu.r1 = 2;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, -3_i32 as u32);
+        assert_eq!(cpu.r1, -1_i32 as u32);
+        assert_eq!(cpu.r3, 3);
+        assert_eq!(cpu.pc, 0x08000004);
+
+        cpu.r0 = 3;
+        cpu.r1 = 10;
+        cpu.execute_next().unwrap();
+        assert_eq!((cpu.r0, cpu.r1, cpu.r3), (3, 1, 3));
+    }
+
+    #[test]
+    fn test_swi_cpu_set() {
+        let mut rom = rom_bytes(0x200);
+        // swi 0x0B0000
+        rom[0..4].copy_from_slice(&0xEF0B0000_u32.to_le_bytes());
+        rom[4..8].copy_from_slice(&0xEF0B0000_u32.to_le_bytes());
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        for (idx, value) in [0x11111111, 0x22222222, 0x33333333].iter().enumerate() {
+            cpu.set_memory(0x02000000 + idx as u32 * 4, *value);
+        }
+
+        // copy 2 words
+        cpu.r0 = 0x02000000;
+        cpu.r1 = 0x03000000;
+        cpu.r2 = (1 << 26) | 2;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.get_memory(0x03000000), 0x11111111);
+        assert_eq!(cpu.get_memory(0x03000004), 0x22222222);
+        assert_eq!(cpu.get_memory(0x03000008), 0);
+
+        // fill 3 halfwords
+        cpu.set_memory_u16(0x02000100, 0xABCD);
+        cpu.r0 = 0x02000100;
+        cpu.r1 = 0x03000100;
+        cpu.r2 = (1 << 24) | 3;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.get_memory(0x03000100), 0xABCDABCD);
+        assert_eq!(cpu.get_memory_u16(0x03000104), 0xABCD);
+        assert_eq!(cpu.get_memory_u16(0x03000106), 0);
+    }
+
+    #[test]
+    fn test_fill_memory_matches_set_memory() {
+        // (start, words): Mode 3 framebuffer, unaligned, across the end of OAM into ROM,
+        // across the end of SRAM into unmapped memory
+        let fills = [
+            (0x06000000, 240 * 160 / 2),
+            (0x03000002, 5),
+            (0x07FFFFF6, 6),
+            (0x0FFFFFF8, 4),
+        ];
+        for (start, words) in fills {
+            let rom = rom_bytes(0x200);
+            let mut fast = Cpu::new();
+            fast.initialize_cpu(&rom, BootConfig::default()).unwrap();
+            let mut slow = Cpu::new();
+            slow.initialize_cpu(&rom, BootConfig::default()).unwrap();
+
+            fast.fill_memory(start, 0x7FFF1234, words);
+            for idx in 0..words as u32 {
+                slow.set_memory(start.wrapping_add(idx * 4), 0x7FFF1234);
+            }
+
+            let begin = start.saturating_sub(8);
+            let end = begin + words as u32 * 4 + 16;
+            for addr in begin..end {
+                assert_eq!(fast.read_byte(addr), slow.read_byte(addr), "{addr:08x}");
+            }
+            assert_eq!(fast.bus.rom(), slow.bus.rom(), "{start:08x}");
+        }
+    }
+
+    #[test]
+    fn test_swi_cpu_fast_set() {
+        let mut rom = rom_bytes(0x200);
+        // swi 0x0C0000
+        rom[0..4].copy_from_slice(&0xEF0C0000_u32.to_le_bytes());
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        for idx in 0..9 {
+            cpu.set_memory(0x02000000 + idx * 4, idx + 1);
+        }
+
+        // 3 words rounds up to a full block of 8
+        cpu.r0 = 0x02000000;
+        cpu.r1 = 0x03000000;
+        cpu.r2 = 3;
+        cpu.execute_next().unwrap();
+        for idx in 0..8 {
+            assert_eq!(cpu.get_memory(0x03000000 + idx * 4), idx + 1);
+        }
+        assert_eq!(cpu.get_memory(0x03000020), 0);
+    }
+
+    #[test]
+    fn test_thumb_swi_div() {
+        let mut rom = rom_bytes(0x200);
+        // swi 0x06
+        rom[0..2].copy_from_slice(&0xDF06_u16.to_le_bytes());
+        // swi 0x05
+        rom[2..4].copy_from_slice(&0xDF05_u16.to_le_bytes());
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.thumb = true;
+        cpu.r0 = 9;
+        cpu.r1 = 0;
+        cpu.execute_next().unwrap();
+        assert_eq!((cpu.r0, cpu.r1, cpu.r3), (1, 9, 1));
+        assert_eq!(cpu.pc, 0x08000002);
+
+        assert_eq!(
+            cpu.execute_next(),
+            Err(ExecErr::UnimplementedInstr("SWI 05 not implemented".into()))
+        );
+    }
+
+    #[test]
+    fn test_dispstat_vblank() {
+        let mut cpu = Cpu::new();
+        // VBlank IRQ enabled
+        cpu.set_memory_u16(DISPSTAT, DISPSTAT_VBLANK_IRQ);
+
+        cpu.advance_cycles(LINE_CYCLES * 160 - 1);
+        assert_eq!(cpu.get_memory_u16(DISPSTAT) & DISPSTAT_VBLANK, 0);
+        assert_eq!(cpu.get_memory_u16(IF) & IRQ_VBLANK, 0);
+
+        cpu.advance_cycles(1);
+        assert_ne!(cpu.get_memory_u16(DISPSTAT) & DISPSTAT_VBLANK, 0);
+        assert_eq!(cpu.get_memory_u16(IF), IRQ_VBLANK);
+
+        cpu.advance_cycles(LINE_CYCLES * 68 - 1);
+        assert_ne!(cpu.get_memory_u16(DISPSTAT) & DISPSTAT_VBLANK, 0);
+
+        // the next frame starts
+        cpu.advance_cycles(1);
+        assert_eq!(cpu.get_memory_u16(DISPSTAT) & DISPSTAT_VBLANK, 0);
+        assert_eq!(cpu.cycles, LINE_CYCLES * 228);
+    }
+
+    #[test]
+    fn test_dispstat_hblank_and_vcount() {
+        let mut cpu = Cpu::new();
+        // match line 2, HBlank and VCount IRQs enabled
+        cpu.set_memory_u16(
+            DISPSTAT,
+            (2 << 8) | DISPSTAT_HBLANK_IRQ | DISPSTAT_VCOUNT_IRQ,
+        );
+
+        cpu.advance_cycles(HDRAW_CYCLES);
+        assert_ne!(cpu.get_memory_u16(DISPSTAT) & DISPSTAT_HBLANK, 0);
+        assert_eq!(cpu.get_memory_u16(IF), IRQ_HBLANK);
+
+        cpu.advance_cycles(LINE_CYCLES - HDRAW_CYCLES);
+        assert_eq!(cpu.get_memory_u16(DISPSTAT) & DISPSTAT_HBLANK, 0);
+        assert_eq!(cpu.get_memory_u16(DISPSTAT) & DISPSTAT_VCOUNT, 0);
+
+        cpu.set_memory_u16(IF, 0);
+        cpu.advance_cycles(LINE_CYCLES);
+        assert_ne!(cpu.get_memory_u16(DISPSTAT) & DISPSTAT_VCOUNT, 0);
+        assert_eq!(cpu.get_memory_u16(IF), IRQ_VCOUNT);
+    }
+
+    #[test]
+    fn test_instr_cycles() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE3A02403, // mov r2, #0x03000000
+                0xE5821000, // str r1, [r2]
+                0xE5921000, // ldr r1, [r2]
+                0xE1A01311, // mov r1, r1, lsl r3
+                0x1A000000, // bne 0x08000018
+                0xEA000000, // b 0x0800001C
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.zero_flag = true;
+        let mut cycles = Vec::new();
+        for _ in 0..6 {
+            let before = cpu.cycles();
+            cpu.execute_next().unwrap();
+            cycles.push(cpu.cycles() - before);
+        }
+        // the not taken branch takes a cycle and the taken branch refills the pipeline
+        assert_eq!(cycles, [1, 2, 3, 2, 1, 3]);
+    }
+
+    #[test]
+    fn test_step_cycles() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE3A02403, // mov r2, #0x03000000
+                0xE2800001, // loop: add r0, r0, #1
+                0xE5921000, // ldr r1, [r2]
+                0xEAFFFFFC, // b loop
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        let cycles = cpu.step_cycles(1000).unwrap();
+        // the last instruction can take at most 3 cycles over the budget
+        assert!((1000..1003).contains(&cycles), "{cycles}");
+        assert_eq!(cpu.cycles(), cycles);
+        // 7 cycles per iteration after the first mov
+        assert!((cycles - 1) / 7 <= cpu.r0 as u64 && cpu.r0 as u64 <= (cycles - 1) / 7 + 1);
+
+        let cycles = cpu.step_cycles(10).unwrap();
+        assert!((10..13).contains(&cycles));
+        assert_eq!(cpu.step_cycles(0).unwrap(), 0);
+
+        // stops when the cpu halts
+        let rom = interworking_rom(&[(0x00, arm(&[0xEAFFFFFE]))]);
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        let before = cpu.cycles();
+        let executed = cpu.stats().instructions;
+        let cycles = cpu.step_cycles(1000).unwrap();
+        assert!(cpu.is_halted());
+        // time and the LCD keep going while halted
+        assert!((1000..1003).contains(&cycles), "{cycles}");
+        assert_eq!(cpu.cycles() - before, cycles);
+        // without executing the branch again
+        assert_eq!(cpu.stats().instructions, executed + 1);
+
+        cpu.set_memory_u16(DISPSTAT, DISPSTAT_VBLANK_IRQ);
+        cpu.run_frame().unwrap();
+        assert!(cpu.cycles() % FRAME_CYCLES < HALTED_CYCLES);
+        assert_ne!(cpu.get_memory_u16(IF) & IRQ_VBLANK, 0);
+    }
+
+    #[test]
+    fn test_stats() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE3A00005, // mov r0, #5
+                0xEAFFFFFE, // b .
+            ]),
+        )]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.stats().instructions, 2);
+        assert_eq!(cpu.stats().frames, 1);
+
+        cpu.add_run_time(Duration::from_secs(1));
+        assert!(cpu.stats().elapsed >= Duration::from_secs(1));
+
+        cpu.reset().unwrap();
+        assert_eq!(*cpu.stats(), Stats::default());
+    }
+
+    #[test]
+    fn test_keypad_irq() {
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom_bytes(0x200), BootConfig::default())
+            .unwrap();
+        assert_eq!(cpu.get_memory_u16(KEYINPUT), 0x03FF);
+
+        // IRQ when both A and Start are pressed
+        cpu.set_memory_u16(KEYCNT, 0xC000 | Key::A.bit() | Key::Start.bit());
+        cpu.set_key(Key::A, true);
+        assert_eq!(cpu.get_memory_u16(KEYINPUT), 0x03FE);
+        assert_eq!(cpu.get_memory_u16(IF), 0);
+        cpu.set_key(Key::B, true);
+        assert_eq!(cpu.get_memory_u16(IF), 0);
+        cpu.set_key(Key::Start, true);
+        assert_eq!(cpu.get_memory_u16(KEYINPUT), 0x03F4);
+        assert_eq!(cpu.get_memory_u16(IF), IRQ_KEYPAD);
+
+        cpu.set_memory_u16(IF, 0);
+        cpu.set_key(Key::A, false);
+        cpu.set_key(Key::B, false);
+        assert_eq!(cpu.get_memory_u16(IF), 0);
+        assert_eq!(cpu.get_memory_u16(KEYINPUT), 0x03F7);
+    }
+
+    #[test]
+    fn test_vcount() {
+        let mut cpu = Cpu::new();
+        cpu.advance_cycles(LINE_CYCLES - 1);
+        assert_eq!(cpu.get_memory_u16(VCOUNT), 0);
+        cpu.advance_cycles(1);
+        assert_eq!(cpu.get_memory_u16(VCOUNT), 1);
+
+        // writes are overwritten by the next update
+        cpu.set_memory_u16(VCOUNT, 100);
+        cpu.advance_cycles(LINE_CYCLES * 226);
+        assert_eq!(cpu.get_memory_u16(VCOUNT), 227);
+
+        cpu.advance_cycles(LINE_CYCLES);
+        assert_eq!(cpu.get_memory_u16(VCOUNT), 0);
+    }
+
+    #[test]
+    fn test_memory_u16() {
+        let mut cpu = Cpu::new();
+        cpu.set_memory_u16(0x03000000, 0x1234);
+        cpu.set_memory_u16(0x03000002, 0xABCD);
+        assert_eq!(cpu.get_memory(0x03000000), 0xABCD1234);
+        assert_eq!(cpu.get_memory_u16(0x03000000), 0x1234);
+        assert_eq!(cpu.get_memory_u16(0x03000002), 0xABCD);
+        assert_eq!(cpu.get_memory_u16(0x03000001), 0xCD12);
+
+        cpu.set_memory(0x03000004, 0xDEADBEEF);
+        assert_eq!(cpu.get_memory_u16(0x03000004), 0xBEEF);
+        assert_eq!(cpu.get_memory_u16(0x03000006), 0xDEAD);
+    }
+
+    #[test]
+    fn test_memory_out_of_bounds() {
+        let mut cpu = Cpu::new();
+        cpu.set_memory_u16(0xFFFFFFFE, 0x1234);
+        assert_eq!(cpu.get_memory_u16(0xFFFFFFFE), 0);
+
+        // wraps to the start of the address space
+        cpu.set_memory(0xFFFFFFFE, 0x12345678);
+        assert_eq!(cpu.get_memory(0x00000000), 0x1234);
+        assert_eq!(cpu.get_memory(0xFFFFFFFE), 0x12340000);
+    }
+
+    #[test]
+    fn test_memory_u8() {
+        let mut cpu = Cpu::new();
+        cpu.set_memory(0x02000000, 0x44332211);
+        assert_eq!(cpu.get_memory_u8(0x02000000), 0x11);
+        assert_eq!(cpu.get_memory_u8(0x02000003), 0x44);
+
+        cpu.set_memory_u8(0x02000001, 0xAA);
+        cpu.set_memory_u8(0x02000003, 0xBB);
+        assert_eq!(cpu.get_memory(0x02000000), 0xBB33AA11);
+        assert_eq!(cpu.get_memory_u16(0x02000002), 0xBB33);
+
+        // ROM is read only and out of bounds writes are ignored
+        let mut rom = rom_bytes(0x200);
+        rom[0] = 0x12;
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.set_memory_u8(0x08000000, 0xFF);
+        cpu.set_memory_u8(0xFFFFFFFF, 0xFF);
+        assert_eq!(cpu.get_memory_u8(0x08000000), 0x12);
+        assert_eq!(cpu.get_memory_u8(0xFFFFFFFF), 0);
+    }
+
+    #[test]
+    fn test_interworking_round_trip() {
+        let rom = interworking_rom(&[
+            (
+                0x00,
+                arm(&[
+                    0xE28F0005, // add r0, pc, #5
+                    0xE12FFF10, // bx r0
+                ]),
+            ),
+            (
+                0x0C,
+                thumb(&[
+                    0x2105, // mov r1, #5
+                    0x4710, // bx r2
+                ]),
+            ),
+            (
+                0x20,
+                arm(&[
+                    0xE3A03007, // mov r3, #7
+                    0xE12FFF14, // bx r4
+                ]),
+            ),
+            (
+                0x40,
+                thumb(&[
+                    0x2609, // mov r6, #9
+                ]),
+            ),
+        ]);
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r2 = 0x08000020;
+        cpu.r4 = 0x08000041;
+
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0x0800000D);
+
+        // ARM -> Thumb
+        cpu.execute_next().unwrap();
+        assert!(cpu.thumb);
+        assert_eq!(cpu.pc, 0x0800000C);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r1, 5);
+        assert_eq!(cpu.pc, 0x0800000E);
+
+        // Thumb -> ARM
+        cpu.execute_next().unwrap();
+        assert!(!cpu.thumb);
+        assert_eq!(cpu.pc, 0x08000020);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r3, 7);
+        assert_eq!(cpu.pc, 0x08000024);
+
+        // ARM -> Thumb again
+        cpu.execute_next().unwrap();
+        assert!(cpu.thumb);
+        assert_eq!(cpu.pc, 0x08000040);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r6, 9);
+        assert_eq!(cpu.cpsr() & 0x20, 0x20);
+    }
+
+    #[test]
+    fn test_bx_even_target_stays_in_arm() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE12FFF10, // bx r0
+                0x0, 0x0, 0x0, 0xE3A01005, // mov r1, #5
+            ]),
+        )]);
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r0 = 0x08000010;
+        cpu.execute_next().unwrap();
+        assert!(!cpu.thumb);
+        assert_eq!(cpu.pc, 0x08000010);
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r1, 5);
+    }
+
+    #[test]
+    fn test_bx_condition() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0x012FFF10, // bxeq r0
+                0x112FFF10, // bxne r0
+            ]),
+        )]);
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r0 = 0x08000021;
+        cpu.zero_flag = false;
+
+        // not taken
+        cpu.execute_next().unwrap();
+        assert!(!cpu.thumb);
+        assert_eq!(cpu.pc, 0x08000004);
+        assert_eq!(cpu.call_depth(), 0);
+
+        cpu.execute_next().unwrap();
+        assert!(cpu.thumb);
+        assert_eq!(cpu.pc, 0x08000020);
+    }
+
+    #[test]
+    fn test_set_pc_with_mode_alignment() {
+        let mut cpu = Cpu::new();
+        cpu.set_pc_with_mode(0x08000023);
+        assert!(cpu.thumb);
+        assert_eq!(cpu.pc, 0x08000022);
+
+        cpu.set_pc_with_mode(0x08000022);
+        assert!(!cpu.thumb);
+        assert_eq!(cpu.pc, 0x08000020);
+    }
+
+    #[test]
+    fn test_load_large_rom() {
+        let size = 24 * 1024 * 1024;
+        let mut bytes = rom_bytes(size);
+        bytes[size - 4..].copy_from_slice(&0xdeadbeef_u32.to_le_bytes());
+        bytes[size - 6..size - 4].copy_from_slice(&0x1234_u16.to_le_bytes());
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&bytes, BootConfig::default()).unwrap();
+        assert_eq!(cpu.pc, 0x08000000);
+        assert_eq!(cpu.get_memory(0x08000000 + size as u32 - 4), 0xdeadbeef);
+        assert_eq!(cpu.get_memory_u16(0x08000000 + size as u32 - 6), 0x1234);
+        assert_eq!(cpu.get_memory(0x0A000000 + size as u32 - 4), 0xdeadbeef);
+        assert_eq!(cpu.get_memory(0x0C000000 + size as u32 - 4), 0xdeadbeef);
+        // past the end of the ROM
+        assert_eq!(cpu.get_memory(0x08000000 + size as u32), 0);
+    }
+
+    #[test]
+    fn test_step_info() {
+        let rom = interworking_rom(&[
+            (0x00, arm(&[0xE3A00005])), // mov r0, #5
+            (0x10, thumb(&[0xE7FE])),   // b .
+        ]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        let step = cpu.step().unwrap();
+        assert_eq!(step.pc, 0x08000000);
+        assert_eq!(step.next_pc, 0x08000004);
+        assert_eq!(step.opcode, 0xE3A00005);
+        assert_eq!(
+            step.instr,
+            DecodedInstr::Arm(Instruction::try_from(0xE3A00005).unwrap())
+        );
+        assert!(matches!(
+            step.instr,
+            DecodedInstr::Arm(Instruction::Alu(Alu {
+                op: AluOp::Mov,
+                rd: Register::R0,
+                ..
+            }))
+        ));
+
+        cpu.set_thumb(true);
+        cpu.pc = 0x08000010;
+        let step = cpu.step().unwrap();
+        assert_eq!(
+            step,
+            StepInfo {
+                pc: 0x08000010,
+                opcode: 0xE7FE,
+                instr: DecodedInstr::Thumb(ThumbInstr::try_from(0xE7FE).unwrap()),
+                next_pc: 0x08000010,
+            }
+        );
+        assert_eq!(crate::disasm::disassemble_step(&step), "B $+0x0");
+    }
+
+    #[test]
+    fn test_peek_instruction() {
+        let rom = interworking_rom(&[
+            (0x00, arm(&[0xE3A00005])),       // mov r0, #5
+            (0x10, thumb(&[0xF000, 0xF804])), // bl
+            (0x20, arm(&[0xE6000010])),       // undefined
+        ]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.set_trace_range(Some((0x08000000, 0x08000030)));
+
+        assert_eq!(
+            cpu.peek_instruction(0x08000000, false),
+            Ok(DecodedInstr::Arm(
+                Instruction::try_from(0xE3A00005).unwrap()
+            ))
+        );
+        assert_eq!(
+            cpu.peek_instruction(0x08000010, true),
+            Ok(DecodedInstr::Thumb(
+                ThumbInstr::try_from_long(0xF000, 0xF804).unwrap()
+            ))
+        );
+        assert_eq!(
+            cpu.peek_instruction(0x08000020, false),
+            Err(ExecErr::Undefined(0xE6000010))
+        );
+        assert_eq!(cpu.pc, 0x08000000);
+        assert_eq!(cpu.r0, 0);
+        assert!(cpu.history().is_empty());
+        assert!(cpu.take_trace().is_empty());
+    }
+
+    #[test]
+    fn test_history() {
+        let rom = interworking_rom(&[
+            (
+                0x00,
+                arm(&[
+                    0xE3A00005, // mov r0, #5
+                    0xE28F1005, // add r1, pc, #5
+                    0xE12FFF11, // bx r1
+                ]),
+            ),
+            (
+                0x10,
+                thumb(&[
+                    0x2001, // mov r0, #1
+                    0xF000, 0xF800, // bl 0x08000018
+                ]),
+            ),
+        ]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        for _ in 0..5 {
+            cpu.execute_next().unwrap();
+        }
+
+        let entry = |pc, opcode, thumb| HistoryEntry { pc, opcode, thumb };
+        assert_eq!(
+            cpu.history().iter().copied().collect::<Vec<_>>(),
+            [
+                entry(0x08000000, 0xE3A00005, false),
+                entry(0x08000004, 0xE28F1005, false),
+                entry(0x08000008, 0xE12FFF11, false),
+                entry(0x08000010, 0x2001, true),
+                entry(0x08000012, 0xF800F000, true),
+            ]
+        );
+
+        // only the latest instructions are kept
+        cpu.set_thumb(false);
+        for _ in 0..HISTORY_SIZE {
+            cpu.pc = 0x08000000;
+            cpu.execute_next().unwrap();
+        }
+        assert_eq!(cpu.history().len(), HISTORY_SIZE);
+        assert!(cpu.history().iter().all(|entry| entry.pc == 0x08000000));
+    }
+
+    /// Bus that records the word accesses outside of the ROM
+    #[derive(Debug, Default)]
+    struct MockBus {
+        rom: Vec<u8>,
+        ram: HashMap<u32, u8>,
+        /// (address, value, is write), shared with the test
+        accesses: Rc<RefCell<Vec<(u32, u32, bool)>>>,
+    }
+
+    impl Bus for MockBus {
+        fn read8(&self, addr: u32) -> u8 {
+            match addr.checked_sub(0x08000000) {
+                Some(offset) => self.rom.get(offset as usize).copied().unwrap_or(0),
+                None => self.ram.get(&addr).copied().unwrap_or(0),
+            }
+        }
+
+        fn write8(&mut self, addr: u32, value: u8) {
+            self.ram.insert(addr, value);
+        }
+
+        fn read32(&self, addr: u32) -> u32 {
+            let value = u32::from_le_bytes([0, 1, 2, 3].map(|idx| self.read8(addr + idx)));
+            if addr < 0x08000000 {
+                self.accesses.borrow_mut().push((addr, value, false));
+            }
+            value
+        }
+
+        fn write32(&mut self, addr: u32, value: u32) {
+            self.accesses.borrow_mut().push((addr, value, true));
+            for (idx, byte) in value.to_le_bytes().into_iter().enumerate() {
+                self.write8(addr + idx as u32, byte);
+            }
+        }
+
+        fn load_rom(&mut self, rom: &[u8]) {
+            self.rom = rom.to_vec();
+        }
+
+        fn rom(&self) -> &[u8] {
+            &self.rom
+        }
+    }
+
+    #[test]
+    fn test_mock_bus_load_store() {
+        let rom = interworking_rom(&[(
+            0x00,
+            arm(&[
+                0xE3A01403, // mov r1, #0x3000000
+                0xE3A00005, // mov r0, #5
+                0xE5810000, // str r0, [r1]
+                0xE5912004, // ldr r2, [r1, #4]
+                0xE5913000, // ldr r3, [r1]
+            ]),
+        )]);
+        let bus = MockBus::default();
+        let accesses = bus.accesses.clone();
+        let mut cpu = Cpu::with_bus(Box::new(bus));
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        for _ in 0..5 {
+            cpu.execute_next().unwrap();
+        }
+
+        assert_eq!(
+            *accesses.borrow(),
+            [
+                (0x03000000, 5, true),
+                (0x03000004, 0, false),
+                (0x03000000, 5, false),
+            ]
+        );
+        assert_eq!(cpu.r2, 0);
+        assert_eq!(cpu.r3, 5);
+
+        // reset keeps the bus
+        cpu.reset().unwrap();
+        for _ in 0..3 {
+            cpu.execute_next().unwrap();
+        }
+        assert_eq!(accesses.borrow().len(), 4);
+        assert_eq!(accesses.borrow()[3], (0x03000000, 5, true));
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        let code = arm(&[
+            0xE3A00001, // mov r0, #1
+            0xE5812000, // str r2, [r1]
+            0xE3A04002, // mov r4, #2
+            0xE3A03001, // mov r3, #1, replaced with mov r3, #7
+        ]);
+        let rom = interworking_rom(&[]);
+        for (target, mirror) in [(0x0300000C, 0x0300000C), (0x0300000C, 0x0300800C)] {
+            let mut cpu = Cpu::new();
+            cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+            cpu.write_region(0x03000000, &code);
+            cpu.pc = 0x03000000;
+            cpu.r1 = mirror;
+            cpu.r2 = 0xE3A03007;
+            for _ in 0..4 {
+                cpu.execute_next().unwrap();
+            }
+            assert_eq!(cpu.r3, 7, "{mirror:08x}");
+            assert_eq!(cpu.get_memory(target), 0xE3A03007);
+        }
+
+        // Thumb code replacing the next halfword of the same word
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.write_region(0x02000000, &thumb(&[0x800A, 0x2301])); // strh r2, [r1]; mov r3, #1
+        cpu.set_thumb(true);
+        cpu.pc = 0x02000000;
+        cpu.r1 = 0x02000002;
+        cpu.r2 = 0x2307; // mov r3, #7
+        cpu.execute_next().unwrap();
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r3, 7);
+    }
+
+    #[test]
+    fn test_thumb_load_store_wraps_address() {
+        let rom = interworking_rom(&[(
+            0x10,
+            thumb(&[
+                0x6088, // str r0, [r1, #8]
+                0x688A, // ldr r2, [r1, #8]
+            ]),
+        )]);
+        let bus = MockBus::default();
+        let accesses = bus.accesses.clone();
+        let mut cpu = Cpu::with_bus(Box::new(bus));
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.set_thumb(true);
+        cpu.pc = 0x08000010;
+        cpu.r0 = 0xAB;
+        cpu.r1 = 0xFFFFFFFC;
+        cpu.execute_next().unwrap();
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r2, 0xAB);
+
+        // unaligned like the other word loads
+        cpu.run_thumb_mls(ThumbMls {
+            op: ThumbMlsOp::Ldr,
+            rd: Register::R3,
+            rb: Register::R1,
+            nn: 0x0A,
+        })
+        .unwrap();
+        assert_eq!(cpu.r3, 0x00AB0000);
+        assert_eq!(
+            *accesses.borrow(),
+            [(4, 0xAB, true), (4, 0xAB, false), (4, 0xAB, false)]
+        );
+    }
+
+    #[test]
+    fn test_open_bus_reads() {
+        let mut rom = rom_bytes(0x200);
+        // ldr r0, [r1]
+        rom[0..4].copy_from_slice(&0xE5910000_u32.to_le_bytes());
+
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+        cpu.r1 = 0x04000500;
+        cpu.execute_next().unwrap();
+        assert_eq!(cpu.r0, 0xE5910000);
+        assert_eq!(cpu.get_memory_u16(0x04000502), 0xE591);
+        assert_eq!(cpu.get_memory(0x01000000), 0xE5910000);
+        // mapped I/O registers are still memory
+        cpu.set_memory_u16(0x04000000, 0x0403);
+        assert_eq!(cpu.get_memory_u16(0x04000000), 0x0403);
+    }
+
+    #[test]
+    fn test_rom_too_large() {
+        let bytes = rom_bytes(ROM_MAX_SIZE as usize + 1);
+        let mut cpu = Cpu::new();
+        assert_eq!(
+            cpu.initialize_cpu(&bytes, BootConfig::default()),
+            Err(ExecErr::RomTooLarge(ROM_MAX_SIZE as usize + 1))
+        );
+    }
 }
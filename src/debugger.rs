@@ -1,85 +1,1120 @@
 use std::{
-    io::{self, BufRead, Write},
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
+    path::PathBuf,
     process::exit,
+    time::Instant,
 };
 
-use crate::{cpu::Cpu, instr::common::EResult};
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use crate::{
+    cpu::{BootConfig, Cpu, StepInfo, REGISTER_NAMES},
+    disasm::{disassemble_arm, disassemble_step, disassemble_thumb, disassemble_thumb_long},
+    gba_file::GBAHeader,
+    instr::{
+        arm::Instruction,
+        common::{EResult, ExecErr},
+        DecodedInstr,
+    },
+    logger::LogCategory,
+    mem::{classify, OAM_SIZE, OAM_START, PALETTE_SIZE, PALETTE_START, VRAM_SIZE, VRAM_START},
+};
+
+/// History file of the interactive debugger in the home directory
+const HISTORY_FILE: &str = ".cgba_emulator_history";
+
+/// Most instructions `run`, `finish` and `until` execute before returning to the prompt,
+/// enough for a few minutes of emulated time
+const DEFAULT_RUN_LIMIT: u64 = 100_000_000;
 
 pub struct Debugger {
     pub cpu: Cpu,
     on_break: bool,
     breaks: Vec<u32>,
+    /// Watched addresses and their last known values
+    watches: Vec<(u32, u32)>,
+}
+
+struct Command {
+    /// Name of the command and its aliases
+    names: &'static [&'static str],
+    /// Arguments of the command for the help text
+    args: &'static str,
+    description: &'static str,
+    /// Execute the command, gets the full command line as an argument
+    run: fn(&mut Debugger, &str) -> EResult<()>,
 }
 
+/// All the debugger commands
+const COMMANDS: &[Command] = &[
+    Command {
+        names: &["h", "help"],
+        args: "",
+        description: "Print this help",
+        run: |_, _| {
+            print!("{}", Debugger::help_text());
+            Ok(())
+        },
+    },
+    Command {
+        names: &["q", "quit", "exit"],
+        args: "",
+        description: "Stop the debugger",
+        run: |_, _| exit(0),
+    },
+    Command {
+        names: &["p", "print"],
+        args: "",
+        description: "Print the current state of the Cpu",
+        run: |debugger, _| {
+            print!("{}", debugger.snapshot());
+            Ok(())
+        },
+    },
+    Command {
+        names: &["r", "run"],
+        args: "[max|all]",
+        description: "Run until the next breakpoint or watch, or until max (default 100000000) instructions have run",
+        run: |debugger, cmd| debugger.run(cmd),
+    },
+    Command {
+        names: &["n", "next"],
+        args: "",
+        description: "Execute the current instruction",
+        run: |debugger, _| {
+            debugger.on_break = false;
+            let step = debugger.cpu.step()?;
+            print!("{}", debugger.step_message(&step));
+            debugger.check_watches();
+            Ok(())
+        },
+    },
+    Command {
+        names: &["finish"],
+        args: "",
+        description: "Run until the current subroutine returns",
+        run: |debugger, _| debugger.finish(),
+    },
+    Command {
+        names: &["until"],
+        args: "<hexaddr>",
+        description: "Run until PC reaches the address, a breakpoint or a watch",
+        run: |debugger, cmd| {
+            match Debugger::hex_arg(cmd, "until <hexaddr>") {
+                Some(addr) => debugger.run_to(addr),
+                None => Ok(()),
+            }
+        },
+    },
+    Command {
+        names: &["runtil"],
+        args: "<hexoffset>",
+        description: "Run until PC reaches the address relative to 0x08000000",
+        run: |debugger, cmd| {
+            match Debugger::hex_arg(cmd, "runtil <hexoffset>") {
+                Some(addr) => debugger.run_to(0x08000000 | addr),
+                None => Ok(()),
+            }
+        },
+    },
+    Command {
+        names: &["reset"],
+        args: "",
+        description: "Restart the loaded ROM from the beginning",
+        run: |debugger, _| {
+            debugger.on_break = false;
+            debugger.cpu.reset()
+        },
+    },
+    Command {
+        names: &["logon"],
+        args: "",
+        description: "Turn on logging",
+        run: |debugger, _| {
+            debugger.cpu.set_logging(true);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["logoff"],
+        args: "",
+        description: "Turn off logging",
+        run: |debugger, _| {
+            debugger.cpu.set_logging(false);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["logasm"],
+        args: "[off]",
+        description: "Log executed instructions as assembly with the registers they change",
+        run: |debugger, cmd| {
+            let enabled = cmd.split_whitespace().nth(1) != Some("off");
+            debugger.cpu.logger_mut().set_asm(enabled);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["logfile"],
+        args: "[file]",
+        description: "Write logs into the file, or stdout if no file is given",
+        run: |debugger, cmd| {
+            debugger.set_log_file(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["logcat"],
+        args: "<decode|mem|branch|all>...",
+        description: "Only log messages from the given categories",
+        run: |debugger, cmd| {
+            debugger.set_log_categories(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["v", "value"],
+        args: "<hexaddr> [s|u|h|b]",
+        description: "Print the 32bit value in memory, as signed or unsigned decimal, or the 16bit or 8bit value",
+        run: |debugger, cmd| {
+            debugger.print_value(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["x"],
+        args: "<hexaddr> <count>",
+        description: "Hexdump count 32bit values starting from the address",
+        run: |debugger, cmd| {
+            debugger.dump_memory(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["disasm"],
+        args: "[hexaddr] [count]",
+        description: "Disassemble count (default 5) instructions starting from the address or PC",
+        run: |debugger, cmd| {
+            debugger.disassemble(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["coverage"],
+        args: "[count]",
+        description: "Count the ARM instruction classes of count (default all) words from the ROM entry point",
+        run: |debugger, cmd| {
+            debugger.print_coverage(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["rominfo"],
+        args: "",
+        description: "Print the header of the loaded ROM",
+        run: |debugger, _| {
+            debugger.print_rom_info();
+            Ok(())
+        },
+    },
+    Command {
+        names: &["stats"],
+        args: "",
+        description: "Print the executed instructions and frames, and instructions and frames per second",
+        run: |debugger, _| {
+            print!("{}", debugger.cpu.stats());
+            Ok(())
+        },
+    },
+    Command {
+        names: &["history"],
+        args: "[count]",
+        description: "Print the last count (default 10) executed instructions, oldest first",
+        run: |debugger, cmd| {
+            debugger.print_history(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["set"],
+        args: "<reg> <hexvalue>",
+        description: "Set register value, register can be r0-r15, sp, lr, pc or cpsr",
+        run: |debugger, cmd| {
+            debugger.set_register(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["b", "break"],
+        args: "<hexaddr>",
+        description: "Set breakpoint on the address",
+        run: |debugger, cmd| {
+            debugger.add_break(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["rb", "rbreak"],
+        args: "<hexoffset>",
+        description: "Set breakpoint on the address relative to 0x08000000",
+        run: |debugger, cmd| {
+            debugger.add_relative_break(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["info"],
+        args: "break",
+        description: "List breakpoints with their indices",
+        run: |debugger, cmd| {
+            debugger.info(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["delete"],
+        args: "<index>|all",
+        description: "Delete breakpoint by its index, or all of them",
+        run: |debugger, cmd| {
+            debugger.delete_break(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["watch"],
+        args: "[read|write] <hexaddr>",
+        description: "Break when the 32bit value in the address changes, or when it's read or written",
+        run: |debugger, cmd| {
+            debugger.add_watch(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["assert"],
+        args: "<reg>|[hexaddr] == <hexvalue>",
+        description: "Stop the script with an error if the register or 32bit memory value differs",
+        run: |debugger, cmd| debugger.assert(cmd),
+    },
+    Command {
+        names: &["mode"],
+        args: "[arm|thumb]",
+        description: "Print the current instruction set, or force the cpu to ARM or Thumb",
+        run: |debugger, cmd| {
+            debugger.mode(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["trace"],
+        args: "<hexlow> <hexhigh>|dump|off",
+        description: "Record memory accesses inside the range, print and clear them with dump",
+        run: |debugger, cmd| {
+            debugger.trace(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["savestate"],
+        args: "<file>",
+        description: "Save the state of the Cpu and memory into a file",
+        run: |debugger, cmd| {
+            debugger.save_state(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["loadstate"],
+        args: "<file>",
+        description: "Load the state of the Cpu and memory from a file",
+        run: |debugger, cmd| {
+            debugger.load_state(cmd);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["dumpvram"],
+        args: "<file>",
+        description: "Write the raw bytes of VRAM into a file",
+        run: |debugger, cmd| {
+            debugger.dump_region(cmd, VRAM_START, VRAM_SIZE);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["dumppal"],
+        args: "<file>",
+        description: "Write the raw bytes of the palette RAM into a file",
+        run: |debugger, cmd| {
+            debugger.dump_region(cmd, PALETTE_START, PALETTE_SIZE);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["dumpoam"],
+        args: "<file>",
+        description: "Write the raw bytes of OAM into a file",
+        run: |debugger, cmd| {
+            debugger.dump_region(cmd, OAM_START, OAM_SIZE);
+            Ok(())
+        },
+    },
+    Command {
+        names: &["loadvram"],
+        args: "<file>",
+        description: "Load the bytes of a file into VRAM from its start",
+        run: |debugger, cmd| {
+            debugger.load_region(cmd, VRAM_START, VRAM_SIZE);
+            Ok(())
+        },
+    },
+];
+
 impl Debugger {
     pub fn new(cpu: Cpu) -> Self {
         Self {
             cpu,
             on_break: false,
             breaks: Vec::new(),
+            watches: Vec::new(),
         }
     }
 
-    pub fn initialize(&mut self, bytes: &[u8]) {
-        self.cpu.initialize_cpu(bytes);
+    /// Load the ROM, multiboot images are detected from the header
+    pub fn initialize(&mut self, bytes: &[u8]) -> EResult<()> {
+        self.initialize_with(bytes, BootConfig::detect(bytes))
+    }
+
+    pub fn initialize_with(&mut self, bytes: &[u8], boot: BootConfig) -> EResult<()> {
+        self.cpu.initialize_cpu(bytes, boot)
+    }
+
+    fn run(&mut self, cmd: &str) -> EResult<()> {
+        let usage = "Usage: run [max|all]";
+        let mut args = cmd.split_whitespace().skip(1);
+        let limit = match (args.next(), args.next()) {
+            (None, _) => Some(DEFAULT_RUN_LIMIT),
+            (Some("all"), None) => None,
+            (Some(max), None) => match max.parse() {
+                Ok(max) => Some(max),
+                Err(_) => {
+                    println!("{usage}");
+                    return Ok(());
+                }
+            },
+            _ => {
+                println!("{usage}");
+                return Ok(());
+            }
+        };
+
+        self.run_until(limit, |_| false)
+    }
+
+    /// Run until a breakpoint or a watch is hit, `limit` instructions have been executed,
+    /// or `done` returns true after an instruction. Without a limit the run can't be stopped
+    /// from the debugger.
+    fn run_until(&mut self, limit: Option<u64>, done: impl Fn(&Self) -> bool) -> EResult<()> {
+        let start = Instant::now();
+        let result = self.run_loop(limit, done);
+        self.cpu.add_run_time(start.elapsed());
+        result
     }
 
-    fn run(&mut self) -> EResult<()> {
+    fn run_loop(&mut self, limit: Option<u64>, done: impl Fn(&Self) -> bool) -> EResult<()> {
+        let mut executed = 0;
         loop {
+            if limit == Some(executed) {
+                println!(
+                    "stopped after {executed} instructions on addr {:08x}",
+                    self.cpu.pc
+                );
+                break;
+            }
+
             if !self.on_break && self.breaks.contains(&self.cpu.pc) {
-                println!("break on addr {:08x}", self.cpu.pc);
+                // ring the terminal bell, scripts don't need it
+                if io::stdout().is_terminal() {
+                    print!("\x07");
+                }
+                print!("{}", self.break_message());
                 self.on_break = true;
                 break;
             }
 
             self.on_break = false;
+            // debugger's own reads aren't watch hits
+            self.cpu.take_watch_hits();
             self.cpu.execute_next()?;
+            executed += 1;
+
+            if self.cpu.is_halted() {
+                println!("cpu halted on addr {:08x}", self.cpu.pc);
+                break;
+            }
+
+            let hits = self.cpu.take_watch_hits();
+            for hit in &hits {
+                println!("watch hit {hit}");
+            }
+
+            if !hits.is_empty() || self.check_watches() || done(self) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Registers, flags and the instruction at PC in a compact form
+    fn snapshot(&self) -> String {
+        let mut lines = String::new();
+        let registers = REGISTER_NAMES.iter().zip(self.cpu.register_values());
+        for (idx, (name, value)) in registers.enumerate() {
+            let end = if idx % 4 == 3 { "\n" } else { "  " };
+            lines.push_str(&format!("{name:>3}: {value:08x}{end}"));
+        }
+
+        let state = if self.cpu.is_thumb() { "thumb" } else { "arm" };
+        lines.push_str(&format!(
+            "cpsr: {:08x}  flags: {}  {state}\n",
+            self.cpu.cpsr(),
+            self.cpu.flags()
+        ));
+        lines.push_str(&self.disassembly(self.cpu.pc, 1));
+        lines
+    }
+
+    /// Printed after `next` executed the instruction of `step`
+    fn step_message(&self, step: &StepInfo) -> String {
+        format!(
+            "{:08x}: {}\n{}",
+            step.pc,
+            disassemble_step(step),
+            self.snapshot()
+        )
+    }
+
+    /// Printed when `run` stops on a breakpoint
+    fn break_message(&self) -> String {
+        format!("break on addr {:08x}\n{}", self.cpu.pc, self.snapshot())
+    }
+
+    /// Run until the current subroutine returns to the address in LR
+    fn finish(&mut self) -> EResult<()> {
+        let target = self.cpu.lr & !1;
+        let depth = self.cpu.call_depth();
+        self.run_until(Some(DEFAULT_RUN_LIMIT), |debugger| {
+            debugger.cpu.pc == target && debugger.cpu.call_depth() < depth
+        })?;
+
+        if self.cpu.pc == target {
+            println!("returned to addr {target:08x}");
+        }
+
+        Ok(())
+    }
+
+    /// Run until PC is `target` without adding a breakpoint, executes at least one instruction
+    fn run_to(&mut self, target: u32) -> EResult<()> {
+        self.run_until(Some(DEFAULT_RUN_LIMIT), |debugger| {
+            debugger.cpu.pc == target
+        })?;
+
+        if self.cpu.pc == target {
+            println!("reached addr {target:08x}");
         }
 
         Ok(())
     }
 
+    /// Update the cached values of the watched addresses, returns true if any of them changed
+    fn check_watches(&mut self) -> bool {
+        let mut changed = false;
+        for (addr, old) in self.watches.iter_mut() {
+            let new = self.cpu.get_memory(*addr);
+            if new != *old {
+                println!("watch on addr {addr:08x}: {old:08x} -> {new:08x}");
+                *old = new;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    /// Parse the only argument of the command as a hex value,
+    /// prints the usage and returns None if it's missing or invalid
+    fn hex_arg(cmd: &str, usage: &str) -> Option<u32> {
+        let mut args = cmd.split_whitespace().skip(1);
+        if let (Some(arg), None) = (args.next(), args.next()) {
+            if let Ok(value) = u32::from_str_radix(arg, 16) {
+                return Some(value);
+            }
+            println!("Invalid hex value '{arg}'");
+        }
+
+        println!("Usage: {usage}");
+        None
+    }
+
+    fn add_watch(&mut self, cmd: &str) {
+        let args: Vec<&str> = cmd.split_whitespace().collect();
+        let (kind, addr) = match args[..] {
+            [_, kind @ ("read" | "write"), addr] => (Some(kind == "write"), addr),
+            [_, addr] => (None, addr),
+            _ => {
+                println!("Usage: watch [read|write] <hexaddr>");
+                return;
+            }
+        };
+        let Ok(addr) = u32::from_str_radix(addr, 16) else {
+            println!("Invalid hex value '{addr}'");
+            return;
+        };
+
+        match kind {
+            Some(is_write) => self.cpu.add_access_watch(addr, is_write),
+            None => self.watches.push((addr, self.cpu.get_memory(addr))),
+        }
+    }
+
     fn add_break(&mut self, cmd: &str) {
-        let addr = cmd.split_whitespace().nth(1).unwrap();
-        let addr = u32::from_str_radix(addr, 16).unwrap();
+        let Some(addr) = Self::hex_arg(cmd, "b|break <hexaddr>") else {
+            return;
+        };
         self.breaks.push(addr);
     }
 
     fn add_relative_break(&mut self, cmd: &str) {
-        let addr = cmd.split_whitespace().nth(1).unwrap();
-        let addr = 0x08000000 | u32::from_str_radix(addr, 16).unwrap();
-        self.breaks.push(addr);
+        let Some(addr) = Self::hex_arg(cmd, "rb|rbreak <hexoffset>") else {
+            return;
+        };
+        self.breaks.push(0x08000000 | addr);
+    }
+
+    fn format_breaks(&self) -> String {
+        if self.breaks.is_empty() {
+            return "No breakpoints\n".into();
+        }
+
+        self.breaks
+            .iter()
+            .enumerate()
+            .map(|(idx, addr)| format!("{idx}: {addr:08x}\n"))
+            .collect()
+    }
+
+    fn delete_break(&mut self, cmd: &str) {
+        let Some(arg) = cmd.split_whitespace().nth(1) else {
+            println!("Usage: delete <index>|all");
+            return;
+        };
+
+        if arg == "all" {
+            self.breaks.clear();
+            return;
+        }
+
+        match arg.parse::<usize>() {
+            Ok(idx) if idx < self.breaks.len() => {
+                self.breaks.remove(idx);
+            }
+            Ok(idx) => println!(
+                "No breakpoint with index {idx}, there are {} breakpoints",
+                self.breaks.len()
+            ),
+            Err(_) => println!("Invalid breakpoint index '{arg}'"),
+        }
     }
 
     fn print_value(&mut self, cmd: &str) {
-        let addr = cmd.split_whitespace().nth(1).unwrap();
-        let addr = u32::from_str_radix(addr, 16).unwrap();
-        let value = self.cpu.get_memory(addr);
-        println!("value found {:08x}", value);
+        let usage = "Usage: v|value <hexaddr> [s|u|h|b]";
+        let mut args = cmd.split_whitespace().skip(1);
+        let (Some(addr), format, None) = (args.next(), args.next(), args.next()) else {
+            println!("{usage}");
+            return;
+        };
+        let Ok(addr) = u32::from_str_radix(addr, 16) else {
+            println!("Invalid hex value '{addr}'");
+            println!("{usage}");
+            return;
+        };
+
+        match self.format_value(addr, format) {
+            Some(value) => println!("value found {value}"),
+            None => println!("{usage}"),
+        }
+    }
+
+    /// Value in memory at `addr` as a hex word, or in the `format` of the `v` command.
+    /// None if the format is unknown.
+    fn format_value(&self, addr: u32, format: Option<&str>) -> Option<String> {
+        let value = match format {
+            None => format!("{:08x}", self.cpu.get_memory(addr)),
+            Some("s") => format!("{}", self.cpu.get_memory(addr) as i32),
+            Some("u") => format!("{}", self.cpu.get_memory(addr)),
+            Some("h") => format!("{:04x}", self.cpu.get_memory_u16(addr)),
+            Some("b") => format!("{:02x}", self.cpu.get_memory_u8(addr)),
+            Some(_) => return None,
+        };
+
+        Some(value)
+    }
+
+    /// Format `words` 32bit words starting from `addr` as a hexdump with
+    /// 16 bytes per line and an ASCII gutter
+    fn hexdump(&self, addr: u32, words: u32) -> String {
+        let bytes: Vec<u8> = (0..words as u64 * 4)
+            // stop at the end of the address space
+            .map_while(|offset| u32::try_from(addr as u64 + offset).ok())
+            .map(|byte_addr| self.cpu.get_memory_u8(byte_addr))
+            .collect();
+
+        let mut dump = String::new();
+        let mut region = None;
+        for (line, chunk) in bytes.chunks(16).enumerate() {
+            let line_addr = addr + line as u32 * 16;
+            // label the lines where a new region starts
+            let line_region = classify(line_addr);
+            if region != Some(line_region) {
+                dump.push_str(&format!("[{line_region}]\n"));
+                region = Some(line_region);
+            }
+
+            let mut hex = String::new();
+            for (idx, byte) in chunk.iter().enumerate() {
+                if idx == 8 {
+                    hex.push(' ');
+                }
+                hex.push_str(&format!("{byte:02x} "));
+            }
+
+            let ascii: String = chunk
+                .iter()
+                .map(|b| match b {
+                    0x20..=0x7e => *b as char,
+                    _ => '.',
+                })
+                .collect();
+
+            dump.push_str(&format!("{line_addr:08x}: {hex:<49} |{ascii}|\n"));
+        }
+
+        dump
+    }
+
+    fn dump_memory(&mut self, cmd: &str) {
+        let mut args = cmd.split_whitespace().skip(1);
+        let (Some(addr), Some(count), None) = (args.next(), args.next(), args.next()) else {
+            println!("Usage: x <hexaddr> <count>");
+            return;
+        };
+
+        let (Ok(addr), Ok(count)) = (u32::from_str_radix(addr, 16), count.parse::<u32>()) else {
+            println!("Usage: x <hexaddr> <count>");
+            return;
+        };
+
+        print!("{}", self.hexdump(addr, count));
+    }
+
+    /// Disassemble `count` instructions starting from `addr` in the current cpu mode
+    fn disassembly(&self, addr: u32, count: u32) -> String {
+        let mut lines = String::new();
+        let mut addr = addr;
+        // reads of the debugger aren't memory accesses of the program
+        let half_at = |addr: u32| {
+            let bytes = self.cpu.read_region(addr, 2);
+            u16::from_le_bytes([bytes[0], bytes[1]])
+        };
+        for _ in 0..count {
+            let marker = if addr == self.cpu.pc { '>' } else { ' ' };
+            let (raw, asm, width) = if self.cpu.is_thumb() {
+                let half = half_at(addr);
+                // long branch is made of two halfwords
+                if (half >> 11) == 0b11110 {
+                    let second = half_at(addr.wrapping_add(2));
+                    let raw = format!("{half:04x} {second:04x}");
+                    (raw, disassemble_thumb_long(half, second), 4)
+                } else {
+                    (format!("{half:04x}"), disassemble_thumb(half), 2)
+                }
+            } else {
+                let word =
+                    u32::from(half_at(addr)) | u32::from(half_at(addr.wrapping_add(2))) << 16;
+                (format!("{word:08x}"), disassemble_arm(word), 4)
+            };
+
+            lines.push_str(&format!("{marker}{addr:08x}: {raw:<9}  {asm}\n"));
+            addr = addr.wrapping_add(width);
+        }
+
+        lines
+    }
+
+    /// Decode `count` ARM words from the start of the ROM without running them
+    /// and count them by instruction class, most common first.
+    /// Unknown instructions are counted too instead of stopping on them.
+    fn coverage(&self, count: u32) -> Vec<(String, usize)> {
+        let mut counts = HashMap::new();
+        for idx in 0..count {
+            let addr = 0x08000000u32.wrapping_add(idx * 4);
+            let Ok(DecodedInstr::Arm(instr)) = self.cpu.peek_instruction(addr, false) else {
+                *counts.entry("Unknown".into()).or_insert(0) += 1;
+                continue;
+            };
+            let class = match instr {
+                Instruction::Alu(alu) => format!("Alu {:?}", alu.op),
+                Instruction::Branch(_) => "Branch".into(),
+                Instruction::BranchLinkExchange(_) => "BranchLinkExchange".into(),
+                Instruction::BranchExchange(_) => "BranchExchange".into(),
+                Instruction::Sdt(_) => "Sdt".into(),
+                Instruction::Pld(_) => "Pld".into(),
+                Instruction::Psr => "Psr".into(),
+                Instruction::Swap(_) => "Swap".into(),
+                Instruction::Swi(_) => "Swi".into(),
+            };
+            *counts.entry(class).or_insert(0) += 1;
+        }
+
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts
+    }
+
+    fn print_coverage(&mut self, cmd: &str) {
+        let words = (self.cpu.rom_size() / 4) as u32;
+        let count = match cmd.split_whitespace().nth(1).map(|arg| arg.parse::<u32>()) {
+            None => words,
+            Some(Ok(count)) => count.min(words),
+            Some(Err(_)) => {
+                println!("Usage: coverage [count]");
+                return;
+            }
+        };
+
+        for (class, count) in self.coverage(count) {
+            println!("{count:>8}  {class}");
+        }
+    }
+
+    /// Last `count` executed instructions disassembled, oldest first
+    fn history(&self, count: usize) -> String {
+        let history = self.cpu.history();
+        history
+            .iter()
+            .skip(history.len().saturating_sub(count))
+            .map(|entry| {
+                let (raw, asm) = if !entry.thumb {
+                    (
+                        format!("{:08x}", entry.opcode),
+                        disassemble_arm(entry.opcode),
+                    )
+                } else if entry.opcode >> 16 != 0 {
+                    let (first, second) = (entry.opcode as u16, (entry.opcode >> 16) as u16);
+                    let raw = format!("{first:04x} {second:04x}");
+                    (raw, disassemble_thumb_long(first, second))
+                } else {
+                    let half = entry.opcode as u16;
+                    (format!("{half:04x}"), disassemble_thumb(half))
+                };
+                format!(" {:08x}: {raw:<9}  {asm}\n", entry.pc)
+            })
+            .collect()
+    }
+
+    fn print_rom_info(&self) {
+        match GBAHeader::from_file(self.cpu.rom()) {
+            Ok(header) => print!("{header}"),
+            Err(_) => println!("No ROM loaded"),
+        }
+    }
+
+    fn print_history(&mut self, cmd: &str) {
+        let count = match cmd
+            .split_whitespace()
+            .nth(1)
+            .map(|arg| arg.parse::<usize>())
+        {
+            None => 10,
+            Some(Ok(count)) => count,
+            Some(Err(_)) => {
+                println!("Usage: history [count]");
+                return;
+            }
+        };
+
+        print!("{}", self.history(count));
+    }
+
+    fn disassemble(&mut self, cmd: &str) {
+        let usage = "Usage: disasm [hexaddr] [count]";
+        let mut args = cmd.split_whitespace().skip(1);
+        let addr = match args.next().map(|arg| u32::from_str_radix(arg, 16)) {
+            None => self.cpu.pc,
+            Some(Ok(addr)) => addr,
+            Some(Err(_)) => {
+                println!("{usage}");
+                return;
+            }
+        };
+        let count = match args.next().map(|arg| arg.parse::<u32>()) {
+            None => 5,
+            Some(Ok(count)) => count,
+            Some(Err(_)) => {
+                println!("{usage}");
+                return;
+            }
+        };
+        if args.next().is_some() {
+            println!("{usage}");
+            return;
+        }
+
+        print!("{}", self.disassembly(addr, count));
+    }
+
+    fn save_state(&mut self, cmd: &str) {
+        let Some(path) = cmd.split_whitespace().nth(1) else {
+            println!("Usage: savestate <file>");
+            return;
+        };
+
+        if let Err(e) = fs::write(path, self.cpu.save_state()) {
+            println!("Failed to write save state '{path}': {e}");
+        }
+    }
+
+    /// Write `size` bytes of the memory region at `start` into the file of `cmd`
+    fn dump_region(&self, cmd: &str, start: u32, size: u32) {
+        let mut args = cmd.split_whitespace();
+        let name = args.next().unwrap_or_default();
+        let (Some(path), None) = (args.next(), args.next()) else {
+            println!("Usage: {name} <file>");
+            return;
+        };
+
+        if let Err(e) = fs::write(path, self.cpu.read_region(start, size)) {
+            println!("Failed to write '{path}': {e}");
+        }
+    }
+
+    /// Load the file of `cmd` into the memory region of `size` bytes at `start`
+    fn load_region(&mut self, cmd: &str, start: u32, size: u32) {
+        let mut args = cmd.split_whitespace();
+        let name = args.next().unwrap_or_default();
+        let (Some(path), None) = (args.next(), args.next()) else {
+            println!("Usage: {name} <file>");
+            return;
+        };
+
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                println!("Failed to read '{path}': {e}");
+                return;
+            }
+        };
+
+        if bytes.len() > size as usize {
+            println!(
+                "'{path}' has {} bytes, the region has only {size}",
+                bytes.len()
+            );
+            return;
+        }
+
+        self.cpu.write_region(start, &bytes);
+    }
+
+    fn load_state(&mut self, cmd: &str) {
+        let Some(path) = cmd.split_whitespace().nth(1) else {
+            println!("Usage: loadstate <file>");
+            return;
+        };
+
+        let state = match fs::read(path) {
+            Ok(state) => state,
+            Err(e) => {
+                println!("Failed to read save state '{path}': {e}");
+                return;
+            }
+        };
+
+        if let Err(e) = self.cpu.load_state(&state) {
+            println!("Failed to load save state '{path}': {e}");
+        }
+    }
+
+    fn set_log_file(&mut self, cmd: &str) {
+        let mut args = cmd.split_whitespace().skip(1);
+        let file = match (args.next(), args.next()) {
+            (Some(path), None) => match fs::File::create(path) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    println!("Failed to create log file '{path}': {e}");
+                    return;
+                }
+            },
+            (None, _) => None,
+            _ => {
+                println!("Usage: logfile [file]");
+                return;
+            }
+        };
+
+        self.cpu.logger_mut().set_file(file);
+    }
+
+    fn set_log_categories(&mut self, cmd: &str) {
+        let mut categories = Vec::new();
+        for name in cmd.split_whitespace().skip(1) {
+            if name == "all" {
+                categories.extend(LogCategory::ALL);
+            } else if let Some(category) = LogCategory::from_name(name) {
+                categories.push(category);
+            } else {
+                println!("Unknown log category '{name}'");
+                println!("Usage: logcat <decode|mem|branch|all>...");
+                return;
+            }
+        }
+
+        if categories.is_empty() {
+            println!("Usage: logcat <decode|mem|branch|all>...");
+            return;
+        }
+
+        self.cpu.logger_mut().set_categories(&categories);
+    }
+
+    fn trace(&mut self, cmd: &str) {
+        let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+        match args[..] {
+            ["dump"] => {
+                for entry in self.cpu.take_trace() {
+                    println!("{entry}");
+                }
+            }
+            ["off"] => self.cpu.set_trace_range(None),
+            [low, high] => match (u32::from_str_radix(low, 16), u32::from_str_radix(high, 16)) {
+                (Ok(low), Ok(high)) if low <= high => self.cpu.set_trace_range(Some((low, high))),
+                _ => println!("Invalid trace range '{low} {high}'"),
+            },
+            _ => println!("Usage: trace <hexlow> <hexhigh>|dump|off"),
+        }
+    }
+
+    fn assert(&mut self, cmd: &str) -> EResult<()> {
+        let usage = "Usage: assert <reg>|[hexaddr] == <hexvalue>";
+        let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+        let [target, "==", expected] = args[..] else {
+            return Err(ExecErr::InvalidAssertion(usage.into()));
+        };
+
+        let Ok(expected) = u32::from_str_radix(expected, 16) else {
+            return Err(ExecErr::InvalidAssertion(format!(
+                "Invalid hex value '{expected}'"
+            )));
+        };
+
+        let actual = match target
+            .strip_prefix('[')
+            .and_then(|addr| addr.strip_suffix(']'))
+        {
+            Some(addr) => match u32::from_str_radix(addr, 16) {
+                Ok(addr) => self.cpu.get_memory(addr),
+                Err(_) => {
+                    return Err(ExecErr::InvalidAssertion(format!(
+                        "Invalid hex address '{addr}'"
+                    )))
+                }
+            },
+            None => self
+                .cpu
+                .get_register_by_name(target)
+                .map_err(ExecErr::InvalidAssertion)?,
+        };
+
+        if actual != expected {
+            return Err(ExecErr::AssertionFailed(format!(
+                "{target} == {expected:08x}, got {actual:08x}"
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn mode(&mut self, cmd: &str) {
+        let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+        match args[..] {
+            [] if self.cpu.is_thumb() => println!("thumb"),
+            [] => println!("arm"),
+            ["arm"] => self.cpu.set_thumb(false),
+            ["thumb"] => self.cpu.set_thumb(true),
+            _ => println!("Usage: mode [arm|thumb]"),
+        }
+    }
+
+    fn set_register(&mut self, cmd: &str) {
+        let mut args = cmd.split_whitespace().skip(1);
+        let (Some(reg), Some(value), None) = (args.next(), args.next(), args.next()) else {
+            println!("Usage: set <reg> <hexvalue>");
+            return;
+        };
+
+        let Ok(value) = u32::from_str_radix(value, 16) else {
+            println!("Invalid hex value '{value}'");
+            return;
+        };
+
+        if let Err(e) = self.cpu.set_register_by_name(reg, value) {
+            println!("{e}");
+        }
+    }
+
+    fn info(&mut self, cmd: &str) {
+        match cmd.split_whitespace().nth(1) {
+            Some("break") => print!("{}", self.format_breaks()),
+            _ => println!("Usage: info break"),
+        }
+    }
+
+    fn help_text() -> String {
+        let usages: Vec<(String, &str)> = COMMANDS
+            .iter()
+            .map(|command| {
+                let names = command.names.join(", ");
+                let usage = if command.args.is_empty() {
+                    names
+                } else {
+                    format!("{names} {}", command.args)
+                };
+                (usage, command.description)
+            })
+            .collect();
+
+        let width = usages
+            .iter()
+            .map(|(usage, _)| usage.len())
+            .max()
+            .unwrap_or(0);
+        usages
+            .iter()
+            .map(|(usage, description)| format!("{usage:<width$}  {description}\n"))
+            .collect()
     }
 
     fn run_command(&mut self, cmd: &str) -> EResult<()> {
-        if cmd == "q" || cmd == "quit" || cmd == "exit" {
-            exit(0);
-        } else if cmd == "p" || cmd == "print" {
-            println!("{}", self.cpu);
-        } else if cmd == "r" || cmd == "run" {
-            self.run()?;
-        } else if cmd == "n" || cmd == "next" {
-            self.on_break = false;
-            self.cpu.execute_next()?
-        } else if cmd == "logon" {
-            self.cpu.set_logging(true);
-        } else if cmd == "logoff" {
-            self.cpu.set_logging(false);
-        } else if cmd.starts_with("v ") || cmd.starts_with("value ") {
-            self.print_value(cmd);
-        } else if cmd.starts_with("b ") || cmd.starts_with("break ") {
-            self.add_break(cmd);
-        } else if cmd.starts_with("rb ") || cmd.starts_with("rbreak ") {
-            self.add_relative_break(cmd);
-        } else {
-            println!("Unknown command {cmd}");
+        let name = cmd.split_whitespace().next().unwrap_or_default();
+        match COMMANDS
+            .iter()
+            .find(|command| command.names.contains(&name))
+        {
+            Some(command) => (command.run)(self, cmd)?,
+            None => println!("Unknown command {cmd}"),
         }
 
         Ok(())
@@ -102,13 +1137,772 @@ impl Debugger {
         Ok(())
     }
 
+    /// Interactive debugger with line editing and history kept in `~/.cgba_emulator_history`.
+    /// Reads plain lines if stdin isn't a terminal.
     pub fn repl(&mut self) -> EResult<()> {
+        if !io::stdin().is_terminal() {
+            return self.repl_lines(io::stdin().lock());
+        }
+
+        let mut editor = match DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(e) => {
+                println!("Failed to start the line editor: {e}");
+                return self.repl_lines(io::stdin().lock());
+            }
+        };
+
+        let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        if let Some(path) = &history {
+            // there's no history before the first session
+            let _ = editor.load_history(path);
+        }
+
+        loop {
+            let cmd = match editor.readline("> ") {
+                Ok(cmd) => cmd,
+                // ctrl-c and ctrl-d
+                Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Ok(()),
+                Err(e) => {
+                    println!("Failed to read command: {e}");
+                    return Ok(());
+                }
+            };
+
+            let cmd = cmd.trim();
+            if !cmd.is_empty() {
+                let _ = editor.add_history_entry(cmd);
+                // saved after every command since quit exits the process
+                if let Some(path) = &history {
+                    if let Err(e) = editor.save_history(path) {
+                        println!("Failed to save history to '{}': {e}", path.display());
+                    }
+                }
+            }
+            self.repl_command(cmd)?;
+        }
+    }
+
+    /// Run the commands read from `input` line by line until the input ends
+    pub fn repl_lines(&mut self, mut input: impl BufRead) -> EResult<()> {
         loop {
             print!("> ");
             io::stdout().flush().unwrap();
             let mut cmd = String::new();
-            let _ = io::stdin().lock().read_line(&mut cmd).unwrap();
-            self.run_command(cmd.trim())?;
+            match input.read_line(&mut cmd) {
+                Ok(0) | Err(_) => return Ok(()),
+                Ok(_) => self.repl_command(cmd.trim())?,
+            }
+        }
+    }
+
+    fn repl_command(&mut self, cmd: &str) -> EResult<()> {
+        // only scripts stop on failed or invalid assertions
+        match self.run_command(cmd) {
+            Err(e @ (ExecErr::AssertionFailed(_) | ExecErr::InvalidAssertion(_))) => {
+                println!("{e}");
+                Ok(())
+            }
+            res => res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::{arm_rom, thumb_rom};
+
+    #[test]
+    fn test_coverage() {
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger
+            .initialize(&arm_rom(&[
+                0xE3A00005, // mov r0, #5
+                0xE3A01006, // mov r1, #6
+                0xE2802001, // add r2, r0, #1
+                0xE5910000, // ldr r0, [r1]
+                0xEF060000, // swi 0x060000
+                0xE8BD0001, // ldmia sp!, {r0}, not decoded yet
+                0xEAFFFFFE, // b .
+            ]))
+            .unwrap();
+        assert_eq!(
+            debugger.coverage(7),
+            vec![
+                ("Alu Mov".to_string(), 2),
+                ("Alu Add".to_string(), 1),
+                ("Branch".to_string(), 1),
+                ("Sdt".to_string(), 1),
+                ("Swi".to_string(), 1),
+                ("Unknown".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_register() {
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger
+            .run_file("set r3 deadbeef\nset pc 08000010")
+            .unwrap();
+        assert_eq!(debugger.cpu.r3, 0xdeadbeef);
+        assert_eq!(debugger.cpu.pc, 0x08000010);
+        assert!(format!("{}", debugger.cpu).contains("r3: 0xdeadbeef"));
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let mut cpu = Cpu::new();
+        for (idx, word) in [0x6c6c6548, 0x47202c6f, 0x00214142, 0x03020100, 0x7e7f8081]
+            .iter()
+            .enumerate()
+        {
+            cpu.set_memory(0x03000000 + idx as u32 * 4, *word);
         }
+
+        let debugger = Debugger::new(cpu);
+        assert_eq!(
+            debugger.hexdump(0x03000000, 5),
+            "[IWRAM]\n\
+             03000000: 48 65 6c 6c 6f 2c 20 47  42 41 21 00 00 01 02 03  |Hello, GBA!.....|\n\
+             03000010: 81 80 7f 7e                                       |...~|\n"
+        );
+        assert_eq!(debugger.hexdump(0x03000000, 0), "");
+    }
+
+    #[test]
+    fn test_hexdump_end_of_address_space() {
+        let debugger = Debugger::new(Cpu::new());
+        let dump = debugger.hexdump(0xfffffff8, 4);
+        assert_eq!(dump.lines().count(), 2);
+        assert!(dump.starts_with("[Unmapped]\nfffffff8: 00 00 00 00 00 00 00 00 "));
+    }
+
+    #[test]
+    fn test_hexdump_regions() {
+        let debugger = Debugger::new(Cpu::new());
+        let dump = debugger.hexdump(0x02FFFFF0, 8);
+        let labels: Vec<&str> = dump.lines().filter(|line| line.starts_with('[')).collect();
+        assert_eq!(labels, ["[EWRAM]", "[IWRAM]"]);
+        assert!(dump.contains("[IWRAM]\n03000000: "));
+    }
+
+    #[test]
+    fn test_watch() {
+        let rom = arm_rom(&[
+            0xE3A00005, // mov r0, #5
+            0xE3A01403, // mov r1, #0x03000000
+            0xE5810000, // str r0, [r1]
+            0xE3A00006, // mov r0, #6
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger.run_file("watch 03000000\nr").unwrap();
+
+        // stops right after the store
+        assert_eq!(debugger.cpu.pc, 0x0800000C);
+        assert_eq!(debugger.cpu.r0, 5);
+        assert_eq!(debugger.watches, vec![(0x03000000, 5)]);
+    }
+
+    #[test]
+    fn test_access_watch() {
+        let rom = arm_rom(&[
+            0xE3A00005, // mov r0, #5
+            0xE3A01403, // mov r1, #0x03000000
+            0xE5912000, // ldr r2, [r1]
+            0xE5810000, // str r0, [r1]
+            0xE3A00006, // mov r0, #6
+            0xE5912000, // ldr r2, [r1]
+            0xEAFFFFFE, // b .
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        // the load doesn't break a write watch, the store does
+        debugger
+            .run_file(
+                "watch write 03000002
+r",
+            )
+            .unwrap();
+        assert_eq!(debugger.cpu.pc, 0x08000010);
+        assert_eq!(debugger.cpu.get_memory(0x03000000), 5);
+
+        // reading the address in the debugger isn't a hit
+        debugger
+            .run_file(
+                "v 03000000
+watch read 03000000
+r",
+            )
+            .unwrap();
+        assert_eq!(debugger.cpu.pc, 0x08000018);
+        assert_eq!(debugger.cpu.r2, 5);
+
+        debugger.run_file("r").unwrap();
+        assert!(debugger.cpu.is_halted());
+    }
+
+    #[test]
+    fn test_format_value() {
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.cpu.set_memory(0x03000000, 0xFFFFFFFB);
+        debugger.cpu.set_memory(0x03000004, 0x12345678);
+
+        let value = |format| debugger.format_value(0x03000000, format);
+        assert_eq!(value(None).as_deref(), Some("fffffffb"));
+        assert_eq!(value(Some("s")).as_deref(), Some("-5"));
+        assert_eq!(value(Some("u")).as_deref(), Some("4294967291"));
+        assert_eq!(value(Some("x")), None);
+
+        let value = |format| debugger.format_value(0x03000004, format);
+        assert_eq!(value(Some("s")).as_deref(), Some("305419896"));
+        assert_eq!(value(Some("h")).as_deref(), Some("5678"));
+        assert_eq!(value(Some("b")).as_deref(), Some("78"));
+        assert_eq!(
+            debugger.format_value(0x03000007, Some("b")).as_deref(),
+            Some("12")
+        );
+
+        // malformed commands only print the usage
+        debugger
+            .run_file("v\nv 03000000 s extra\nv zz s\nv 03000000 q")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_list_and_delete_breaks() {
+        let mut debugger = Debugger::new(Cpu::new());
+        assert_eq!(debugger.format_breaks(), "No breakpoints\n");
+
+        debugger.run_file("b 08000188\nrb 200").unwrap();
+        assert_eq!(debugger.format_breaks(), "0: 08000188\n1: 08000200\n");
+
+        // out of range and invalid indices are ignored
+        debugger.run_file("delete 2\ndelete x\ndelete").unwrap();
+        assert_eq!(debugger.breaks, vec![0x08000188, 0x08000200]);
+
+        debugger.run_file("delete 0").unwrap();
+        assert_eq!(debugger.breaks, vec![0x08000200]);
+        assert_eq!(debugger.format_breaks(), "0: 08000200\n");
+
+        debugger.run_file("b 08000188\ndelete all").unwrap();
+        assert!(debugger.breaks.is_empty());
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let path = std::env::temp_dir().join("gba_emu_test_debugger.state");
+        let path = path.to_str().unwrap();
+
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger
+            .run_file(&format!("set r1 1234\nsavestate {path}\nset r1 0"))
+            .unwrap();
+        assert_eq!(debugger.cpu.r1, 0);
+
+        debugger.run_file(&format!("loadstate {path}")).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(debugger.cpu.r1, 0x1234);
+    }
+
+    #[test]
+    fn test_dump_and_load_vram() {
+        let dir = std::env::temp_dir();
+        let vram = dir.join("gba_emu_test_debugger.vram");
+        let vram = vram.to_str().unwrap();
+        let pal = dir.join("gba_emu_test_debugger.pal");
+        let pal = pal.to_str().unwrap();
+        let oam = dir.join("gba_emu_test_debugger.oam");
+        let oam = oam.to_str().unwrap();
+
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.cpu.set_memory(0x06000000, 0x12345678);
+        debugger.cpu.set_memory(0x06017FFC, 0xdeadbeef);
+        debugger.cpu.set_memory_u16(0x05000002, 0x7FFF);
+        debugger
+            .run_file(&format!(
+                "dumpvram {vram}
+dumppal {pal}
+dumpoam {oam}"
+            ))
+            .unwrap();
+        let dump = std::fs::read(vram).unwrap();
+        assert_eq!(dump.len(), 0x18000);
+        assert_eq!(dump[..4], [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(std::fs::read(pal).unwrap()[2..4], [0xFF, 0x7F]);
+        assert_eq!(std::fs::read(oam).unwrap(), vec![0; 0x400]);
+
+        // round trip into a new cpu
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.run_file(&format!("loadvram {vram}")).unwrap();
+        assert_eq!(debugger.cpu.read_region(0x06000000, 0x18000), dump);
+        assert_eq!(debugger.cpu.get_memory(0x06017FFC), 0xdeadbeef);
+
+        // too large file doesn't touch VRAM
+        std::fs::write(vram, vec![1; 0x18001]).unwrap();
+        debugger.run_file(&format!("loadvram {vram}")).unwrap();
+        assert_eq!(debugger.cpu.get_memory(0x06000000), 0x12345678);
+
+        for path in [vram, pal, oam] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_reset() {
+        let rom = arm_rom(&[0xE3A00005, 0xE3A01403]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger.run_file("n\nn\nreset").unwrap();
+        assert_eq!(debugger.cpu.pc, 0x08000000);
+        assert_eq!(debugger.cpu.r0, 0);
+    }
+
+    #[test]
+    fn test_malformed_commands() {
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger
+            .run_file(
+                "b\nb zzz\nbreak 1 2\nrb\nrb xyz\nv\nvalue qq\nwatch\nwatch g\nx\nx 1\nx zz 1\n\
+                 x 0 zz\nset\ndelete 99\nsavestate\nloadstate\nloadstate /nonexistent/state\nrominfo",
+            )
+            .unwrap();
+        assert!(debugger.breaks.is_empty());
+        assert!(debugger.watches.is_empty());
+    }
+
+    #[test]
+    fn test_help() {
+        let help = Debugger::help_text();
+        for name in [
+            "help",
+            "quit",
+            "print",
+            "run",
+            "next",
+            "reset",
+            "logon",
+            "logoff",
+            "logasm",
+            "stats",
+            "value",
+            "x",
+            "set",
+            "break",
+            "rbreak",
+            "info",
+            "delete",
+            "watch",
+            "savestate",
+            "loadstate",
+            "dumpvram",
+            "dumppal",
+            "dumpoam",
+            "loadvram",
+        ] {
+            assert!(help.contains(name), "help is missing {name}");
+        }
+
+        for command in COMMANDS {
+            for name in command.names {
+                assert!(help.contains(name), "help is missing {name}");
+            }
+        }
+        assert_eq!(help.lines().count(), COMMANDS.len());
+    }
+
+    #[test]
+    fn test_finish() {
+        let rom = thumb_rom(&[
+            0xF000, 0xF804, // 00: bl sub
+            0x2001, // 04: mov r0, #1
+            0xE7FE, // 06: b 06
+            0x2203, // 08: sub2: mov r2, #3
+            0x4770, // 0A: bx lr
+            0x4674, // 0C: sub: mov r4, lr
+            0x2102, // 0E: mov r1, #2
+            0xF7FF, 0xFFFA, // 10: bl sub2
+            0x4720, // 14: bx r4
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger.run_file("set cpsr 3f\nn\nn").unwrap();
+        assert_eq!(debugger.cpu.pc, 0x0800000E);
+
+        // doesn't stop when the nested sub2 returns
+        debugger.run_file("finish").unwrap();
+        assert_eq!(debugger.cpu.pc, 0x08000004);
+        assert_eq!(debugger.cpu.r0, 0);
+        assert_eq!(debugger.cpu.r1, 2);
+        assert_eq!(debugger.cpu.r2, 3);
+    }
+
+    #[test]
+    fn test_history() {
+        let rom = arm_rom(&[
+            0xE3A00005, // mov r0, #5
+            0xE2800001, // add r0, r0, #1
+            0xE3A01403, // mov r1, #0x3000000
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        assert_eq!(debugger.history(10), "");
+
+        debugger.run_file("n\nn\nn").unwrap();
+        assert_eq!(
+            debugger.history(2),
+            " 08000004: e2800001   ADD r0, r0, #0x1\n 08000008: e3a01403   MOV r1, #0x3000000\n"
+        );
+        assert_eq!(debugger.history(10).lines().count(), 3);
+    }
+
+    #[test]
+    fn test_until() {
+        let rom = arm_rom(&[
+            0xE3A00005, // 00: mov r0, #5
+            0xE2800001, // 04: add r0, r0, #1
+            0xE2800001, // 08: add r0, r0, #1
+            0xE2800001, // 0C: add r0, r0, #1
+            0xEAFFFFFE, // 10: b .
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger.run_file("until 08000008").unwrap();
+        assert_eq!(debugger.cpu.pc, 0x08000008);
+        assert_eq!(debugger.cpu.r0, 6);
+        // no breakpoint is left behind
+        assert!(debugger.breaks.is_empty());
+
+        // stops on an existing breakpoint before the target
+        debugger
+            .run_file(
+                "b 0800000c
+runtil 10",
+            )
+            .unwrap();
+        assert_eq!(debugger.cpu.pc, 0x0800000C);
+        debugger.run_file("runtil 10").unwrap();
+        assert_eq!(debugger.cpu.pc, 0x08000010);
+        assert_eq!(debugger.cpu.r0, 8);
+    }
+
+    #[test]
+    fn test_finish_mov_pc_lr() {
+        let rom = thumb_rom(&[
+            0xF000, 0xF802, // 00: bl sub
+            0x2001, // 04: mov r0, #1
+            0xE7FE, // 06: b 06
+            0x2102, // 08: sub: mov r1, #2
+            0x46F7, // 0A: mov pc, lr
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger.run_file("set cpsr 3f\nn\nfinish").unwrap();
+        assert_eq!(debugger.cpu.pc, 0x08000004);
+        assert!(debugger.cpu.is_thumb());
+        assert_eq!(debugger.cpu.r0, 0);
+        assert_eq!(debugger.cpu.r1, 2);
+    }
+
+    #[test]
+    fn test_finish_stops_on_break() {
+        let rom = thumb_rom(&[
+            0xF000, 0xF804, // 00: bl sub
+            0x2001, // 04: mov r0, #1
+            0xE7FE, // 06: b 06
+            0x0000, 0x0000, // 08: padding
+            0x2102, // 0C: sub: mov r1, #2
+            0x2203, // 0E: mov r2, #3
+            0x4770, // 10: bx lr
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger
+            .run_file("set cpsr 3f\nn\nb 0800000e\nfinish")
+            .unwrap();
+        assert_eq!(debugger.cpu.pc, 0x0800000E);
+
+        debugger.run_file("finish").unwrap();
+        assert_eq!(debugger.cpu.pc, 0x08000004);
+        assert_eq!(debugger.cpu.r2, 3);
+    }
+
+    #[test]
+    fn test_log_file_and_categories() {
+        let rom = arm_rom(&[
+            0xE3A00005, // mov r0, #5
+            0xE3A01403, // mov r1, #0x03000000
+            0xE5810000, // str r0, [r1]
+        ]);
+        let path = std::env::temp_dir().join("gba_emu_test_debugger.log");
+        let path = path.to_str().unwrap();
+
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger
+            .run_file(&format!(
+                "logfile {path}\nlogcat mem\nlogon\nn\nn\nn\nlogfile"
+            ))
+            .unwrap();
+
+        let log = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(log.lines().count(), 1);
+        assert!(log.contains("Write word 00000005 to addr: 03000000"));
+    }
+
+    #[test]
+    fn test_logasm() {
+        let rom = arm_rom(&[
+            0xE3A00005, // mov r0, #5
+            0xE3A01403, // mov r1, #0x03000000
+        ]);
+        let path = std::env::temp_dir().join("gba_emu_test_debugger_asm.log");
+        let path = path.to_str().unwrap();
+
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger
+            .run_file(&format!(
+                "logfile {path}\nlogasm\nn\nlogasm off\nn\nlogfile"
+            ))
+            .unwrap();
+
+        let log = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(log, "08000000: MOV r0, #0x5 r0=00000005\n");
+    }
+
+    #[test]
+    fn test_trace() {
+        let rom = arm_rom(&[
+            0xE3A00005, // mov r0, #5
+            0xE3A01403, // mov r1, #0x03000000
+            0xE5810000, // str r0, [r1]
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger
+            .run_file("trace 0 2\ntrace 1\ntrace 3000000 3000003\nn\nn\nn")
+            .unwrap();
+
+        let trace = debugger.cpu.take_trace();
+        assert_eq!(trace.len(), 1);
+        assert_eq!(trace[0].addr, 0x03000000);
+        assert!(trace[0].is_write);
+
+        debugger.run_file("trace off\nreset\nn\nn\nn").unwrap();
+        assert!(debugger.cpu.take_trace().is_empty());
+    }
+
+    #[test]
+    fn test_run_stops_when_halted() {
+        let rom = arm_rom(&[
+            0xE3A00005, // mov r0, #5
+            0xEAFFFFFE, // b .
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger.run_file("r").unwrap();
+        assert!(debugger.cpu.is_halted());
+        assert_eq!(debugger.cpu.pc, 0x08000004);
+        assert_eq!(debugger.cpu.r0, 5);
+    }
+
+    #[test]
+    fn test_run_limit() {
+        let rom = arm_rom(&[
+            0xE2800001, // loop: add r0, r0, #1
+            0xEAFFFFFD, // b loop
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger.run_file("run 50").unwrap();
+        assert_eq!(debugger.cpu.stats().instructions, 50);
+        assert_eq!(debugger.cpu.r0, 25);
+        assert_eq!(debugger.cpu.pc, 0x08000000);
+
+        // continues from where it stopped
+        debugger.run_file("r 3").unwrap();
+        assert_eq!(debugger.cpu.r0, 27);
+
+        // invalid limits don't run at all
+        debugger.run_file("run x\nrun 5 6\nrun -1").unwrap();
+        assert_eq!(debugger.cpu.stats().instructions, 53);
+    }
+
+    #[test]
+    fn test_stats() {
+        let rom = arm_rom(&[
+            0xE3A00005, // mov r0, #5
+            0xEAFFFFFE, // b .
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger.run_file("r\nstats").unwrap();
+        assert_eq!(debugger.cpu.stats().instructions, 2);
+
+        debugger.run_file("reset\nstats").unwrap();
+        assert_eq!(debugger.cpu.stats().instructions, 0);
+    }
+
+    #[test]
+    fn test_break_message() {
+        let rom = arm_rom(&[
+            0xE3A00005, // mov r0, #5
+            0xE2801001, // add r1, r0, #1
+            0xEAFFFFFE, // b .
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger.run_file("b 08000008\nr").unwrap();
+        assert_eq!(debugger.cpu.pc, 0x08000008);
+
+        let message = debugger.break_message();
+        let lines: Vec<&str> = message.lines().collect();
+        assert_eq!(lines[0], "break on addr 08000008");
+        assert_eq!(
+            lines[1],
+            " r0: 00000005   r1: 00000006   r2: 00000000   r3: 00000000"
+        );
+        assert!(lines[4].ends_with("pc: 08000008"), "{}", lines[4]);
+        assert_eq!(lines[5], "cpsr: 0000001f  flags: ----  arm");
+        assert_eq!(lines[6], ">08000008: eafffffe   B $+0x0");
+    }
+
+    #[test]
+    fn test_step_message() {
+        let rom = arm_rom(&[
+            0xE3A00005, // mov r0, #5
+            0xE2801001, // add r1, r0, #1
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        let step = debugger.cpu.step().unwrap();
+
+        let message = debugger.step_message(&step);
+        let lines: Vec<&str> = message.lines().collect();
+        assert_eq!(lines[0], "08000000: MOV r0, #0x5");
+        assert_eq!(
+            lines[1],
+            " r0: 00000005   r1: 00000000   r2: 00000000   r3: 00000000"
+        );
+        assert_eq!(lines[6], ">08000004: e2801001   ADD r1, r0, #0x1");
+    }
+
+    #[test]
+    fn test_disassembly() {
+        let rom = arm_rom(&[
+            0xE3A00005, // mov r0, #5
+            0xE5810000, // str r0, [r1]
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        assert_eq!(
+            debugger.disassembly(0x08000000, 2),
+            ">08000000: e3a00005   MOV r0, #0x5\n 08000004: e5810000   STR r0, [r1]\n"
+        );
+
+        let rom = thumb_rom(&[
+            0xF000, 0xF804, // bl $+0xc
+            0x2001, // mov r0, #1
+        ]);
+        debugger.initialize(&rom).unwrap();
+        debugger.run_file("set cpsr 3f").unwrap();
+        assert_eq!(
+            debugger.disassembly(0x08000000, 2),
+            ">08000000: f000 f804  BL $+0xc\n 08000004: 2001       MOV r0, #0x1\n"
+        );
+    }
+
+    #[test]
+    fn test_assert() {
+        let rom = arm_rom(&[
+            0xE3A00005, // mov r0, #5
+            0xE2800001, // add r0, r0, #1
+            0xE5810000, // str r0, [r1]
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger
+            .run_file("set r1 03000000\nn\nn\nn\nassert r0 == 6\nassert [03000000] == 6")
+            .unwrap();
+
+        assert_eq!(
+            debugger.run_file("assert r0 == 5\nset r2 1"),
+            Err(ExecErr::AssertionFailed(
+                "r0 == 00000005, got 00000006".into()
+            ))
+        );
+        // script stops on the failed assertion
+        assert_eq!(debugger.cpu.r2, 0);
+
+        assert_eq!(
+            debugger.run_file("assert [03000000] == 7"),
+            Err(ExecErr::AssertionFailed(
+                "[03000000] == 00000007, got 00000006".into()
+            ))
+        );
+
+        // malformed assertions fail the script too
+        let usage = "Usage: assert <reg>|[hexaddr] == <hexvalue>";
+        for (script, msg) in [
+            ("assert r0", usage.to_string()),
+            ("assert r0 = 6", usage.to_string()),
+            ("assert r0 == xyz", "Invalid hex value 'xyz'".into()),
+            ("assert [xyz] == 1", "Invalid hex address 'xyz'".into()),
+            ("assert foo == 1", "Unknown register 'foo'".into()),
+        ] {
+            assert_eq!(
+                debugger.run_file(script),
+                Err(ExecErr::InvalidAssertion(msg)),
+                "{script}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_repl_lines() {
+        let rom = arm_rom(&[
+            0xE3A00005, // mov r0, #5
+            0xE2800001, // add r0, r0, #1
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        // failed or invalid assertions don't stop the repl, it returns at the end of the input
+        let input = "n\nassert r0 == 6\nassert r0\n\nn\nset r2 1\n";
+        debugger.repl_lines(io::Cursor::new(input)).unwrap();
+        assert_eq!(debugger.cpu.r0, 6);
+        assert_eq!(debugger.cpu.r2, 1);
+        assert_eq!(debugger.cpu.pc, 0x08000008);
+    }
+
+    #[test]
+    fn test_mode() {
+        let rom = arm_rom(&[
+            0x20052001, // mov r0, #1; mov r0, #5 as Thumb
+        ]);
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger.initialize(&rom).unwrap();
+        debugger.run_file("mode thumb").unwrap();
+        assert!(debugger.cpu.is_thumb());
+        assert_eq!(
+            debugger.disassembly(0x08000000, 2),
+            ">08000000: 2001       MOV r0, #0x1\n 08000002: 2005       MOV r0, #0x5\n"
+        );
+
+        debugger.run_file("mode arm\nmode wide").unwrap();
+        assert!(!debugger.cpu.is_thumb());
+    }
+
+    #[test]
+    fn test_set_register_invalid() {
+        let mut debugger = Debugger::new(Cpu::new());
+        debugger
+            .run_file("set r3\nset r3 xyz\nset foo 1\nset r3 1 2")
+            .unwrap();
+        assert_eq!(debugger.cpu.r3, 0);
     }
 }
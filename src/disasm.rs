@@ -0,0 +1,446 @@
+use crate::{
+    cpu::StepInfo,
+    instr::{
+        arm::{AluOp, Condition, Instruction, Sdt},
+        common::{ExecErr, Register},
+        thumb::{
+            ThumbAddSub, ThumbAluOp, ThumbBranchOp, ThumbHiRegOp, ThumbInstr, ThumbLshOp,
+            ThumbLsiOp, ThumbMcasOp, ThumbMlsOp, ThumbMultLSOp, ThumbPushPopOp, ThumbRegShiftOp,
+        },
+        DecodedInstr,
+    },
+};
+
+fn reg(register: Register) -> &'static str {
+    match register {
+        Register::R0 => "r0",
+        Register::R1 => "r1",
+        Register::R2 => "r2",
+        Register::R3 => "r3",
+        Register::R4 => "r4",
+        Register::R5 => "r5",
+        Register::R6 => "r6",
+        Register::R7 => "r7",
+        Register::R8 => "r8",
+        Register::R9 => "r9",
+        Register::R10 => "r10",
+        Register::R11 => "r11",
+        Register::R12 => "r12",
+        Register::R13 => "sp",
+        Register::R14 => "lr",
+        Register::R15 => "pc",
+    }
+}
+
+fn reg_list(rlist: &[Register]) -> String {
+    let names: Vec<&str> = rlist.iter().map(|r| reg(*r)).collect();
+    format!("{{{}}}", names.join(", "))
+}
+
+/// Suffix of the condition, empty for always
+fn condition(condition: &Condition) -> &'static str {
+    match condition {
+        Condition::Eq => "EQ",
+        Condition::Ne => "NE",
+        Condition::Cs => "CS",
+        Condition::Cc => "CC",
+        Condition::Mi => "MI",
+        Condition::Pl => "PL",
+        Condition::Vs => "VS",
+        Condition::Vc => "VC",
+        Condition::Hi => "HI",
+        Condition::Ls => "LS",
+        Condition::Ge => "GE",
+        Condition::Lt => "LT",
+        Condition::Gt => "GT",
+        Condition::Le => "LE",
+        Condition::Al => "",
+        Condition::Nv => "NV",
+    }
+}
+
+/// Branch target relative to the address of the branch instruction
+fn relative(offset: i32) -> String {
+    if offset < 0 {
+        format!("$-{:#x}", -offset)
+    } else {
+        format!("$+{offset:#x}")
+    }
+}
+
+/// Register operand with an immediate shift, `rm, lsl #2`
+fn shifted_reg(operand: u32) -> String {
+    let rm = reg(Register::from(operand & 0b1111));
+    let shift = ["lsl", "lsr", "asr", "ror"][((operand >> 5) & 0b11) as usize];
+
+    if (operand >> 4) & 1 == 1 {
+        let rs = reg(Register::from((operand >> 8) & 0b1111));
+        return format!("{rm}, {shift} {rs}");
+    }
+
+    let amount = (operand >> 7) & 0b11111;
+    match (shift, amount) {
+        ("lsl", 0) => rm.into(),
+        ("ror", 0) => format!("{rm}, rrx"),
+        // lsr and asr #0 are encoded as #32
+        (_, 0) => format!("{rm}, {shift} #32"),
+        _ => format!("{rm}, {shift} #{amount}"),
+    }
+}
+
+fn alu_mnemonic(op: &AluOp) -> &'static str {
+    match op {
+        AluOp::And => "AND",
+        AluOp::Eor => "EOR",
+        AluOp::Sub => "SUB",
+        AluOp::Rsb => "RSB",
+        AluOp::Add => "ADD",
+        AluOp::Adc => "ADC",
+        AluOp::Sbc => "SBC",
+        AluOp::Rsc => "RSC",
+        AluOp::Tst => "TST",
+        AluOp::Teq => "TEQ",
+        AluOp::Cmp => "CMP",
+        AluOp::Cmn => "CMN",
+        AluOp::Orr => "ORR",
+        AluOp::Mov => "MOV",
+        AluOp::Bic => "BIC",
+        AluOp::Mvn => "MVN",
+    }
+}
+
+/// Render the instruction executed by `Cpu::step` as assembly without decoding it again
+pub fn disassemble_step(step: &StepInfo) -> String {
+    match &step.instr {
+        DecodedInstr::Arm(instr) => format_arm(instr.clone(), step.opcode),
+        DecodedInstr::Thumb(instr) => format_thumb(instr.clone()),
+        DecodedInstr::Undefined { thumb: false } => format!(".word {:#010x}", step.opcode),
+        DecodedInstr::Undefined { thumb: true } => format!(".hword {:#06x}", step.opcode),
+    }
+}
+
+/// Render ARM instruction `word` as assembly
+pub fn disassemble_arm(word: u32) -> String {
+    match Instruction::try_from(word) {
+        Ok(instr) => format_arm(instr, word),
+        Err(_) => format!(".word {word:#010x}"),
+    }
+}
+
+/// Addressing mode of a single data transfer, like `[r0, #0x4]!`
+fn sdt_address(sdt: &Sdt) -> String {
+    let sign = if sdt.up { "" } else { "-" };
+    let offset = if sdt.immediate {
+        (sdt.operand != 0).then(|| format!("#{sign}{:#x}", sdt.operand))
+    } else {
+        Some(format!("{sign}{}", shifted_reg(sdt.operand)))
+    };
+
+    let rn = reg(sdt.rn);
+    match (offset, sdt.pre) {
+        (None, _) => format!("[{rn}]"),
+        (Some(offset), true) => {
+            let writeback = if sdt.tw { "!" } else { "" };
+            format!("[{rn}, {offset}]{writeback}")
+        }
+        (Some(offset), false) => format!("[{rn}], {offset}"),
+    }
+}
+
+/// Render decoded ARM instruction `instr` of `word` as assembly
+fn format_arm(instr: Instruction, word: u32) -> String {
+    match instr {
+        Instruction::Branch(b) => {
+            let mnemonic = if b.is_link { "BL" } else { "B" };
+            // sign extend the 24 bit offset and multiply it by 4
+            let offset = (((b.nn << 8) as i32) >> 6) + 8;
+            format!("{mnemonic}{} {}", condition(&b.condition), relative(offset))
+        }
+        Instruction::BranchLinkExchange(b) => format!("BLX {}", relative(b.offset + 8)),
+        Instruction::BranchExchange(b) => {
+            format!("BX{} {}", condition(&b.condition), reg(b.rn))
+        }
+        Instruction::Alu(alu) => {
+            let cond = condition(&alu.condition);
+            let op2 = if alu.immediate {
+                let rors = (alu.operand >> 8) & 0b1111;
+                let nn = (alu.operand & 0xff).rotate_right(rors * 2);
+                format!("#{nn:#x}")
+            } else {
+                shifted_reg(alu.operand)
+            };
+
+            let mnemonic = alu_mnemonic(&alu.op);
+            match alu.op {
+                // compare instructions always set the flags
+                AluOp::Tst | AluOp::Teq | AluOp::Cmp | AluOp::Cmn => {
+                    format!("{mnemonic}{cond} {}, {op2}", reg(alu.rn))
+                }
+                AluOp::Mov | AluOp::Mvn => {
+                    let s = if alu.s { "S" } else { "" };
+                    format!("{mnemonic}{cond}{s} {}, {op2}", reg(alu.rd))
+                }
+                _ => {
+                    let s = if alu.s { "S" } else { "" };
+                    format!(
+                        "{mnemonic}{cond}{s} {}, {}, {op2}",
+                        reg(alu.rd),
+                        reg(alu.rn)
+                    )
+                }
+            }
+        }
+        Instruction::Sdt(sdt) => {
+            let mnemonic = if sdt.load_memory { "LDR" } else { "STR" };
+            let b = if sdt.bit { "B" } else { "" };
+            format!(
+                "{mnemonic}{}{b} {}, {}",
+                condition(&sdt.condition),
+                reg(sdt.rd),
+                sdt_address(&sdt)
+            )
+        }
+        Instruction::Pld(sdt) => format!("PLD {}", sdt_address(&sdt)),
+        Instruction::Swap(swap) => {
+            let b = if swap.byte { "B" } else { "" };
+            format!(
+                "SWP{}{b} {}, {}, [{}]",
+                condition(&swap.condition),
+                reg(swap.rd),
+                reg(swap.rm),
+                reg(swap.rn)
+            )
+        }
+        Instruction::Swi(swi) => {
+            format!("SWI{} #{:#x}", condition(&swi.condition), swi.comment)
+        }
+        Instruction::Psr => {
+            let cond = condition(&Condition::from(word >> 28));
+            let psr = if (word >> 22) & 1 == 1 {
+                "spsr"
+            } else {
+                "cpsr"
+            };
+            if (word >> 21) & 1 == 0 {
+                format!(
+                    "MRS{cond} {}, {psr}",
+                    reg(Register::from((word >> 12) & 0b1111))
+                )
+            } else if (word >> 25) & 1 == 1 {
+                let rors = (word >> 8) & 0b1111;
+                let nn = (word & 0xff).rotate_right(rors * 2);
+                format!("MSR{cond} {psr}, #{nn:#x}")
+            } else {
+                format!("MSR{cond} {psr}, {}", reg(Register::from(word & 0b1111)))
+            }
+        }
+    }
+}
+
+/// Render Thumb instruction `half` as assembly.
+/// The first half of a long branch is rendered on its own, use
+/// [`disassemble_thumb_long`] to render both halves together.
+pub fn disassemble_thumb(half: u16) -> String {
+    match ThumbInstr::try_from(half) {
+        Ok(instr) => format_thumb(instr),
+        Err(ExecErr::LongInstruction) => {
+            // upper 11 bits of the 22 bit offset
+            let offset = ((((half as u32) & 0x7ff) << 21) as i32) >> 9;
+            format!("BL {} (first half)", relative(offset + 4))
+        }
+        Err(_) => format!(".hword {half:#06x}"),
+    }
+}
+
+fn format_thumb(instr: ThumbInstr) -> String {
+    match instr {
+        ThumbInstr::Mls(mls) => match mls.op {
+            ThumbMlsOp::Ldr => {
+                format!("LDR {}, [{}, #{:#x}]", reg(mls.rd), reg(mls.rb), mls.nn)
+            }
+        },
+        ThumbInstr::Alu(alu) => {
+            let mnemonic = match alu.op {
+                ThumbAluOp::Cmp => "CMP",
+                ThumbAluOp::Bic => "BIC",
+                ThumbAluOp::Ror => "ROR",
+                ThumbAluOp::Neg => "NEG",
+                ThumbAluOp::Mul => "MUL",
+            };
+            format!("{mnemonic} {}, {}", reg(alu.rd), reg(alu.rs))
+        }
+        ThumbInstr::Lsi(lsi) => {
+            let (mnemonic, offset) = match lsi.op {
+                ThumbLsiOp::Str => ("STR", lsi.nn * 4),
+                ThumbLsiOp::Ldr => ("LDR", lsi.nn * 4),
+                ThumbLsiOp::Strb => ("STRB", lsi.nn),
+                ThumbLsiOp::Ldrb => ("LDRB", lsi.nn),
+            };
+            format!(
+                "{mnemonic} {}, [{}, #{offset:#x}]",
+                reg(lsi.rd),
+                reg(lsi.rb)
+            )
+        }
+        ThumbInstr::Lsh(lsh) => {
+            let mnemonic = match lsh.op {
+                ThumbLshOp::Strh => "STRH",
+                ThumbLshOp::Ldrh => "LDRH",
+            };
+            format!(
+                "{mnemonic} {}, [{}, #{:#x}]",
+                reg(lsh.rd),
+                reg(lsh.rb),
+                lsh.nn * 2
+            )
+        }
+        ThumbInstr::HiReg(hireg) => match hireg.op {
+            ThumbHiRegOp::Bx => format!("BX {}", reg(hireg.rs)),
+            ThumbHiRegOp::Mov => format!("MOV {}, {}", reg(hireg.rd), reg(hireg.rs)),
+            ThumbHiRegOp::Nop => "NOP".into(),
+        },
+        ThumbInstr::Mcas(mcas) => {
+            let mnemonic = match mcas.op {
+                ThumbMcasOp::Mov => "MOV",
+                ThumbMcasOp::Cmp => "CMP",
+                ThumbMcasOp::Add => "ADD",
+                ThumbMcasOp::Sub => "SUB",
+            };
+            format!("{mnemonic} {}, #{:#x}", reg(mcas.rd), mcas.nn)
+        }
+        ThumbInstr::AddSub(addsub) => match addsub {
+            ThumbAddSub::Addr(r) => format!("ADD {}, {}, {}", reg(r.rd), reg(r.rs), reg(r.rn)),
+            ThumbAddSub::Subr(r) => format!("SUB {}, {}, {}", reg(r.rd), reg(r.rs), reg(r.rn)),
+            ThumbAddSub::Addi(i) => format!("ADD {}, {}, #{:#x}", reg(i.rd), reg(i.rs), i.nn),
+            ThumbAddSub::Subi(i) => format!("SUB {}, {}, #{:#x}", reg(i.rd), reg(i.rs), i.nn),
+        },
+        ThumbInstr::MultLS(mult) => {
+            let mnemonic = match mult.op {
+                ThumbMultLSOp::Stmia => "STMIA",
+                ThumbMultLSOp::Ldmia => "LDMIA",
+            };
+            format!("{mnemonic} {}!, {}", reg(mult.rb), reg_list(&mult.rlist))
+        }
+        ThumbInstr::Branch(branch) => {
+            let mnemonic = match branch.op {
+                ThumbBranchOp::Beq => "BEQ",
+                ThumbBranchOp::Bne => "BNE",
+                ThumbBranchOp::Bcs => "BCS",
+                ThumbBranchOp::Bhi => "BHI",
+            };
+            format!("{mnemonic} {}", relative(branch.offset as i32 * 2 + 4))
+        }
+        ThumbInstr::Swi(swi) => format!("SWI #{:#x}", swi.comment),
+        ThumbInstr::UBranch(ubranch) => {
+            format!("B {}", relative(ubranch.offset as i32 + 4))
+        }
+        ThumbInstr::PushPop(pushpop) => {
+            let mnemonic = match pushpop.op {
+                ThumbPushPopOp::Push => "PUSH",
+                ThumbPushPopOp::Pop => "POP",
+            };
+            format!("{mnemonic} {}", reg_list(&pushpop.rlist))
+        }
+        ThumbInstr::LongBranch(long) => format!("BL {}", relative(long.target + 4)),
+        ThumbInstr::RegShift(shift) => {
+            let mnemonic = match shift.op {
+                ThumbRegShiftOp::Lsl => "LSL",
+                ThumbRegShiftOp::Lsr => "LSR",
+                ThumbRegShiftOp::Asr => "ASR",
+            };
+            format!(
+                "{mnemonic} {}, {}, #{}",
+                reg(shift.rd),
+                reg(shift.rs),
+                shift.nn
+            )
+        }
+    }
+}
+
+/// Render the Thumb long branch made of halves `first` and `second` as assembly
+pub fn disassemble_thumb_long(first: u16, second: u16) -> String {
+    match ThumbInstr::try_from_long(first, second) {
+        Ok(instr) => format_thumb(instr),
+        Err(_) => format!(".hword {first:#06x}, {second:#06x}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble_arm() {
+        for (word, expected) in [
+            (0xE3A00005, "MOV r0, #0x5"),
+            (0xE3A01403, "MOV r1, #0x3000000"),
+            (0xE2810004, "ADD r0, r1, #0x4"),
+            (0xE0910102, "ADDS r0, r1, r2, lsl #2"),
+            (0x13520000, "CMPNE r2, #0x0"),
+            (0xE5810000, "STR r0, [r1]"),
+            (0xE5932008, "LDR r2, [r3, #0x8]"),
+            (0xE5532004, "LDRB r2, [r3, #-0x4]"),
+            (0xE4932004, "LDR r2, [r3], #0x4"),
+            (0xE5B32004, "LDR r2, [r3, #0x4]!"),
+            (0xEA000001, "B $+0xc"),
+            (0xEAFFFFFE, "B $+0x0"),
+            (0x0AFFFFFC, "BEQ $-0x8"),
+            (0xEB000000, "BL $+0x8"),
+            (0xE12FFF11, "BX r1"),
+            (0xFA000002, "BLX $+0x10"),
+            (0xFB000002, "BLX $+0x12"),
+            (0xF5D1F004, "PLD [r1, #0x4]"),
+            (0xF3A00005, ".word 0xf3a00005"),
+            (0xE10F0000, "MRS r0, cpsr"),
+            (0xE1010092, "SWP r0, r2, [r1]"),
+            (0xE1410092, "SWPB r0, r2, [r1]"),
+            (0xEF060000, "SWI #0x60000"),
+            (0xE8BD0003, ".word 0xe8bd0003"),
+        ] {
+            assert_eq!(disassemble_arm(word), expected, "{word:08X}");
+        }
+    }
+
+    #[test]
+    fn test_disassemble_thumb() {
+        for (half, expected) in [
+            (0x2001, "MOV r0, #0x1"),
+            (0x3105, "ADD r1, #0x5"),
+            (0x1888, "ADD r0, r1, r2"),
+            (0x1E48, "SUB r0, r1, #0x1"),
+            (0x4770, "BX lr"),
+            (0x4674, "MOV r4, lr"),
+            (0x46C0, "NOP"),
+            (0xB5F8, "PUSH {r3, r4, r5, r6, r7, lr}"),
+            (0xBC08, "POP {r3}"),
+            (0xE7FE, "B $+0x0"),
+            (0xD0FC, "BEQ $-0x4"),
+            (0x4801, "LDR r0, [pc, #0x4]"),
+            (0x6048, "STR r0, [r1, #0x4]"),
+            (0x6888, "LDR r0, [r1, #0x8]"),
+            (0x7048, "STRB r0, [r1, #0x1]"),
+            (0x7fc8, "LDRB r0, [r1, #0x1f]"),
+            (0x8048, "STRH r0, [r1, #0x2]"),
+            (0x8fc8, "LDRH r0, [r1, #0x3e]"),
+            (0x0088, "LSL r0, r1, #2"),
+            (0xC103, "STMIA r1!, {r0, r1}"),
+            (0x4388, "BIC r0, r1"),
+            (0xDF06, "SWI #0x6"),
+            (0xDE00, ".hword 0xde00"),
+            (0xF000, "BL $+0x4 (first half)"),
+        ] {
+            assert_eq!(disassemble_thumb(half), expected, "{half:04X}");
+        }
+    }
+
+    #[test]
+    fn test_disassemble_thumb_long() {
+        assert_eq!(disassemble_thumb_long(0xF000, 0xF804), "BL $+0xc");
+        assert_eq!(disassemble_thumb_long(0xF7FF, 0xFFFA), "BL $-0x8");
+        assert_eq!(
+            disassemble_thumb_long(0xF000, 0x2001),
+            ".hword 0xf000, 0x2001"
+        );
+    }
+}
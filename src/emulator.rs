@@ -0,0 +1,90 @@
+//! Emulation loop without a front-end, runs the cpu frame by frame
+
+use std::time::Instant;
+
+use crate::{cpu::Cpu, instr::common::EResult, stats::Stats, video::Video};
+
+/// Cpu and its screen, steps the emulation one frame at a time.
+/// Front-ends call `run_frame` in a loop and show the returned frame.
+pub struct Emulator {
+    video: Video,
+    /// RGBA pixels of the last rendered frame
+    framebuffer: Vec<u8>,
+    /// End of the last frame, the stats count the time between frames
+    last_frame: Option<Instant>,
+}
+
+impl Emulator {
+    pub fn new(cpu: Cpu) -> Self {
+        Self {
+            video: Video::new(cpu),
+            framebuffer: Vec::new(),
+            last_frame: None,
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        self.video.cpu()
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        self.video.cpu_mut()
+    }
+
+    /// Emulation speed of the frames run so far
+    pub fn stats(&self) -> &Stats {
+        self.cpu().stats()
+    }
+
+    /// Run the cpu until the start of the next frame and render it.
+    /// Returns the RGBA pixels of the frame, 4 bytes per pixel in row-major order.
+    pub fn run_frame(&mut self) -> EResult<&[u8]> {
+        let start = *self.last_frame.get_or_insert_with(Instant::now);
+        self.video.cpu_mut().run_frame()?;
+        self.framebuffer = self.video.framebuffer();
+        let end = Instant::now();
+        self.video.cpu_mut().add_run_time(end - start);
+        self.last_frame = Some(end);
+        Ok(&self.framebuffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::BootConfig, lcd::FRAME_CYCLES, test_rom::arm_rom};
+
+    #[test]
+    fn test_run_frame_mode3() {
+        let rom = arm_rom(&[
+            0xE3A00301, // mov r0, #0x04000000
+            0xE3A01B01, // mov r1, #0x400
+            0xE2811003, // add r1, r1, #3
+            0xE5801000, // str r1, [r0]
+            0xE3A00406, // mov r0, #0x06000000
+            0xE3A0101F, // mov r1, #0x1F
+            0xE5801000, // str r1, [r0]
+            0xEAFFFFFE, // b .
+        ]);
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom, BootConfig::default()).unwrap();
+
+        let mut emulator = Emulator::new(cpu);
+        let frame = emulator.run_frame().unwrap();
+        assert_eq!(frame.len(), 240 * 160 * 4);
+        // red pixel in the top left corner, the rest is black
+        assert_eq!(frame[0..4], [0xFF, 0, 0, 0xFF]);
+        assert!(frame[4..].chunks(4).all(|pixel| pixel == [0, 0, 0, 0xFF]));
+        assert!(emulator.cpu().is_halted());
+        // the LCD keeps going until the end of the frame while halted
+        assert_eq!(emulator.cpu().cycles(), FRAME_CYCLES);
+        assert_eq!(emulator.stats().frames, 1);
+        assert_eq!(emulator.stats().instructions, 8);
+
+        // time the front-end spends between frames counts too
+        let before = emulator.stats().elapsed;
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        emulator.run_frame().unwrap();
+        assert!(emulator.stats().elapsed - before >= std::time::Duration::from_millis(10));
+    }
+}
@@ -1,3 +1,13 @@
+use std::fmt::Display;
+
+use crate::instr::common::{EResult, ExecErr};
+
+/// Size of the cartridge header at the start of the ROM
+pub const HEADER_SIZE: usize = 0xE4;
+
+/// Value of the fixed byte at 0xB2 of a valid header
+const FIXED_VALUE: u8 = 0x96;
+
 #[derive(Debug, Default)]
 #[repr(C)]
 pub struct GBAHeader {
@@ -20,6 +30,10 @@ pub struct GBAHeader {
     /// Game title (max 12 characters)
     pub game_title: String,
 
+    /// Unmodified bytes of the game title, since `game_title` is lossy
+    /// on titles that are not valid UTF-8
+    pub game_title_raw: Vec<u8>,
+
     /// char game_code[0x04] @ 0xAC
     ///
     /// Game code (4 characters)
@@ -33,7 +47,7 @@ pub struct GBAHeader {
     /// u8 fixed_value @ 0xB2
     ///
     /// Fixed value, must be 0x96
-    fixed_value: u8,
+    pub fixed_value: u8,
 
     /// u8 main_unit_code @ 0xB3
     ///
@@ -57,7 +71,7 @@ pub struct GBAHeader {
 
     /// u8 complement_check @ 0xBD;
     ///
-    /// complement check TODO:
+    /// Header checksum, -(0x19 + sum of bytes 0xA0..=0xBC) & 0xFF
     complement_check: u8,
 
     /// u8 reserved_area2[0x02] @ 0xBE
@@ -116,6 +130,92 @@ pub struct GBAHeader {
     /// (either one using 32bit ARM code). Or, if you are not intending to support
     /// joybus mode (which is probably rarely used), ignore this entry.
     pub joy_entry_point: u32,
+
+    /// Backup memory type detected from the ID string in the ROM,
+    /// not a part of the header itself
+    pub save_type: SaveType,
+
+    /// Does the complement check match the header
+    complement_valid: bool,
+}
+
+/// Cartridge backup memory used for saving the game
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SaveType {
+    /// No backup memory or it couldn't be detected
+    #[default]
+    None,
+    /// 512 bytes or 8KB of serial EEPROM
+    Eeprom,
+    /// 32KB of battery backed SRAM
+    Sram,
+    /// 64KB of Flash
+    Flash64K,
+    /// 128KB of Flash in two 64KB banks
+    Flash128K,
+}
+
+/// ID strings the save libraries leave into the ROM, always word aligned
+const SAVE_TYPE_IDS: [(&[u8], SaveType); 5] = [
+    (b"EEPROM_V", SaveType::Eeprom),
+    (b"SRAM_V", SaveType::Sram),
+    (b"FLASH_V", SaveType::Flash64K),
+    (b"FLASH512_V", SaveType::Flash64K),
+    (b"FLASH1M_V", SaveType::Flash128K),
+];
+
+/// Find the save type of the ROM `bytes` from the ID string of its save library
+pub fn detect_save_type(bytes: &[u8]) -> SaveType {
+    for offset in (0..bytes.len()).step_by(4) {
+        // every ID starts with one of these, skip the rest quickly
+        if !matches!(bytes[offset], b'E' | b'S' | b'F') {
+            continue;
+        }
+
+        let data = &bytes[offset..];
+        for (id, save_type) in SAVE_TYPE_IDS {
+            if data.starts_with(id) {
+                return save_type;
+            }
+        }
+    }
+
+    SaveType::None
+}
+
+impl Display for GBAHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let validity = |valid| if valid { "valid" } else { "invalid" };
+        writeln!(f, "GBAHeader {{")?;
+        writeln!(f, "    title: {},", self.game_title)?;
+        writeln!(f, "    game_code: {},", self.game_code)?;
+        writeln!(f, "    maker_code: {},", self.maker_code)?;
+        writeln!(f, "    version: {},", self.software_version)?;
+        writeln!(f, "    entry_point: 0x{:08x},", self.rom_entry_point)?;
+        writeln!(f, "    save_type: {:?},", self.save_type)?;
+        writeln!(
+            f,
+            "    fixed_value: 0x{:02x} ({}),",
+            self.fixed_value,
+            validity(self.fixed_value == FIXED_VALUE)
+        )?;
+        writeln!(
+            f,
+            "    complement_check: 0x{:02x} ({}),",
+            self.complement_check,
+            validity(self.complement_valid)
+        )?;
+        writeln!(f, "}}")?;
+        Ok(())
+    }
+}
+
+/// Interpret header characters as a string, replacing invalid UTF-8
+/// and removing the trailing NUL padding
+fn header_string(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_end_matches('\0')
+        .to_string()
 }
 
 impl GBAHeader {
@@ -128,23 +228,20 @@ impl GBAHeader {
     }
 
     fn add_game_title(&mut self, data: &[u8]) {
-        self.game_title =
-            String::from_utf8(data[0xA0..0xA0 + 12].into()).expect("Game title utf8 string");
+        self.game_title_raw = data[0xA0..0xA0 + 12].into();
+        self.game_title = header_string(&self.game_title_raw);
     }
 
     fn add_game_code(&mut self, data: &[u8]) {
-        self.game_code =
-            String::from_utf8(data[0xAC..0xAC + 4].into()).expect("Game code utf8 string");
+        self.game_code = header_string(&data[0xAC..0xAC + 4]);
     }
 
     fn add_maker_code(&mut self, data: &[u8]) {
-        self.maker_code =
-            String::from_utf8(data[0xB0..0xB0 + 2].into()).expect("Game maker utf8 string");
+        self.maker_code = header_string(&data[0xB0..0xB0 + 2]);
     }
 
     fn add_fixed_value(&mut self, data: &[u8]) {
         self.fixed_value = data[0xB2];
-        assert_eq!(self.fixed_value, 0x96);
     }
 
     fn add_main_unit_code(&mut self, data: &[u8]) {
@@ -160,10 +257,22 @@ impl GBAHeader {
     }
 
     fn add_complement_check(&mut self, data: &[u8]) {
-        // TODO: do the check
         self.complement_check = data[0xBD];
     }
 
+    /// Calculate the header checksum from the bytes 0xA0..=0xBC
+    fn calculate_complement(data: &[u8]) -> u8 {
+        let sum = data[0xA0..=0xBC]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        0u8.wrapping_sub(sum.wrapping_add(0x19))
+    }
+
+    /// Check that the stored complement check matches the header in `bytes`
+    pub fn verify(&self, bytes: &[u8]) -> bool {
+        Self::calculate_complement(bytes) == self.complement_check
+    }
+
     fn add_ram_entry_point(&mut self, data: &[u8]) {
         self.ram_entry_point = u32::from_le_bytes(data[0xC0..0xC0 + 4].try_into().unwrap());
     }
@@ -180,7 +289,13 @@ impl GBAHeader {
         self.joy_entry_point = u32::from_le_bytes(data[0xE0..0xE0 + 4].try_into().unwrap());
     }
 
-    pub fn from_file(bytes: &[u8]) -> Self {
+    /// Parse the header from the start of the ROM `bytes`. Invalid values are
+    /// kept as they are, only a ROM shorter than the header is an error.
+    pub fn from_file(bytes: &[u8]) -> EResult<Self> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(ExecErr::RomTooSmall(bytes.len()));
+        }
+
         let mut header: Self = Default::default();
 
         header.add_rom_entry_point(bytes);
@@ -197,7 +312,137 @@ impl GBAHeader {
         header.add_boot_mode(bytes);
         header.add_slave_id(bytes);
         header.add_joy_entry_point(bytes);
+        header.save_type = detect_save_type(bytes);
+        header.complement_valid = header.verify(bytes);
+
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_bytes() -> Vec<u8> {
+        let mut bytes = vec![0; 0xE4];
+        bytes[0xA0..0xA0 + 12].copy_from_slice(b"TESTGAME\0\0\0\0");
+        bytes[0xAC..0xAC + 4].copy_from_slice(b"ATST");
+        bytes[0xB0..0xB0 + 2].copy_from_slice(b"01");
+        bytes[0xB2] = 0x96;
+        bytes[0xBD] = GBAHeader::calculate_complement(&bytes);
+        bytes
+    }
+
+    #[test]
+    fn test_complement_check_valid() {
+        let bytes = header_bytes();
+        let header = GBAHeader::from_file(&bytes).unwrap();
+        assert!(header.verify(&bytes));
+    }
+
+    #[test]
+    fn test_complement_check_known_value() {
+        // all zero except the fixed value: -(0x19 + 0x96) & 0xFF
+        let mut bytes = vec![0; 0xE4];
+        bytes[0xB2] = 0x96;
+        assert_eq!(GBAHeader::calculate_complement(&bytes), 0x51);
+    }
+
+    #[test]
+    fn test_header_strings() {
+        let bytes = header_bytes();
+        let header = GBAHeader::from_file(&bytes).unwrap();
+        assert_eq!(header.game_title, "TESTGAME");
+        assert_eq!(header.game_code, "ATST");
+        assert_eq!(header.maker_code, "01");
+    }
+
+    #[test]
+    fn test_non_utf8_game_title() {
+        let mut bytes = header_bytes();
+        bytes[0xA0..0xA0 + 12].copy_from_slice(b"AB\0C\xFFD\0\0\0\0\0\0");
+        let header = GBAHeader::from_file(&bytes).unwrap();
+        assert_eq!(header.game_title, "AB\0C\u{FFFD}D");
+        assert_eq!(header.game_title_raw, b"AB\0C\xFFD\0\0\0\0\0\0");
+    }
+
+    #[test]
+    fn test_detect_save_type() {
+        let ids: [(&[u8], SaveType); 5] = [
+            (b"EEPROM_V124", SaveType::Eeprom),
+            (b"SRAM_V113", SaveType::Sram),
+            (b"FLASH_V126", SaveType::Flash64K),
+            (b"FLASH512_V131", SaveType::Flash64K),
+            (b"FLASH1M_V103", SaveType::Flash128K),
+        ];
+        for (id, save_type) in ids {
+            let mut bytes = header_bytes();
+            bytes.resize(0x400, 0);
+            bytes[0x300..0x300 + id.len()].copy_from_slice(id);
+            assert_eq!(detect_save_type(&bytes), save_type);
+            assert_eq!(GBAHeader::from_file(&bytes).unwrap().save_type, save_type);
+        }
+
+        let bytes = header_bytes();
+        assert_eq!(detect_save_type(&bytes), SaveType::None);
+        assert_eq!(
+            GBAHeader::from_file(&bytes).unwrap().save_type,
+            SaveType::None
+        );
+        // ID strings are word aligned
+        let mut bytes = vec![0; 0x20];
+        bytes[0x11..0x17].copy_from_slice(b"SRAM_V");
+        assert_eq!(detect_save_type(&bytes), SaveType::None);
+        assert_eq!(detect_save_type(b"SRAM_V"), SaveType::Sram);
+    }
+
+    #[test]
+    fn test_display() {
+        let mut bytes = header_bytes();
+        bytes[0..4].copy_from_slice(&0xEA00002Eu32.to_le_bytes());
+        bytes[0xBC] = 2;
+        bytes[0xBD] = GBAHeader::calculate_complement(&bytes);
+        let info = GBAHeader::from_file(&bytes).unwrap().to_string();
+        assert!(info.contains("title: TESTGAME,"), "{info}");
+        assert!(info.contains("game_code: ATST,"), "{info}");
+        assert!(info.contains("maker_code: 01,"), "{info}");
+        assert!(info.contains("version: 2,"), "{info}");
+        assert!(info.contains("entry_point: 0xea00002e,"), "{info}");
+        assert!(info.contains("(valid)"), "{info}");
+
+        bytes[0xA0] = b'X';
+        let info = GBAHeader::from_file(&bytes).unwrap().to_string();
+        assert!(info.contains("title: XESTGAME,"), "{info}");
+        assert!(info.contains("complement_check: 0x"), "{info}");
+        assert!(info.ends_with("(invalid),\n}\n"), "{info}");
+    }
+
+    #[test]
+    fn test_short_rom() {
+        assert_eq!(
+            GBAHeader::from_file(&[0; 4]).unwrap_err(),
+            ExecErr::RomTooSmall(4)
+        );
+        assert_eq!(
+            GBAHeader::from_file(&header_bytes()[..HEADER_SIZE - 1]).unwrap_err(),
+            ExecErr::RomTooSmall(HEADER_SIZE - 1)
+        );
+    }
+
+    #[test]
+    fn test_bad_fixed_value() {
+        let info = GBAHeader::from_file(&[0; 0x200]).unwrap().to_string();
+        assert!(info.contains("fixed_value: 0x00 (invalid),"), "{info}");
+
+        let info = GBAHeader::from_file(&header_bytes()).unwrap().to_string();
+        assert!(info.contains("fixed_value: 0x96 (valid),"), "{info}");
+    }
 
-        header
+    #[test]
+    fn test_complement_check_corrupted() {
+        let mut bytes = header_bytes();
+        bytes[0xA0] = b'X';
+        let header = GBAHeader::from_file(&bytes).unwrap();
+        assert!(!header.verify(&bytes));
     }
 }
@@ -1,6 +1,6 @@
 use super::common::{ExecErr, Register};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Condition {
     Eq,
     Ne,
@@ -44,7 +44,7 @@ impl From<u32> for Condition {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub struct Branch {
     pub condition: Condition,
@@ -66,9 +66,27 @@ impl From<u32> for Branch {
     }
 }
 
-#[derive(Debug)]
+/// BLX with an immediate offset, always switches to Thumb
+#[derive(Debug, Clone, PartialEq)]
+pub struct BranchLinkExchange {
+    /// Signed offset from PC+8 in bytes, the H bit adds a halfword
+    pub offset: i32,
+}
+
+impl From<u32> for BranchLinkExchange {
+    fn from(value: u32) -> Self {
+        // sign extend the 24 bit offset and multiply it by 4
+        let offset = ((value << 8) as i32) >> 6;
+        let halfword = ((value >> 24) & 1) as i32 * 2;
+        Self {
+            offset: offset + halfword,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
-/// BX only, since BXJ AND BLX are not supported
+/// BX only, since BXJ and BLX with a register are not supported
 pub struct BranchExchange {
     pub condition: Condition,
     pub rn: Register,
@@ -82,7 +100,7 @@ impl From<u32> for BranchExchange {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AluOp {
     And,
     Eor,
@@ -126,7 +144,7 @@ impl From<u32> for AluOp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub struct Alu {
     pub condition: Condition,
@@ -161,7 +179,7 @@ impl From<u32> for Alu {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub struct Sdt {
     pub condition: Condition,
@@ -205,25 +223,89 @@ impl From<u32> for Sdt {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+/// SWP and SWPB
+pub struct Swap {
+    pub condition: Condition,
+    /// Swap a byte instead of a word
+    pub byte: bool,
+    /// Base register
+    pub rn: Register,
+    /// Destination register
+    pub rd: Register,
+    /// Source register
+    pub rm: Register,
+}
+
+impl From<u32> for Swap {
+    fn from(value: u32) -> Self {
+        let condition = Condition::from((value >> 28) & 0b1111);
+        let byte = (value >> 22) & 0b1 == 1;
+        let rn = Register::from((value >> 16) & 0b1111);
+        let rd = Register::from((value >> 12) & 0b1111);
+        let rm = Register::from(value & 0b1111);
+
+        Self {
+            condition,
+            byte,
+            rn,
+            rd,
+            rm,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+/// SWI, software interrupt used for calling the BIOS functions
+pub struct Swi {
+    pub condition: Condition,
+    /// Comment field, the BIOS function number is in bits 16-23
+    pub comment: u32,
+}
+
+impl From<u32> for Swi {
+    fn from(value: u32) -> Self {
+        let condition = Condition::from((value >> 28) & 0b1111);
+        let comment = value & 0xffffff;
+        Self { condition, comment }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Instruction {
     Branch(Branch),
+    /// BLX with an immediate offset
+    BranchLinkExchange(BranchLinkExchange),
     BranchExchange(BranchExchange),
     Alu(Alu),
-    /// Single Data Tranfer, LDR, STR, PLD
+    /// Single Data Tranfer, LDR, STR
     Sdt(Sdt),
+    /// Preload data hint of ARMv5, decoded like a load
+    Pld(Sdt),
     /// PSR Transfer (MRS, MSR)
     Psr,
+    /// Single Data Swap, SWP, SWPB
+    Swap(Swap),
+    /// Software Interrupt
+    Swi(Swi),
 }
 
 impl TryFrom<u32> for Instruction {
     type Error = ExecErr;
 
     fn try_from(value: u32) -> Result<Self, Self::Error> {
-        if (value >> 25) & 0b111 == 0b101 {
+        if value >> 28 == 0b1111 {
+            Self::try_from_unconditional(value)
+        } else if (value >> 25) & 0b111 == 0b101 {
             Ok(Self::Branch(Branch::from(value)))
         } else if (value >> 8) & 0xfffff == 0b0001_0010_1111_1111_1111 {
             Ok(Self::BranchExchange(BranchExchange::from(value)))
+        } else if value & 0x0fb00ff0 == 0x01000090 {
+            Ok(Self::Swap(Swap::from(value)))
+        } else if (value >> 24) & 0b1111 == 0b1111 {
+            Ok(Self::Swi(Swi::from(value)))
         } else if (value >> 26) & 0b11 == 0b00 {
             let op = AluOp::from((value >> 21) & 0b1111);
             let s = (value >> 20) & 0b1;
@@ -232,6 +314,9 @@ impl TryFrom<u32> for Instruction {
             }
 
             Ok(Self::Alu(Alu::from(value)))
+        } else if value & 0x0E000010 == 0x06000010 {
+            // register offset SDT space with bit 4 set is undefined
+            Err(ExecErr::Undefined(value))
         } else if (value >> 26) & 0b01 == 0b01 {
             Ok(Self::Sdt(Sdt::from(value)))
         } else {
@@ -239,3 +324,17 @@ impl TryFrom<u32> for Instruction {
         }
     }
 }
+
+impl Instruction {
+    /// ARMv5 uses the NV condition (0b1111) for instructions that are always executed
+    /// instead of never executing them. Other encodings are undefined.
+    fn try_from_unconditional(value: u32) -> Result<Self, ExecErr> {
+        if (value >> 25) & 0b111 == 0b101 {
+            Ok(Self::BranchLinkExchange(BranchLinkExchange::from(value)))
+        } else if value & 0x0D70F000 == 0x0550F000 {
+            Ok(Self::Pld(Sdt::from(value)))
+        } else {
+            Err(ExecErr::Undefined(value))
+        }
+    }
+}
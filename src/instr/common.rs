@@ -1,12 +1,55 @@
-#[derive(Debug, PartialEq)]
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ExecErr {
     UnknownInstr(u32),
     UnknownThumbInstr(u16),
+    /// Encoding in the undefined instruction space of the architecture, unlike
+    /// the unknown instructions which can also be valid but not decoded yet
+    Undefined(u32),
     UnimplementedInstr(String),
     /// When Execution needs more bytes to be interpreted
     LongInstruction,
+    /// Thumb long branch prefix followed by something else than the BL suffix
+    InvalidLongInstr(u16, u16),
+    /// ROM of the given size doesn't fit in the cartridge address space,
+    /// or a multiboot image in EWRAM
+    RomTooLarge(usize),
+    /// ROM of the given size is too small to have the cartridge header
+    RomTooSmall(usize),
+    /// `assert` command of a debugger script didn't hold
+    AssertionFailed(String),
+    /// `assert` command of a debugger script couldn't be parsed
+    InvalidAssertion(String),
+    /// PC isn't aligned to the instruction size of the current mode
+    UnalignedPc(u32),
+}
+
+impl Display for ExecErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownInstr(instr) => write!(f, "Unknown instr {instr:08X}"),
+            Self::UnknownThumbInstr(instr) => write!(f, "Unknown instr {instr:04X}"),
+            Self::Undefined(instr) => write!(f, "Undefined instr {instr:08X}"),
+            Self::UnimplementedInstr(instr) => write!(f, "Unimplemented '{instr}'"),
+            Self::LongInstruction => write!(
+                f,
+                "Unexpected state where instruction needs more bytes to execute"
+            ),
+            Self::InvalidLongInstr(first, second) => {
+                write!(f, "Invalid long instr {first:04X} {second:04X}")
+            }
+            Self::RomTooLarge(size) => write!(f, "ROM of {size} bytes is too large"),
+            Self::RomTooSmall(size) => write!(f, "ROM of {size} bytes is too small"),
+            Self::AssertionFailed(msg) => write!(f, "Assertion failed: {msg}"),
+            Self::InvalidAssertion(msg) => write!(f, "Invalid assertion: {msg}"),
+            Self::UnalignedPc(pc) => write!(f, "Unaligned PC {pc:08X}"),
+        }
+    }
 }
 
+impl std::error::Error for ExecErr {}
+
 pub type EResult<T> = Result<T, ExecErr>;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -61,3 +104,49 @@ impl From<u16> for Register {
         (value as u32).into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exec_err_display() {
+        let errors = [
+            (ExecErr::UnknownInstr(0xE6000010), "Unknown instr E6000010"),
+            (ExecErr::UnknownThumbInstr(0xB600), "Unknown instr B600"),
+            (ExecErr::Undefined(0xE6000010), "Undefined instr E6000010"),
+            (
+                ExecErr::UnimplementedInstr("AluOp::Rsc not implemented".into()),
+                "Unimplemented 'AluOp::Rsc not implemented'",
+            ),
+            (
+                ExecErr::LongInstruction,
+                "Unexpected state where instruction needs more bytes to execute",
+            ),
+            (
+                ExecErr::InvalidLongInstr(0xF000, 0x46C0),
+                "Invalid long instr F000 46C0",
+            ),
+            (
+                ExecErr::RomTooLarge(0x2000001),
+                "ROM of 33554433 bytes is too large",
+            ),
+            (ExecErr::RomTooSmall(4), "ROM of 4 bytes is too small"),
+            (
+                ExecErr::AssertionFailed("r0 == 00000005, got 00000006".into()),
+                "Assertion failed: r0 == 00000005, got 00000006",
+            ),
+            (
+                ExecErr::InvalidAssertion("Invalid hex value 'xyz'".into()),
+                "Invalid assertion: Invalid hex value 'xyz'",
+            ),
+        ];
+        for (err, msg) in errors {
+            assert_eq!(err.to_string(), msg);
+        }
+
+        // usable as a boxed error
+        let err: Box<dyn std::error::Error> = Box::new(ExecErr::LongInstruction);
+        assert!(err.to_string().starts_with("Unexpected state"));
+    }
+}
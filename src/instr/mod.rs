@@ -1,3 +1,23 @@
+use crate::cpu::Cpu;
+use common::EResult;
+
 pub mod arm;
 pub mod common;
 pub mod thumb;
+
+/// Decoded instruction that can run on the cpu
+pub trait Executable {
+    /// Execute the instruction at PC, PC moves to the next instruction or the branch target
+    fn execute(&self, cpu: &mut Cpu) -> EResult<()>;
+}
+
+/// Decoded instruction of either instruction set
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodedInstr {
+    Arm(arm::Instruction),
+    Thumb(thumb::ThumbInstr),
+    /// Undefined encoding that took the Undefined Instruction exception
+    Undefined {
+        thumb: bool,
+    },
+}
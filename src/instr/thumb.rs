@@ -1,14 +1,20 @@
 use super::common::{EResult, ExecErr, Register};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ThumbAluOp {
     /// compare, Void = Rd - Rs
     Cmp,
     /// bit clear, Rd = Rd AND NOT Rs
     Bic,
+    /// rotate right, Rd = Rd ROR (Rs AND 0FFh)
+    Ror,
+    /// negate, Rd = 0 - Rs
+    Neg,
+    /// multiply, Rd = Rd * Rs
+    Mul,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ThumbAlu {
     pub op: ThumbAluOp,
     /// Destination register
@@ -22,9 +28,12 @@ impl TryFrom<u16> for ThumbAlu {
 
     fn try_from(value: u16) -> Result<Self, Self::Error> {
         let op = match (value >> 6) & 0b1111 {
-            0xe => ThumbAluOp::Bic,
+            0x7 => ThumbAluOp::Ror,
+            0x9 => ThumbAluOp::Neg,
             0xa => ThumbAluOp::Cmp,
-            _ => unreachable!(),
+            0xd => ThumbAluOp::Mul,
+            0xe => ThumbAluOp::Bic,
+            _ => return Err(ExecErr::UnknownThumbInstr(value)),
         };
 
         let rs = Register::from((value >> 3) & 0b111);
@@ -33,16 +42,22 @@ impl TryFrom<u16> for ThumbAlu {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ThumbLsiOp {
-    /// Rd,[Rb,Ro] ;store 32bit data  WORD[Rb+Ro] = Rd
+    /// Rd,[Rb,#nn] ;store 32bit data   WORD[Rb+nn*4] = Rd
     Str,
+    /// Rd,[Rb,#nn] ;load  32bit data   Rd = WORD[Rb+nn*4]
+    Ldr,
+    /// Rd,[Rb,#nn] ;store  8bit data   BYTE[Rb+nn] = Rd
+    Strb,
+    /// Rd,[Rb,#nn] ;load   8bit data   Rd = BYTE[Rb+nn]
+    Ldrb,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ThumbLsi {
     pub op: ThumbLsiOp,
-    /// Unsigned offset
+    /// Unsigned offset in words for Str/Ldr and in bytes for Strb/Ldrb
     pub nn: u16,
     /// Base Register
     pub rb: Register,
@@ -56,7 +71,9 @@ impl TryFrom<u16> for ThumbLsi {
     fn try_from(value: u16) -> Result<Self, Self::Error> {
         let op = match (value >> 11) & 0b11 {
             0 => ThumbLsiOp::Str,
-            _ => unreachable!(),
+            1 => ThumbLsiOp::Ldr,
+            2 => ThumbLsiOp::Strb,
+            _ => ThumbLsiOp::Ldrb,
         };
 
         let nn = (value >> 6) & 0b11111;
@@ -66,17 +83,19 @@ impl TryFrom<u16> for ThumbLsi {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ThumbLshOp {
-    /// Rd,[Rb,Ro] ;store 32bit data  WORD[Rb+Ro] = Rd
+    /// Rd,[Rb,#nn] ;store 16bit data   HALFWORD[Rb+nn*2] = Rd
     Strh,
+    /// Rd,[Rb,#nn] ;load  16bit data   Rd = HALFWORD[Rb+nn*2]
+    Ldrh,
 }
 
 /// THUMB.10: load/store halfword
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ThumbLsh {
     pub op: ThumbLshOp,
-    /// Unsigned offset
+    /// Unsigned offset in halfwords
     pub nn: u16,
     /// Base Register
     pub rb: Register,
@@ -88,9 +107,9 @@ impl TryFrom<u16> for ThumbLsh {
     type Error = ExecErr;
 
     fn try_from(value: u16) -> Result<Self, Self::Error> {
-        let op = match (value >> 11) & 0b11 {
+        let op = match (value >> 11) & 0b1 {
             0 => ThumbLshOp::Strh,
-            _ => unreachable!(),
+            _ => ThumbLshOp::Ldrh,
         };
 
         let nn = (value >> 6) & 0b11111;
@@ -100,7 +119,7 @@ impl TryFrom<u16> for ThumbLsh {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ThumbHiRegOp {
     /// BX  Rs ;jump PC = Rs ;may switch THUMB/ARM
     Bx,
@@ -111,7 +130,7 @@ pub enum ThumbHiRegOp {
 }
 
 /// THUMB.5: Hi register operations/branch exchange
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ThumbHiReg {
     pub op: ThumbHiRegOp,
     /// Destination register
@@ -143,23 +162,24 @@ impl TryFrom<u16> for ThumbHiReg {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ThumbMlsOp {
     Ldr,
 }
 
 /// Thumb mode memory load/store
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ThumbMls {
     pub op: ThumbMlsOp,
     /// Destination register
     pub rd: Register,
-    /// Base register
+    /// Base register, R15 for the PC-relative literal load
     pub rb: Register,
+    /// Unsigned byte offset
     pub nn: u16,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ThumbRegShiftOp {
     /// logical/arithmetic shift left
     Lsl,
@@ -169,7 +189,7 @@ pub enum ThumbRegShiftOp {
     Asr,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ThumbRegShift {
     pub op: ThumbRegShiftOp,
     /// Destination register
@@ -198,7 +218,7 @@ impl TryFrom<u16> for ThumbRegShift {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ThumbPushPopOp {
     /// store in memory, decrements SP (R13)
     Push,
@@ -207,7 +227,7 @@ pub enum ThumbPushPopOp {
 }
 
 /// THUMB.14: push/pop registers
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ThumbPushPop {
     pub op: ThumbPushPopOp,
     /// Register list
@@ -245,7 +265,7 @@ impl TryFrom<u16> for ThumbPushPop {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ThumbBranchOp {
     /// BEQ label ;Z=1 ;equal (zero) (same)
     Beq,
@@ -258,7 +278,7 @@ pub enum ThumbBranchOp {
 }
 
 /// THUMB.16: conditional branch and THUMB.18: unconditional branch
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ThumbBranch {
     pub op: ThumbBranchOp,
     /// Signed Offset, step 2 ($+4-256..$+4+254)
@@ -274,19 +294,29 @@ impl TryFrom<u16> for ThumbBranch {
             0x1 => ThumbBranchOp::Bne,
             0x2 => ThumbBranchOp::Bcs,
             0x8 => ThumbBranchOp::Bhi,
-            _ => unreachable!(),
+            // the AL condition is undefined, 0xF is SWI
+            0xE => return Err(ExecErr::Undefined(value as u32)),
+            _ => return Err(ExecErr::UnknownThumbInstr(value)),
         };
 
-        // Hacky way to get the value as unsigned
+        // sign extend the 8 bit offset
         let offset = (value & 0xff) as i8 as i16;
 
         Ok(Self { op, offset })
     }
 }
 
+/// THUMB.17: software interrupt
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThumbSwi {
+    /// BIOS function number
+    pub comment: u8,
+}
+
 /// THUMB.18: unconditional branch
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ThumbUBranch {
+    /// Signed offset in bytes, step 2 ($+4-2048..$+4+2046)
     pub offset: i16,
 }
 
@@ -294,27 +324,22 @@ impl TryFrom<u16> for ThumbUBranch {
     type Error = ExecErr;
 
     fn try_from(value: u16) -> Result<Self, Self::Error> {
-        let offset = (value & 0x3ff) as i16;
-        let offset = if value & 0x200 == 0x200 {
-            -(((offset) ^ 0x3ff) + 1)
-        } else {
-            offset
-        };
-
+        // sign extend the 11 bit halfword offset
+        let offset = (((value & 0x7ff) << 5) as i16 >> 5) << 1;
         Ok(Self { offset })
     }
 }
 
 /// THUMB.19: long branch with link
 /// Assumes that opcode is always BL, and BLX is not supported
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ThumbLongBranch {
     /// The destination address range (PC+4)-400000h..+3FFFFEh, ie. PC+/-4M.
     pub target: i32,
 }
 
 /// THUMB.2: add/subtract immediate
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub struct ThumbAddSubI {
     /// Destination register
@@ -326,7 +351,7 @@ pub struct ThumbAddSubI {
 }
 
 /// THUMB.2: add/subtract register
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ThumbAddSubR {
     /// Destination register
     pub rd: Register,
@@ -337,7 +362,7 @@ pub struct ThumbAddSubR {
 }
 
 /// THUMB.2: add/subtract
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub enum ThumbAddSub {
     /// add register Rd=Rs+Rn
@@ -373,7 +398,7 @@ impl TryFrom<u16> for ThumbAddSub {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ThumbMcasOp {
     /// move Rd = #nn
     Mov,
@@ -386,7 +411,7 @@ pub enum ThumbMcasOp {
 }
 
 /// THUMB.3: move/compare/add/subtract immediate
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ThumbMcas {
     pub op: ThumbMcasOp,
     /// Destination register
@@ -413,7 +438,7 @@ impl TryFrom<u16> for ThumbMcas {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ThumbMultLSOp {
     /// Rb!,{Rlist};store in memory, increments Rb
     Stmia,
@@ -422,7 +447,7 @@ pub enum ThumbMultLSOp {
 }
 
 /// THUMB.15: multiple load/store
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ThumbMultLS {
     pub op: ThumbMultLSOp,
     /// Base register
@@ -458,7 +483,7 @@ impl TryFrom<u16> for ThumbMultLS {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ThumbInstr {
     /// Memory load/store
     Mls(ThumbMls),
@@ -478,6 +503,8 @@ pub enum ThumbInstr {
     MultLS(ThumbMultLS),
     /// (Conditional) Branch
     Branch(ThumbBranch),
+    /// THUMB.17: software interrupt
+    Swi(ThumbSwi),
     /// THUMB.18: unconditional branch
     UBranch(ThumbUBranch),
     /// THUMB.14: push/pop registers
@@ -496,8 +523,7 @@ impl TryFrom<u16> for ThumbInstr {
         if (value >> 11) & 0b11111 == 0b01001 {
             let rd = Register::from((value >> 8) & 0b111);
             let rb = Register::R15;
-            // + 4 since PC register is evaluated as PC+4
-            let nn = (value & 0xFF) * 4 + 4;
+            let nn = (value & 0xFF) * 4;
 
             Ok(ThumbInstr::Mls(ThumbMls {
                 op: ThumbMlsOp::Ldr,
@@ -515,6 +541,10 @@ impl TryFrom<u16> for ThumbInstr {
             Ok(ThumbInstr::RegShift(ThumbRegShift::try_from(value)?))
         } else if (value >> 13) & 0b111 == 0b001 {
             Ok(ThumbInstr::Mcas(ThumbMcas::try_from(value)?))
+        } else if (value >> 8) & 0xff == 0b1101_1111 {
+            Ok(ThumbInstr::Swi(ThumbSwi {
+                comment: value as u8,
+            }))
         } else if (value >> 12) & 0b1111 == 0b1101 {
             Ok(ThumbInstr::Branch(ThumbBranch::try_from(value)?))
         } else if (value >> 11) & 0b11111 == 0b11100 {
@@ -547,10 +577,7 @@ impl ThumbInstr {
             };
             Ok(ThumbInstr::LongBranch(ThumbLongBranch { target }))
         } else {
-            // TODO: own error for long thum instr
-            Err(ExecErr::UnknownInstr(
-                instr1 as u32 | ((instr2 as u32) << 16),
-            ))
+            Err(ExecErr::InvalidLongInstr(instr1, instr2))
         }
     }
 }
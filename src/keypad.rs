@@ -0,0 +1,84 @@
+//! GBA buttons and the keypad registers
+
+/// Key status register, a cleared bit is a pressed key
+pub const KEYINPUT: u32 = 0x04000130;
+/// Key interrupt control register
+pub const KEYCNT: u32 = 0x04000132;
+
+/// KEYINPUT with none of the keys pressed
+pub const KEYS_RELEASED: u16 = 0x03FF;
+
+/// KEYCNT bit 14, request the keypad interrupt
+const KEYCNT_IRQ: u16 = 1 << 14;
+/// KEYCNT bit 15, all the selected keys have to be pressed instead of any of them
+const KEYCNT_AND: u16 = 1 << 15;
+
+/// Button of the GBA in KEYINPUT bit order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    A,
+    B,
+    Select,
+    Start,
+    Right,
+    Left,
+    Up,
+    Down,
+    R,
+    L,
+}
+
+impl Key {
+    /// Bit of the key in KEYINPUT and KEYCNT
+    pub fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+/// Do the pressed keys of `keyinput` request the keypad interrupt configured in `keycnt`
+pub fn keypad_irq(keyinput: u16, keycnt: u16) -> bool {
+    if keycnt & KEYCNT_IRQ == 0 {
+        return false;
+    }
+
+    let pressed = !keyinput & KEYS_RELEASED;
+    let selected = keycnt & KEYS_RELEASED;
+    if keycnt & KEYCNT_AND != 0 {
+        selected != 0 && pressed & selected == selected
+    } else {
+        pressed & selected != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_bits() {
+        assert_eq!(Key::A.bit(), 1);
+        assert_eq!(Key::Start.bit(), 1 << 3);
+        assert_eq!(Key::L.bit(), 1 << 9);
+    }
+
+    #[test]
+    fn test_keypad_irq() {
+        let pressed = |keys: &[Key]| {
+            keys.iter()
+                .fold(KEYS_RELEASED, |keys, key| keys & !key.bit())
+        };
+        let a_b = Key::A.bit() | Key::B.bit();
+
+        // OR mode, any of the keys
+        assert!(keypad_irq(pressed(&[Key::A]), KEYCNT_IRQ | a_b));
+        assert!(!keypad_irq(pressed(&[Key::Start]), KEYCNT_IRQ | a_b));
+        // AND mode, all of the keys
+        let and = KEYCNT_IRQ | KEYCNT_AND | a_b;
+        assert!(!keypad_irq(pressed(&[Key::A]), and));
+        assert!(keypad_irq(pressed(&[Key::A, Key::B]), and));
+        assert!(keypad_irq(pressed(&[Key::A, Key::B, Key::Up]), and));
+        assert!(!keypad_irq(pressed(&[]), KEYCNT_IRQ | KEYCNT_AND));
+        // interrupt disabled
+        assert!(!keypad_irq(pressed(&[Key::A, Key::B]), KEYCNT_AND | a_b));
+    }
+}
@@ -0,0 +1,105 @@
+//! LCD timing. The screen is drawn line by line, every line ends in a
+//! horizontal blank and every frame ends in a vertical blank.
+
+/// LCD status register
+pub const DISPSTAT: u32 = 0x04000004;
+/// Current line register, read only
+pub const VCOUNT: u32 = 0x04000006;
+
+/// Cycles spent drawing the visible part of a line
+pub const HDRAW_CYCLES: u64 = 960;
+/// Cycles of a full line, including the horizontal blank
+pub const LINE_CYCLES: u64 = 1232;
+/// Lines drawn before the vertical blank starts
+pub const VISIBLE_LINES: u32 = 160;
+/// Lines in a frame, including the vertical blank
+pub const TOTAL_LINES: u32 = 228;
+/// Cycles of a full frame
+pub const FRAME_CYCLES: u64 = LINE_CYCLES * TOTAL_LINES as u64;
+
+/// DISPSTAT bit 0, set during the vertical blank
+pub const DISPSTAT_VBLANK: u16 = 1 << 0;
+/// DISPSTAT bit 1, set during the horizontal blank
+pub const DISPSTAT_HBLANK: u16 = 1 << 1;
+/// DISPSTAT bit 2, set when the current line matches the VCount setting
+pub const DISPSTAT_VCOUNT: u16 = 1 << 2;
+/// DISPSTAT bit 3, request an interrupt when the vertical blank starts
+pub const DISPSTAT_VBLANK_IRQ: u16 = 1 << 3;
+/// DISPSTAT bit 4, request an interrupt when the horizontal blank starts
+pub const DISPSTAT_HBLANK_IRQ: u16 = 1 << 4;
+/// DISPSTAT bit 5, request an interrupt when the VCount setting matches
+pub const DISPSTAT_VCOUNT_IRQ: u16 = 1 << 5;
+
+/// Position of the LCD after the given amount of cycles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LcdState {
+    /// Current line (0-227)
+    pub line: u32,
+    pub vblank: bool,
+    pub hblank: bool,
+}
+
+impl LcdState {
+    pub fn at(cycles: u64) -> Self {
+        let line = ((cycles / LINE_CYCLES) % TOTAL_LINES as u64) as u32;
+        Self {
+            line,
+            vblank: line >= VISIBLE_LINES,
+            hblank: cycles % LINE_CYCLES >= HDRAW_CYCLES,
+        }
+    }
+
+    /// Status flags (bits 0-2) of DISPSTAT with the VCount setting in bits 8-15
+    pub fn dispstat_flags(&self, dispstat: u16) -> u16 {
+        let mut flags = 0;
+        if self.vblank {
+            flags |= DISPSTAT_VBLANK;
+        }
+        if self.hblank {
+            flags |= DISPSTAT_HBLANK;
+        }
+        if self.line == (dispstat >> 8) as u32 {
+            flags |= DISPSTAT_VCOUNT;
+        }
+
+        flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lcd_state() {
+        let state = LcdState::at(0);
+        assert_eq!(state.line, 0);
+        assert!(!state.vblank && !state.hblank);
+
+        let state = LcdState::at(HDRAW_CYCLES);
+        assert_eq!(state.line, 0);
+        assert!(state.hblank);
+
+        let state = LcdState::at(LINE_CYCLES * 160);
+        assert_eq!(state.line, 160);
+        assert!(state.vblank && !state.hblank);
+
+        // wraps to the next frame
+        let state = LcdState::at(LINE_CYCLES * 228 + 1);
+        assert_eq!(state.line, 0);
+        assert!(!state.vblank);
+    }
+
+    #[test]
+    fn test_dispstat_flags() {
+        let state = LcdState::at(LINE_CYCLES * 200 + HDRAW_CYCLES);
+        assert_eq!(
+            state.dispstat_flags(200 << 8),
+            DISPSTAT_VBLANK | DISPSTAT_HBLANK | DISPSTAT_VCOUNT
+        );
+        assert_eq!(
+            state.dispstat_flags(100 << 8),
+            DISPSTAT_VBLANK | DISPSTAT_HBLANK
+        );
+    }
+}
@@ -0,0 +1,29 @@
+//! Game Boy Advance emulator library, the `cgba-emulator` binary is a thin
+//! command line interface over it.
+
+pub mod backup;
+pub mod bios;
+pub mod bus;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod emulator;
+pub mod gba_file;
+pub mod instr;
+pub mod keypad;
+pub mod lcd;
+pub mod logger;
+pub mod mem;
+pub mod prefetch;
+#[cfg(feature = "gui")]
+pub mod sound;
+pub mod stats;
+#[cfg(test)]
+mod test_rom;
+pub mod video;
+
+pub use cpu::{BootConfig, Cpu};
+pub use debugger::Debugger;
+pub use emulator::Emulator;
+pub use gba_file::GBAHeader;
+pub use instr::{arm::Instruction, thumb::ThumbInstr};
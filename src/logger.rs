@@ -1,9 +1,98 @@
+use std::{fmt::Arguments, fs::File, io::Write};
+
 #[macro_export]
 macro_rules! logging {
-    ($enabled:expr,$($arg:tt)*) => {{
-        if ($enabled) {
-            print!("{}:{}: ", file!(), line!());
-            println!($($arg)*);
+    ($logger:expr,$category:expr,$($arg:tt)*) => {{
+        if $logger.is_enabled($category) {
+            $logger.log(file!(), line!(), format_args!($($arg)*));
         }
     }};
 }
+
+/// Category of a log message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogCategory {
+    /// Instruction fetching and decoding
+    Decode,
+    /// Memory reads and writes
+    Memory,
+    /// Taken branches
+    Branch,
+}
+
+impl LogCategory {
+    pub const ALL: [LogCategory; 3] = [Self::Decode, Self::Memory, Self::Branch];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "decode" => Some(Self::Decode),
+            "mem" | "memory" => Some(Self::Memory),
+            "branch" => Some(Self::Branch),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Logger {
+    enabled: bool,
+    /// Log executed instructions as assembly, independent of `enabled`
+    asm: bool,
+    /// Categories that are logged when logging is enabled
+    categories: Vec<LogCategory>,
+    /// Write messages into a file instead of stdout
+    file: Option<File>,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            asm: false,
+            categories: LogCategory::ALL.to_vec(),
+            file: None,
+        }
+    }
+}
+
+impl Logger {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn set_asm(&mut self, asm: bool) {
+        self.asm = asm;
+    }
+
+    pub fn is_asm_enabled(&self) -> bool {
+        self.asm
+    }
+
+    pub fn set_categories(&mut self, categories: &[LogCategory]) {
+        self.categories = categories.to_vec();
+    }
+
+    /// Write messages into the file, or stdout if `file` is None
+    pub fn set_file(&mut self, file: Option<File>) {
+        self.file = file;
+    }
+
+    pub fn is_enabled(&self, category: LogCategory) -> bool {
+        self.enabled && self.categories.contains(&category)
+    }
+
+    pub fn log(&self, file: &str, line: u32, args: Arguments) {
+        self.write_line(format_args!("{file}:{line}: {args}"));
+    }
+
+    /// Write a line without the source location
+    pub fn write_line(&self, args: Arguments) {
+        match self.file.as_ref() {
+            Some(mut log_file) => {
+                // logging is best effort, don't stop the emulation on errors
+                let _ = writeln!(log_file, "{args}");
+            }
+            None => println!("{args}"),
+        }
+    }
+}
@@ -1,55 +1,286 @@
 use std::{
     env::args,
     fs::{self, read_to_string},
+    path::{Path, PathBuf},
+    process::exit,
 };
 
-use cpu::Cpu;
-use debugger::Debugger;
-use instr::common::ExecErr;
-use video::Video;
+use cgba_emulator::{
+    gba_file::SaveType, instr::common::ExecErr, video::Video, BootConfig, Cpu, Debugger, GBAHeader,
+};
+
+/// Command line arguments
+#[derive(Debug, PartialEq)]
+struct Args {
+    /// Path of the ROM file
+    rom_path: String,
+    /// Run the debugger
+    debug: bool,
+    /// Debugger script to run instead of the interactive debugger
+    script: Option<String>,
+    /// Video scale
+    scale: Option<u32>,
+    /// Target frame rate of the window
+    fps: Option<u32>,
+    /// Save the screen into a PNG file after the ROM has run
+    png: Option<String>,
+    /// Run a multiboot image from EWRAM, also detected from the header
+    multiboot: bool,
+    /// Show the emulation speed in the window title
+    stats: bool,
+}
+
+const USAGE: &str =
+    "Usage: cgba-emulator [--scale <N>] [--fps <N>] [--png <file>] [--multiboot] [--stats] <rom.gba> [d|debug [script]]";
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut scale = None;
+    let mut fps = None;
+    let mut png = None;
+    let mut multiboot = false;
+    let mut stats = false;
+    let mut positional = Vec::new();
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if arg == "--scale" {
+            let value = iter.next().ok_or("--scale requires a value")?;
+            match value.parse() {
+                Ok(0) | Err(_) => return Err(format!("Invalid --scale value '{value}'")),
+                Ok(value) => scale = Some(value),
+            }
+        } else if arg == "--fps" {
+            let value = iter.next().ok_or("--fps requires a value")?;
+            match value.parse() {
+                Ok(0) | Err(_) => return Err(format!("Invalid --fps value '{value}'")),
+                Ok(value) => fps = Some(value),
+            }
+        } else if arg == "--png" {
+            png = Some(iter.next().ok_or("--png requires a file")?.clone());
+        } else if arg == "--multiboot" {
+            multiboot = true;
+        } else if arg == "--stats" {
+            stats = true;
+        } else {
+            positional.push(arg.clone());
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let rom_path = positional.next().ok_or("Missing ROM path")?;
+    let debug = match positional.next() {
+        Some(arg) if arg == "d" || arg == "debug" => true,
+        Some(arg) => return Err(format!("Unknown argument '{arg}'")),
+        None => false,
+    };
+    let script = positional.next();
+    if let Some(arg) = positional.next() {
+        return Err(format!("Unknown argument '{arg}'"));
+    }
+
+    Ok(Args {
+        rom_path,
+        debug,
+        script,
+        scale,
+        fps,
+        png,
+        multiboot,
+        stats,
+    })
+}
 
-mod cpu;
-mod debugger;
-mod gba_file;
-mod instr;
-mod logger;
-mod video;
+/// Save file is kept next to the ROM with the .sav extension
+fn save_path(rom_path: &str) -> PathBuf {
+    Path::new(rom_path).with_extension("sav")
+}
+
+/// Write the backup memory of the cpu into the save file, if the ROM has one
+fn write_save(cpu: &Cpu, path: &Path) {
+    if cpu.save_type() == SaveType::None {
+        return;
+    }
+
+    if let Err(e) = fs::write(path, cpu.save_data()) {
+        println!("Failed to write save file '{}': {e}", path.display());
+    }
+}
 
 fn main() {
     let args: Vec<String> = args().collect();
-    let debug = args.len() > 1 && (args[1] == "d" || args[1] == "debug");
-    let bytes = fs::read("demos.gba").unwrap();
+    let args = match parse_args(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{e}");
+            println!("{USAGE}");
+            exit(1);
+        }
+    };
+
+    let bytes = match fs::read(&args.rom_path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            println!("Failed to read ROM '{}': {e}", args.rom_path);
+            exit(1);
+        }
+    };
+
+    match GBAHeader::from_file(&bytes) {
+        Ok(header) => print!("{header}"),
+        Err(e) => {
+            println!("Invalid ROM '{}': {e}", args.rom_path);
+            exit(1);
+        }
+    }
+
+    let boot = if args.multiboot {
+        BootConfig::multiboot()
+    } else {
+        BootConfig::detect(&bytes)
+    };
 
+    let save_path = save_path(&args.rom_path);
     let mut cpu = Cpu::new();
+    // a missing save file is a new game
+    if let Ok(save) = fs::read(&save_path) {
+        cpu.load_save(&save);
+    }
 
-    let (res, cpu) = if debug {
+    let (res, cpu) = if args.debug {
         let mut debugger = Debugger::new(cpu);
-        debugger.initialize(&bytes);
-        if args.len() > 2 {
-            let data = read_to_string(&args[2]).unwrap();
+        if let Err(e) = debugger.initialize_with(&bytes, boot) {
+            (Err(e), debugger.cpu)
+        } else if let Some(script) = &args.script {
+            let data = match read_to_string(script) {
+                Ok(data) => data,
+                Err(e) => {
+                    println!("Failed to read debugger script '{script}': {e}");
+                    exit(1);
+                }
+            };
             (debugger.run_file(&data), debugger.cpu)
         } else {
             (debugger.repl(), debugger.cpu)
         }
     } else {
-        (cpu.run_rom(&bytes, true), cpu)
+        (cpu.run_rom(&bytes, boot, true), cpu)
     };
 
-    match res {
-        Ok(_) => {}
-        Err(e) => match e {
-            ExecErr::UnknownInstr(instr) => println!("Unknown instr {instr:08X}"),
-            ExecErr::UnknownThumbInstr(instr) => println!("Unknown instr {instr:04X}"),
-            ExecErr::UnimplementedInstr(instr) => println!("Unimplemented '{instr}'",),
-            ExecErr::LongInstruction => {
-                println!("Unexpected state where instruction needs more bytes to execute")
-            }
-        },
+    let cpu_failed = res.is_err();
+    if let Err(e) = res {
+        println!("{e}");
+        // failed debugger script assertions are test failures
+        if matches!(e, ExecErr::AssertionFailed(_)) {
+            exit(1);
+        }
     }
 
     println!("{cpu}");
+    write_save(&cpu, &save_path);
 
+    #[cfg(feature = "gui")]
+    let mut video = match args.scale {
+        Some(scale) => Video::with_scale(cpu, scale),
+        None => Video::new(cpu),
+    };
+    #[cfg(not(feature = "gui"))]
     let video = Video::new(cpu);
-    video.initialize_screen();
-    video.draw();
+
+    if let Some(path) = &args.png {
+        if let Err(e) = video.save_png(path) {
+            println!("Failed to write PNG '{path}': {e}");
+        }
+    }
+
+    #[cfg(feature = "gui")]
+    {
+        if let Some(fps) = args.fps {
+            video.set_fps(fps);
+        }
+        video.set_show_stats(args.stats);
+        if cpu_failed {
+            video.stop_cpu();
+        }
+        video.initialize_screen();
+        video.draw();
+        write_save(video.cpu(), &save_path);
+    }
+
+    // without a window the exit code tells if the ROM ran successfully
+    #[cfg(not(feature = "gui"))]
+    if cpu_failed {
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn test_parse_rom_path() {
+        let args = parse(&["emu", "game.gba"]).unwrap();
+        assert_eq!(
+            args,
+            Args {
+                rom_path: "game.gba".into(),
+                debug: false,
+                script: None,
+                scale: None,
+                fps: None,
+                png: None,
+                multiboot: false,
+                stats: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_debug_and_script() {
+        let args = parse(&["emu", "game.gba", "d"]).unwrap();
+        assert_eq!(args.rom_path, "game.gba");
+        assert!(args.debug);
+        assert_eq!(args.script, None);
+
+        let args = parse(&["emu", "--scale", "3", "game.gba", "debug", "test.dbg"]).unwrap();
+        assert_eq!(args.rom_path, "game.gba");
+        assert!(args.debug);
+        assert_eq!(args.script, Some("test.dbg".into()));
+        assert_eq!(args.scale, Some(3));
+
+        let args = parse(&["emu", "game.gba", "--fps", "30"]).unwrap();
+        assert_eq!(args.fps, Some(30));
+
+        let args = parse(&["emu", "--png", "screen.png", "game.gba"]).unwrap();
+        assert_eq!(args.png, Some("screen.png".into()));
+
+        let args = parse(&["emu", "--multiboot", "game.mb"]).unwrap();
+        assert!(args.multiboot);
+
+        let args = parse(&["emu", "--stats", "game.gba"]).unwrap();
+        assert!(args.stats);
+    }
+
+    #[test]
+    fn test_save_path() {
+        assert_eq!(save_path("game.gba"), PathBuf::from("game.sav"));
+        assert_eq!(save_path("roms/game"), PathBuf::from("roms/game.sav"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(&["emu"]).is_err());
+        assert!(parse(&["emu", "game.gba", "test.dbg"]).is_err());
+        assert!(parse(&["emu", "game.gba", "d", "test.dbg", "extra"]).is_err());
+        assert!(parse(&["emu", "game.gba", "--scale"]).is_err());
+        assert!(parse(&["emu", "game.gba", "--scale", "big"]).is_err());
+        assert!(parse(&["emu", "game.gba", "--scale", "0"]).is_err());
+        assert!(parse(&["emu", "game.gba", "--fps", "0"]).is_err());
+        assert!(parse(&["emu", "game.gba", "--png"]).is_err());
+    }
 }
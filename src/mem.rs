@@ -0,0 +1,167 @@
+//! Memory map of the GBA, the base address and size of each region
+
+use std::fmt::Display;
+
+/// BIOS area, 16KB
+pub const BIOS_START: u32 = 0x00000000;
+pub const BIOS_SIZE: u32 = 0x4000;
+
+/// On-board work RAM, 256KB
+pub const EWRAM_START: u32 = 0x02000000;
+pub const EWRAM_SIZE: u32 = 0x40000;
+
+/// On-chip work RAM, 32KB
+pub const IWRAM_START: u32 = 0x03000000;
+pub const IWRAM_SIZE: u32 = 0x8000;
+
+/// I/O registers, the rest of the 0x04 region is unmapped
+pub const IO_START: u32 = 0x04000000;
+pub const IO_SIZE: u32 = 0x400;
+
+/// Palette RAM, 1KB
+pub const PALETTE_START: u32 = 0x05000000;
+pub const PALETTE_SIZE: u32 = 0x400;
+
+/// VRAM, 96KB
+pub const VRAM_START: u32 = 0x06000000;
+pub const VRAM_SIZE: u32 = 0x18000;
+
+/// Object attribute memory, 1KB
+pub const OAM_START: u32 = 0x07000000;
+pub const OAM_SIZE: u32 = 0x400;
+
+/// Cartridge ROM, mirrored at 0x0A000000 and 0x0C000000
+pub const ROM_START: u32 = 0x08000000;
+/// Largest ROM that fits in the 32MB cartridge address space
+pub const ROM_MAX_SIZE: u32 = 0x2000000;
+
+/// Cartridge backup memory, SRAM or Flash
+pub const BACKUP_START: u32 = 0x0E000000;
+
+/// Region of the memory map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Bios,
+    Ewram,
+    Iwram,
+    Io,
+    Palette,
+    Vram,
+    Oam,
+    /// Cartridge ROM and its wait state mirrors
+    Rom,
+    Backup,
+    /// The unused 0x01 region, I/O after the registers and beyond the address bus
+    Unmapped,
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Bios => "BIOS",
+            Self::Ewram => "EWRAM",
+            Self::Iwram => "IWRAM",
+            Self::Io => "I/O",
+            Self::Palette => "Palette",
+            Self::Vram => "VRAM",
+            Self::Oam => "OAM",
+            Self::Rom => "ROM",
+            Self::Backup => "Backup",
+            Self::Unmapped => "Unmapped",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Region of `addr`, the mirrors of a region belong to it
+pub fn classify(addr: u32) -> Region {
+    let offset = addr & 0xFFFFFF;
+    match addr >> 24 {
+        0x00 if offset < BIOS_SIZE => Region::Bios,
+        0x02 => Region::Ewram,
+        0x03 => Region::Iwram,
+        0x04 if offset < IO_SIZE => Region::Io,
+        0x05 => Region::Palette,
+        0x06 => Region::Vram,
+        0x07 => Region::Oam,
+        0x08..=0x0D => Region::Rom,
+        0x0E..=0x0F => Region::Backup,
+        _ => Region::Unmapped,
+    }
+}
+
+/// `addr` folded into the first copy of its region for the mirrored RAM and ROM,
+/// other addresses are returned unchanged
+pub fn canonical(addr: u32) -> u32 {
+    match classify(addr) {
+        Region::Ewram => EWRAM_START | (addr & (EWRAM_SIZE - 1)),
+        Region::Iwram => IWRAM_START | (addr & (IWRAM_SIZE - 1)),
+        Region::Palette => PALETTE_START | (addr & (PALETTE_SIZE - 1)),
+        Region::Oam => OAM_START | (addr & (OAM_SIZE - 1)),
+        Region::Rom => ROM_START | (addr & (ROM_MAX_SIZE - 1)),
+        _ => addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        for (addr, region) in [
+            (0x00000000, Region::Bios),
+            (0x00003FFF, Region::Bios),
+            (0x00004000, Region::Unmapped),
+            (0x01000000, Region::Unmapped),
+            (0x02000000, Region::Ewram),
+            // mirror
+            (0x02FFFFFF, Region::Ewram),
+            (0x03007FFC, Region::Iwram),
+            (0x04000000, Region::Io),
+            (0x040003FF, Region::Io),
+            (0x04000400, Region::Unmapped),
+            (0x05000200, Region::Palette),
+            (0x06017FFF, Region::Vram),
+            (0x07000000, Region::Oam),
+            (0x08000000, Region::Rom),
+            (0x0A000000, Region::Rom),
+            (0x0DFFFFFF, Region::Rom),
+            (0x0E000000, Region::Backup),
+            (0x0F00FFFF, Region::Backup),
+            (0x10000000, Region::Unmapped),
+            (0xFFFFFFFF, Region::Unmapped),
+        ] {
+            assert_eq!(classify(addr), region, "{addr:08X}");
+        }
+    }
+
+    #[test]
+    fn test_canonical() {
+        for (addr, expected) in [
+            (0x02040010, 0x02000010),
+            (0x03FFFFFC, 0x03007FFC),
+            (0x05000400, 0x05000000),
+            (0x0C000100, 0x08000100),
+            (0x09000000, 0x09000000),
+            // not mirrored here
+            (0x06000000, 0x06000000),
+            (0x04000400, 0x04000400),
+        ] {
+            assert_eq!(canonical(addr), expected, "{addr:08X}");
+        }
+    }
+
+    #[test]
+    fn test_region_sizes() {
+        for (start, size, next) in [
+            (BIOS_START, BIOS_SIZE, Region::Unmapped),
+            (IO_START, IO_SIZE, Region::Unmapped),
+            (ROM_START, ROM_MAX_SIZE, Region::Rom),
+        ] {
+            assert_eq!(classify(start), classify(start + size - 1));
+            assert_eq!(classify(start + size), next);
+        }
+        assert_eq!(classify(BACKUP_START), Region::Backup);
+    }
+}
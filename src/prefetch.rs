@@ -0,0 +1,111 @@
+//! Instruction fetch buffer, keeps the block of code around PC so that sequential
+//! fetches and tight loops don't go through the memory map for every instruction
+
+use crate::mem::{canonical, classify, Region};
+
+/// Size of the cached block in bytes, the blocks are aligned to it
+pub const PREFETCH_SIZE: u32 = 64;
+
+const PREFETCH_WORDS: usize = (PREFETCH_SIZE / 4) as usize;
+
+/// Block of code the last instruction was fetched from
+#[derive(Debug, Default)]
+pub struct Prefetch {
+    /// Address of the first cached byte as the cpu fetched it, None when empty
+    start: Option<u32>,
+    words: [u32; PREFETCH_WORDS],
+}
+
+/// Can `addr` be cached. Only the ROM and the work RAM are, other regions
+/// have side effects, read as open bus or are rarely executed from.
+pub fn is_cacheable(addr: u32) -> bool {
+    matches!(classify(addr), Region::Rom | Region::Ewram | Region::Iwram)
+}
+
+impl Prefetch {
+    /// Cached word at the word aligned `addr`
+    pub fn word(&self, addr: u32) -> Option<u32> {
+        let start = self.start?;
+        let offset = addr.wrapping_sub(start);
+        (offset < PREFETCH_SIZE).then(|| self.words[(offset / 4) as usize])
+    }
+
+    /// Cache the block of `addr`, reading its words with `read`.
+    /// Returns the word at the word aligned `addr`.
+    pub fn fill(&mut self, addr: u32, read: impl Fn(u32) -> u32) -> u32 {
+        let start = addr & !(PREFETCH_SIZE - 1);
+        for (idx, word) in self.words.iter_mut().enumerate() {
+            *word = read(start + idx as u32 * 4);
+        }
+        self.start = Some(start);
+        self.words[((addr - start) / 4) as usize]
+    }
+
+    /// Drop the cached block if a write of `size` bytes to `addr` can change it,
+    /// also through a mirror of the region
+    pub fn invalidate(&mut self, addr: u32, size: u32) {
+        let Some(start) = self.start else {
+            return;
+        };
+
+        let block = canonical(start);
+        let last = addr.wrapping_add(size.max(1) - 1);
+        if [addr, last]
+            .into_iter()
+            .any(|addr| canonical(addr) & !(PREFETCH_SIZE - 1) == block)
+        {
+            self.clear();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.start = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_and_hit() {
+        let mut prefetch = Prefetch::default();
+        assert_eq!(prefetch.word(0x08000000), None);
+
+        assert_eq!(prefetch.fill(0x08000044, |addr| addr), 0x08000044);
+        assert_eq!(prefetch.word(0x08000040), Some(0x08000040));
+        assert_eq!(prefetch.word(0x0800007C), Some(0x0800007C));
+        assert_eq!(prefetch.word(0x08000080), None);
+        assert_eq!(prefetch.word(0x0800003C), None);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let mut prefetch = Prefetch::default();
+        prefetch.fill(0x03000000, |_| 0);
+        prefetch.invalidate(0x03000040, 4);
+        assert!(prefetch.word(0x03000000).is_some());
+        // halfword write at the end of the previous block doesn't reach it
+        prefetch.invalidate(0x02FFFFFE, 2);
+        assert!(prefetch.word(0x03000000).is_some());
+
+        prefetch.invalidate(0x0300003E, 4);
+        assert_eq!(prefetch.word(0x03000000), None);
+
+        // written through a mirror of IWRAM
+        prefetch.fill(0x03000010, |_| 0);
+        prefetch.invalidate(0x03008010, 1);
+        assert_eq!(prefetch.word(0x03000010), None);
+    }
+
+    #[test]
+    fn test_is_cacheable() {
+        assert!(is_cacheable(0x08000000));
+        assert!(is_cacheable(0x0C000000));
+        assert!(is_cacheable(0x02000000));
+        assert!(is_cacheable(0x03007FF0));
+        assert!(!is_cacheable(0x00000000));
+        assert!(!is_cacheable(0x04000000));
+        assert!(!is_cacheable(0x06000000));
+    }
+}
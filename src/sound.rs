@@ -0,0 +1,227 @@
+//! Sound output of the two square wave channels.
+//! TODO: sweep of channel 1 and the wave, noise and DMA sound channels
+
+use sdl2::audio::AudioCallback;
+
+use crate::cpu::Cpu;
+
+/// Channel 1 duty, length and envelope
+const SOUND1CNT_H: u32 = 0x04000062;
+/// Channel 1 frequency and control
+const SOUND1CNT_X: u32 = 0x04000064;
+/// Channel 2 duty, length and envelope
+const SOUND2CNT_L: u32 = 0x04000068;
+/// Channel 2 frequency and control
+const SOUND2CNT_H: u32 = 0x0400006C;
+/// Channel left/right enable flags
+const SOUNDCNT_L: u32 = 0x04000080;
+/// Master sound enable
+const SOUNDCNT_X: u32 = 0x04000084;
+
+/// Part of the wave that's high for each duty setting
+const DUTY_CYCLES: [f32; 4] = [0.125, 0.25, 0.5, 0.75];
+
+/// Frequency of the square wave in Hz from the 11 bit frequency register value
+pub fn frequency_to_hz(frequency: u16) -> f32 {
+    131072.0 / (2048 - (frequency & 0x7FF) as u32) as f32
+}
+
+/// Square wave channel 1 or 2
+#[derive(Debug, Default)]
+pub struct SquareChannel {
+    enabled: bool,
+    /// 11 bit frequency register value
+    frequency: u16,
+    /// Duty setting (0-3)
+    duty: u8,
+    /// Current volume (0-15)
+    volume: u8,
+    /// Envelope step time in 1/64 seconds, 0 stops the envelope
+    envelope_step: u8,
+    envelope_increase: bool,
+    /// Seconds since the last envelope step
+    envelope_time: f32,
+    /// Seconds left before the channel stops, None plays until stopped
+    length: Option<f32>,
+    /// Position in the current wave cycle (0.0-1.0)
+    phase: f32,
+}
+
+impl SquareChannel {
+    /// Update the channel from its duty/length/envelope and frequency/control registers.
+    /// Envelope and length are only reloaded when the channel is restarted with bit 15.
+    pub fn write_registers(&mut self, cnt: u16, control: u16) {
+        self.frequency = control & 0x7FF;
+        self.duty = ((cnt >> 6) & 0b11) as u8;
+
+        if (control >> 15) & 1 == 1 {
+            self.enabled = true;
+            self.volume = (cnt >> 12) as u8;
+            self.envelope_step = ((cnt >> 8) & 0b111) as u8;
+            self.envelope_increase = (cnt >> 11) & 1 == 1;
+            self.envelope_time = 0.0;
+            self.length = ((control >> 14) & 1 == 1).then(|| (64 - (cnt & 0x3F)) as f32 / 256.0);
+        }
+    }
+
+    /// Change the volume by one in the envelope direction, stays within 0-15
+    pub fn step_envelope(&mut self) {
+        if self.envelope_step == 0 {
+            return;
+        }
+
+        if self.envelope_increase {
+            self.volume = (self.volume + 1).min(15);
+        } else {
+            self.volume = self.volume.saturating_sub(1);
+        }
+    }
+
+    /// Advance the channel by one sample and return it in range -1.0..=1.0
+    pub fn next_sample(&mut self, sample_rate: u32) -> f32 {
+        if !self.enabled {
+            return 0.0;
+        }
+
+        let dt = 1.0 / sample_rate as f32;
+        if let Some(length) = &mut self.length {
+            *length -= dt;
+            if *length <= 0.0 {
+                self.enabled = false;
+                return 0.0;
+            }
+        }
+
+        if self.envelope_step != 0 {
+            let period = self.envelope_step as f32 / 64.0;
+            self.envelope_time += dt;
+            while self.envelope_time >= period {
+                self.envelope_time -= period;
+                self.step_envelope();
+            }
+        }
+
+        self.phase = (self.phase + frequency_to_hz(self.frequency) * dt) % 1.0;
+        let amplitude = self.volume as f32 / 15.0;
+        if self.phase < DUTY_CYCLES[self.duty as usize] {
+            amplitude
+        } else {
+            -amplitude
+        }
+    }
+}
+
+/// Mixes the sound channels into the SDL audio output
+#[derive(Debug)]
+pub struct Sound {
+    sample_rate: u32,
+    /// Master enable from SOUNDCNT_X
+    enabled: bool,
+    /// Channels enabled for left or right output in SOUNDCNT_L
+    channel_enabled: [bool; 2],
+    channels: [SquareChannel; 2],
+}
+
+impl Sound {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            enabled: false,
+            channel_enabled: [false; 2],
+            channels: Default::default(),
+        }
+    }
+
+    /// Read the sound registers written since the last update.
+    /// The restart bits are write only and are cleared once they are handled.
+    pub fn update_registers(&mut self, cpu: &mut Cpu) {
+        self.enabled = (cpu.get_memory_u16(SOUNDCNT_X) >> 7) & 1 == 1;
+        let cnt = cpu.get_memory_u16(SOUNDCNT_L);
+        // mono output, play the channel if either side is enabled
+        self.channel_enabled = [0, 1].map(|idx| (cnt >> (8 + idx)) & 0b10001 != 0);
+
+        for (channel, (cnt, control)) in self
+            .channels
+            .iter_mut()
+            .zip([(SOUND1CNT_H, SOUND1CNT_X), (SOUND2CNT_L, SOUND2CNT_H)])
+        {
+            let control_value = cpu.get_memory_u16(control);
+            channel.write_registers(cpu.get_memory_u16(cnt), control_value);
+            cpu.set_memory_u16(control, control_value & 0x7FFF);
+        }
+    }
+}
+
+impl AudioCallback for Sound {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            let mut mixed = 0.0;
+            for (channel, enabled) in self.channels.iter_mut().zip(self.channel_enabled) {
+                let value = channel.next_sample(self.sample_rate);
+                if self.enabled && enabled {
+                    mixed += value;
+                }
+            }
+
+            // keep the volume down
+            *sample = mixed / 2.0 * 0.25;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frequency_to_hz() {
+        assert_eq!(frequency_to_hz(0), 64.0);
+        assert_eq!(frequency_to_hz(1024), 128.0);
+        // A4
+        assert_eq!(frequency_to_hz(1750).round(), 440.0);
+        assert_eq!(frequency_to_hz(2047), 131072.0);
+    }
+
+    #[test]
+    fn test_envelope_steps() {
+        let mut channel = SquareChannel::default();
+        // volume 2, decreasing by one every 1/64 seconds, restart
+        channel.write_registers(0x2100, 0x8000);
+        assert_eq!(channel.volume, 2);
+        channel.step_envelope();
+        assert_eq!(channel.volume, 1);
+        channel.step_envelope();
+        channel.step_envelope();
+        assert_eq!(channel.volume, 0);
+
+        // volume 14, increasing
+        channel.write_registers(0xE900, 0x8000);
+        channel.step_envelope();
+        channel.step_envelope();
+        assert_eq!(channel.volume, 15);
+
+        // envelope step 0 keeps the volume
+        channel.write_registers(0xA000, 0x8000);
+        channel.step_envelope();
+        assert_eq!(channel.volume, 10);
+    }
+
+    #[test]
+    fn test_envelope_and_length_in_samples() {
+        let mut channel = SquareChannel::default();
+        // volume 15, decreasing every 1/64 seconds, length of 32/256 seconds
+        channel.write_registers(0xF120, 0xC000);
+        for _ in 0..1010 {
+            channel.next_sample(64000);
+        }
+        assert_eq!(channel.volume, 14);
+
+        for _ in 0..7000 {
+            channel.next_sample(64000);
+        }
+        assert!(!channel.enabled);
+        assert_eq!(channel.next_sample(64000), 0.0);
+    }
+}
@@ -0,0 +1,110 @@
+//! Emulation speed, executed instructions and frames over the wall time spent running them
+
+use std::{fmt::Display, time::Duration};
+
+/// Counters accumulated while the cpu runs, cleared on reset
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stats {
+    /// Executed instructions
+    pub instructions: u64,
+    /// Emulated frames
+    pub frames: u64,
+    /// Wall time spent emulating, including rendering the frames and pacing them
+    pub elapsed: Duration,
+}
+
+impl Stats {
+    /// Instructions per second, 0 before any time has been spent
+    pub fn ips(&self) -> f64 {
+        self.per_second(self.instructions)
+    }
+
+    /// Frames per second, 0 before any time has been spent
+    pub fn fps(&self) -> f64 {
+        self.per_second(self.frames)
+    }
+
+    /// Average wall time of a frame
+    pub fn frame_time(&self) -> Duration {
+        match u32::try_from(self.frames) {
+            Ok(0) => Duration::ZERO,
+            Ok(frames) => self.elapsed / frames,
+            Err(_) => self.elapsed.div_f64(self.frames as f64),
+        }
+    }
+
+    /// Counters accumulated after the `earlier` copy of the stats was taken
+    pub fn since(&self, earlier: &Stats) -> Stats {
+        Stats {
+            instructions: self.instructions.saturating_sub(earlier.instructions),
+            frames: self.frames.saturating_sub(earlier.frames),
+            elapsed: self.elapsed.saturating_sub(earlier.elapsed),
+        }
+    }
+
+    fn per_second(&self, count: u64) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+
+        count as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "instructions: {}", self.instructions)?;
+        writeln!(f, "frames: {}", self.frames)?;
+        writeln!(f, "elapsed: {:.3}s", self.elapsed.as_secs_f64())?;
+        writeln!(f, "ips: {:.0}", self.ips())?;
+        writeln!(f, "fps: {:.1}", self.fps())?;
+        writeln!(
+            f,
+            "frame time: {:.2}ms",
+            self.frame_time().as_secs_f64() * 1000.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ips_and_fps() {
+        let stats = Stats {
+            instructions: 3_000_000,
+            frames: 120,
+            elapsed: Duration::from_millis(1500),
+        };
+        assert_eq!(stats.ips(), 2_000_000.0);
+        assert_eq!(stats.fps(), 80.0);
+        assert_eq!(stats.frame_time(), Duration::from_micros(12500));
+
+        // nothing has run yet
+        assert_eq!(Stats::default().ips(), 0.0);
+        assert_eq!(Stats::default().frame_time(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_since() {
+        let earlier = Stats {
+            instructions: 1000,
+            frames: 1,
+            elapsed: Duration::from_millis(10),
+        };
+        let later = Stats {
+            instructions: 5000,
+            frames: 3,
+            elapsed: Duration::from_millis(30),
+        };
+        let delta = later.since(&earlier);
+        assert_eq!(delta.instructions, 4000);
+        assert_eq!(delta.frames, 2);
+        assert_eq!(delta.ips(), 200_000.0);
+        assert_eq!(
+            delta.to_string(),
+            "instructions: 4000\nframes: 2\nelapsed: 0.020s\nips: 200000\nfps: 100.0\nframe time: 10.00ms\n"
+        );
+    }
+}
@@ -0,0 +1,37 @@
+//! ROM builders shared by the unit tests, the integration tests include
+//! this file too
+
+/// Zeroed ROM of `size` bytes with a valid enough header
+pub(crate) fn rom_bytes(size: usize) -> Vec<u8> {
+    let mut bytes = vec![0; size];
+    // fixed value in the header
+    bytes[0xB2] = 0x96;
+    bytes
+}
+
+/// 0x200 byte ROM with each code block copied to its offset
+pub(crate) fn interworking_rom(parts: &[(usize, Vec<u8>)]) -> Vec<u8> {
+    let mut rom = rom_bytes(0x200);
+    for (offset, code) in parts {
+        rom[*offset..*offset + code.len()].copy_from_slice(code);
+    }
+    rom
+}
+
+pub(crate) fn arm(instrs: &[u32]) -> Vec<u8> {
+    instrs.iter().flat_map(|i| i.to_le_bytes()).collect()
+}
+
+pub(crate) fn thumb(instrs: &[u16]) -> Vec<u8> {
+    instrs.iter().flat_map(|i| i.to_le_bytes()).collect()
+}
+
+/// ROM with the ARM instructions at the entry point
+pub(crate) fn arm_rom(instrs: &[u32]) -> Vec<u8> {
+    interworking_rom(&[(0x00, arm(instrs))])
+}
+
+/// ROM with the Thumb instructions at the entry point
+pub(crate) fn thumb_rom(instrs: &[u16]) -> Vec<u8> {
+    interworking_rom(&[(0x00, thumb(instrs))])
+}
@@ -1,13 +1,37 @@
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::rect::{Point, Rect};
-use std::time::Duration;
+use std::fs::File;
+use std::io::{self, BufWriter};
 
-use crate::cpu::Cpu;
+use crate::{
+    cpu::Cpu,
+    mem::{PALETTE_START, VRAM_START},
+};
+
+mod obj;
+#[cfg(feature = "gui")]
+mod window;
+
+#[cfg(feature = "gui")]
+pub use window::KeyMap;
+
+use obj::ObjPixel;
 
 pub struct Video {
     cpu: Cpu,
+    /// How many times larger the window is compared to the real GBA screen
+    #[cfg(feature = "gui")]
+    scale: u32,
+    /// Target frame rate of the window
+    #[cfg(feature = "gui")]
+    fps: u32,
+    /// Cpu has stopped on an error and is not run anymore
+    #[cfg(feature = "gui")]
+    cpu_stopped: bool,
+    /// Keyboard keys of the GBA buttons
+    #[cfg(feature = "gui")]
+    key_map: KeyMap,
+    /// Show the emulation speed in the window title
+    #[cfg(feature = "gui")]
+    show_stats: bool,
 }
 
 /// Width of a real GBA screen in pixels
@@ -15,87 +39,1056 @@ const GBA_VIDEO_WIDTH: u32 = 240;
 /// Height of a real GBA screen in pixels
 const GBA_VIDEO_HEIGHT: u32 = 160;
 
-const VIDEO_SCALE: u32 = 6;
+/// LCD control register
+const DISPCNT: u32 = 0x04000000;
+/// BG0 control register, BG1-BG3 follow every 2 bytes
+const BGCNT: u32 = 0x04000008;
+/// BG0 horizontal scroll register, BG0VOFS is at +2 and BG1-BG3 follow every 4 bytes
+const BGOFS: u32 = 0x04000010;
+/// BG2 rotation/scaling parameter A, followed by B, C, D and the reference point X and Y.
+/// BG3 registers follow at +0x10.
+const BG2PA: u32 = 0x04000020;
+/// WIN0 horizontal dimensions, left edge in the upper byte and the exclusive right edge
+/// in the lower byte. WIN1H follows at +2 and the vertical WIN0V and WIN1V at +4 and +6.
+const WIN0H: u32 = 0x04000040;
+/// Layers enabled inside WIN0 in bits 0-5 and inside WIN1 in bits 8-13
+const WININ: u32 = 0x04000048;
+/// Layers enabled outside of the windows in bits 0-5 and inside the OBJ window in bits 8-13
+const WINOUT: u32 = 0x0400004A;
+/// Color special effect and its first target layers in bits 0-5 and second in bits 8-13
+const BLDCNT: u32 = 0x04000050;
+/// Alpha blending coefficients of the first (bits 0-4) and second (bits 8-12) target
+const BLDALPHA: u32 = 0x04000052;
+/// Brightness coefficient of the fades in bits 0-4
+const BLDY: u32 = 0x04000054;
+/// Mosaic size register, BG size in bits 0-7 and OBJ size in bits 8-15
+const MOSAIC: u32 = 0x0400004C;
+/// Start of the background palette in palette RAM
+const BG_PALETTE: u32 = PALETTE_START;
+/// Start of the object palette in palette RAM
+const OBJ_PALETTE: u32 = PALETTE_START + 0x200;
+/// Start of the video RAM
+const VRAM: u32 = VRAM_START;
+
+/// Decoded LCD control register
+#[derive(Debug)]
+struct DispCnt {
+    /// BG Mode (0-5)
+    mode: u16,
+    /// Display a white screen and allow fast access to VRAM
+    forced_blank: bool,
+    /// Display flags of BG0-BG3
+    bg_enabled: [bool; 4],
+    /// Display flag of the sprites
+    obj_enabled: bool,
+    /// Sprite tiles are mapped one dimensionally instead of in a 32x32 tile grid
+    obj_1d: bool,
+    /// Display flags of WIN0 and WIN1
+    win_enabled: [bool; 2],
+    /// Display flag of the OBJ window
+    obj_win_enabled: bool,
+}
+
+impl From<u16> for DispCnt {
+    fn from(value: u16) -> Self {
+        Self {
+            mode: value & 0b111,
+            forced_blank: (value >> 7) & 1 == 1,
+            bg_enabled: [0, 1, 2, 3].map(|idx| (value >> (8 + idx)) & 1 == 1),
+            obj_enabled: (value >> 12) & 1 == 1,
+            obj_1d: (value >> 6) & 1 == 1,
+            win_enabled: [(value >> 13) & 1 == 1, (value >> 14) & 1 == 1],
+            obj_win_enabled: (value >> 15) & 1 == 1,
+        }
+    }
+}
+
+/// Layer bits of WININ and WINOUT, BG0-BG3 in bits 0-3
+const LAYER_OBJ: u16 = 1 << 4;
+/// Backdrop layer bit of BLDCNT
+const LAYER_BACKDROP: u16 = 1 << 5;
+/// Color special effects bit of WININ and WINOUT
+const LAYER_EFFECTS: u16 = 1 << 5;
+/// Every layer and the color special effects are enabled
+const ALL_LAYERS: u16 = 0x3F;
+
+/// Color special effect selected in BLDCNT
+#[derive(Debug, PartialEq)]
+enum Effect {
+    None,
+    /// Blend the first target with the second target under it
+    Alpha,
+    /// Fade the first target towards white
+    Brighten,
+    /// Fade the first target towards black
+    Darken,
+}
+
+/// Decoded color special effect registers
+#[derive(Debug)]
+struct Blend {
+    effect: Effect,
+    /// Layer bits of the first and the second target layers
+    first: u16,
+    second: u16,
+    /// Coefficients in 1/16ths, at most 16
+    eva: u32,
+    evb: u32,
+    evy: u32,
+}
+
+impl Blend {
+    /// Apply the effect to the topmost `top` layer with `below` under it.
+    /// Layers are the layer bit and the color of the layer.
+    fn apply(&self, top: (u16, u16), below: (u16, u16)) -> u16 {
+        if top.0 & self.first == 0 {
+            return top.1;
+        }
+
+        let top_color = bgr555_channels(top.1);
+        let channels = match self.effect {
+            Effect::None => return top.1,
+            Effect::Alpha if below.0 & self.second == 0 => return top.1,
+            Effect::Alpha => {
+                let below = bgr555_channels(below.1);
+                [0, 1, 2]
+                    .map(|idx| ((top_color[idx] * self.eva + below[idx] * self.evb) / 16).min(31))
+            }
+            Effect::Brighten => top_color.map(|value| value + (31 - value) * self.evy / 16),
+            Effect::Darken => top_color.map(|value| value - value * self.evy / 16),
+        };
+
+        channels_bgr555(channels)
+    }
+}
+
+/// Rectangle of WIN0 or WIN1, the right and bottom edges are exclusive
+#[derive(Debug)]
+struct WindowRect {
+    left: u32,
+    right: u32,
+    top: u32,
+    bottom: u32,
+}
+
+impl WindowRect {
+    fn contains(&self, x: u32, y: u32) -> bool {
+        (self.left..self.right).contains(&x) && (self.top..self.bottom).contains(&y)
+    }
+}
+
+/// Decoded window registers, which layers are visible in which part of the screen
+#[derive(Debug)]
+struct Windows {
+    /// Enabled WIN0 and WIN1 and their layer bits, WIN0 has the higher priority
+    rects: Vec<(WindowRect, u16)>,
+    /// Layer bits outside of the windows
+    outside: u16,
+}
+
+impl Windows {
+    /// Layer bits at screen position x, y
+    fn layers(&self, x: u32, y: u32) -> u16 {
+        self.rects
+            .iter()
+            .find(|(rect, _)| rect.contains(x, y))
+            .map_or(self.outside, |(_, layers)| *layers)
+    }
+}
+
+/// White in BGR555
+const WHITE: u16 = 0x7FFF;
+
+/// Decoded BGxCNT and scroll registers of a text mode background
+#[derive(Debug)]
+struct TextBg {
+    /// Background number (0-3), lower number wins on equal priority
+    index: usize,
+    /// Priority, 0 is the highest
+    priority: u16,
+    /// Start address of the tile data
+    char_base: u32,
+    /// Start address of the tile map
+    screen_base: u32,
+    /// Mosaic effect is applied with the size of the MOSAIC register
+    mosaic: bool,
+    /// 256 colors/1 palette mode, otherwise 16 colors/16 palettes
+    colors_256: bool,
+    /// Width of the background in pixels, 256 or 512
+    width: u32,
+    /// Height of the background in pixels, 256 or 512
+    height: u32,
+    /// Horizontal scroll offset
+    hofs: u32,
+    /// Vertical scroll offset
+    vofs: u32,
+}
+
+/// Decoded BGxCNT and rotation/scaling registers of an affine background
+#[derive(Debug)]
+struct AffineBg {
+    /// Background number (2-3), lower number wins on equal priority
+    index: usize,
+    /// Priority, 0 is the highest
+    priority: u16,
+    /// Start address of the tile data
+    char_base: u32,
+    /// Start address of the tile map
+    screen_base: u32,
+    /// Mosaic effect is applied with the size of the MOSAIC register
+    mosaic: bool,
+    /// Width and height of the square background in pixels, 128 to 1024
+    size: u32,
+    /// Pixels outside of the background wrap around instead of being transparent
+    wrap: bool,
+    /// Change of the texture position per screen pixel, 8.8 fixed point
+    /// (PA, PC) to the right and (PB, PD) downwards
+    matrix: [i32; 4],
+    /// Texture position of the top left corner of the screen, 20.8 fixed point
+    reference: (i32, i32),
+}
+
+/// Decoded BG2CNT of the Mode 3 bitmap
+#[derive(Debug)]
+struct BitmapBg {
+    /// Priority, 0 is the highest
+    priority: u16,
+    /// Mosaic effect is applied with the size of the MOSAIC register
+    mosaic: bool,
+}
+
+/// Background layer of the compositor
+#[derive(Debug)]
+enum Bg {
+    Text(TextBg),
+    Affine(AffineBg),
+    Bitmap(BitmapBg),
+}
+
+impl Bg {
+    /// Sort key, lower priority value and then the lower background number wins
+    fn order(&self) -> (u16, usize) {
+        match self {
+            Bg::Text(bg) => (bg.priority, bg.index),
+            Bg::Affine(bg) => (bg.priority, bg.index),
+            Bg::Bitmap(bg) => (bg.priority, 2),
+        }
+    }
+
+    fn mosaic(&self) -> bool {
+        match self {
+            Bg::Text(bg) => bg.mosaic,
+            Bg::Affine(bg) => bg.mosaic,
+            Bg::Bitmap(bg) => bg.mosaic,
+        }
+    }
+}
 
 impl Video {
+    /// Video without a window that can only render the screen
+    #[cfg(not(feature = "gui"))]
     pub fn new(cpu: Cpu) -> Self {
         Self { cpu }
     }
 
-    pub fn initialize_screen(&self) {
-        let cntrl = self.cpu.get_memory(0x4000000) as u16;
-        if cntrl != 0x0403 {
-            panic!("Only BG Mode 3 and Screendisplay BG2 is supported")
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    fn text_bg(&self, index: usize) -> TextBg {
+        let cnt = self.cpu.get_memory_u16(BGCNT + index as u32 * 2);
+        let hofs = self.cpu.get_memory_u16(BGOFS + index as u32 * 4);
+        let vofs = self.cpu.get_memory_u16(BGOFS + index as u32 * 4 + 2);
+
+        let (width, height) = match (cnt >> 14) & 0b11 {
+            0 => (256, 256),
+            1 => (512, 256),
+            2 => (256, 512),
+            _ => (512, 512),
+        };
+
+        TextBg {
+            index,
+            priority: cnt & 0b11,
+            char_base: VRAM + ((cnt as u32 >> 2) & 0b11) * 0x4000,
+            screen_base: VRAM + ((cnt as u32 >> 8) & 0b11111) * 0x800,
+            mosaic: (cnt >> 6) & 1 == 1,
+            colors_256: (cnt >> 7) & 1 == 1,
+            width,
+            height,
+            hofs: (hofs & 0x1ff) as u32,
+            vofs: (vofs & 0x1ff) as u32,
+        }
+    }
+
+    /// Decode the control and rotation/scaling registers of the affine background
+    fn affine_bg(&self, index: usize) -> AffineBg {
+        let cnt = self.cpu.get_memory_u16(BGCNT + index as u32 * 2);
+        let params = BG2PA + (index as u32 - 2) * 0x10;
+        let matrix =
+            [0, 1, 2, 3].map(|idx| self.cpu.get_memory_u16(params + idx * 2) as i16 as i32);
+        // reference point is a signed 28 bit value
+        let reference = |offset: u32| ((self.cpu.get_memory(params + offset) << 4) as i32) >> 4;
+
+        AffineBg {
+            index,
+            priority: cnt & 0b11,
+            char_base: VRAM + ((cnt as u32 >> 2) & 0b11) * 0x4000,
+            screen_base: VRAM + ((cnt as u32 >> 8) & 0b11111) * 0x800,
+            mosaic: (cnt >> 6) & 1 == 1,
+            size: 128 << ((cnt >> 14) & 0b11),
+            wrap: (cnt >> 13) & 1 == 1,
+            matrix,
+            reference: (reference(8), reference(12)),
         }
     }
 
-    fn get_points(&self) -> Vec<(Color, Point)> {
-        let mut points = Vec::new();
-        // Assuminb BG Mode 3
-        for (idx, addr) in (0x06000000..=0x06012BFF_u32).step_by(2).enumerate() {
-            let x = idx % GBA_VIDEO_WIDTH as usize;
-            let y = idx / GBA_VIDEO_WIDTH as usize;
+    /// Color of the affine background at screen position x, y or None if it's transparent
+    fn affine_bg_pixel(&self, bg: &AffineBg, x: u32, y: u32) -> Option<u16> {
+        let [pa, pb, pc, pd] = bg.matrix;
+        let (x, y) = (x as i32, y as i32);
+        let tex_x = (bg.reference.0 + pa * x + pb * y) >> 8;
+        let tex_y = (bg.reference.1 + pc * x + pd * y) >> 8;
+
+        let size = bg.size as i32;
+        let (tex_x, tex_y) = if bg.wrap {
+            (tex_x.rem_euclid(size), tex_y.rem_euclid(size))
+        } else if (0..size).contains(&tex_x) && (0..size).contains(&tex_y) {
+            (tex_x, tex_y)
+        } else {
+            return None;
+        };
+        let (tex_x, tex_y) = (tex_x as u32, tex_y as u32);
+
+        // one byte tile numbers and the tiles are always 256 colors
+        let entry_addr = bg.screen_base + (tex_y / 8) * (bg.size / 8) + tex_x / 8;
+        let tile = self.cpu.get_memory_u8(entry_addr) as u32;
+        let addr = bg.char_base + tile * 64 + (tex_y % 8) * 8 + tex_x % 8;
+        match self.cpu.get_memory_u8(addr) {
+            0 => None,
+            palette_idx => Some(self.bg_color(palette_idx)),
+        }
+    }
+
+    /// Width and height of the background mosaic blocks in pixels
+    fn bg_mosaic_size(&self) -> (u32, u32) {
+        let mosaic = self.cpu.get_memory_u16(MOSAIC) as u32;
+        ((mosaic & 0xF) + 1, ((mosaic >> 4) & 0xF) + 1)
+    }
 
-            let value = self.cpu.get_memory_u16(addr);
-            if value != 0 {
-                let r = ((value & 0x1F) as f32 / 31.0 * 255.0) as u8;
-                let g = (((value >> 5) & 0x1F) as f32 / 31.0 * 255.0) as u8;
-                let b = (((value >> 10) & 0x1F) as f32 / 31.0 * 255.0) as u8;
+    /// Color of the text background at screen position x, y or None if it's transparent
+    fn text_bg_pixel(&self, bg: &TextBg, x: u32, y: u32) -> Option<u16> {
+        let bg_x = (x + bg.hofs) % bg.width;
+        let bg_y = (y + bg.vofs) % bg.height;
 
-                let color = Color::RGB(r, g, b);
-                let point = Point::new(x as i32, y as i32);
-                points.push((color, point));
+        // Each screen block is 32x32 tiles, wider and taller backgrounds
+        // continue in the next screen blocks
+        let block = bg_x / 256 + (bg_y / 256) * (bg.width / 256);
+        let tile_x = (bg_x % 256) / 8;
+        let tile_y = (bg_y % 256) / 8;
+        let entry_addr = bg.screen_base + block * 0x800 + (tile_y * 32 + tile_x) * 2;
+        let entry = self.cpu.get_memory_u16(entry_addr);
+
+        let tile = (entry & 0x3ff) as u32;
+        let mut pixel_x = bg_x % 8;
+        let mut pixel_y = bg_y % 8;
+        if (entry >> 10) & 1 == 1 {
+            pixel_x = 7 - pixel_x;
+        }
+        if (entry >> 11) & 1 == 1 {
+            pixel_y = 7 - pixel_y;
+        }
+
+        let palette_idx = if bg.colors_256 {
+            let addr = bg.char_base + tile * 64 + pixel_y * 8 + pixel_x;
+            self.cpu.get_memory_u8(addr) as u32
+        } else {
+            let addr = bg.char_base + tile * 32 + pixel_y * 4 + pixel_x / 2;
+            let idx = (self.cpu.get_memory_u8(addr) >> ((pixel_x & 1) * 4)) & 0xf;
+            if idx == 0 {
+                return None;
             }
+            (entry >> 12) as u32 * 16 + idx as u32
+        };
+
+        // Color 0 is always transparent
+        if palette_idx == 0 {
+            return None;
         }
 
-        points
+        Some(self.bg_color(palette_idx as u8))
+    }
+
+    /// BGR555 color of the background palette entry
+    fn bg_color(&self, index: u8) -> u16 {
+        self.cpu.get_memory_u16(BG_PALETTE + index as u32 * 2)
+    }
+
+    /// BGR555 color of the object palette entry
+    fn obj_color(&self, index: u8) -> u16 {
+        self.cpu.get_memory_u16(OBJ_PALETTE + index as u32 * 2)
+    }
+
+    /// RGB color of the background palette entry
+    pub fn bg_palette(&self, index: u8) -> (u8, u8, u8) {
+        bgr555_to_rgb(self.bg_color(index))
+    }
+
+    /// RGB color of the object palette entry
+    pub fn obj_palette(&self, index: u8) -> (u8, u8, u8) {
+        bgr555_to_rgb(self.obj_color(index))
     }
 
-    pub fn draw(&self) {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
+    /// Enabled backgrounds of the mode in the drawing order, topmost first.
+    /// Mode 0 has four text backgrounds, Mode 1 two text backgrounds and affine
+    /// BG2, Mode 2 affine BG2 and BG3 and Mode 3 the bitmap as BG2.
+    fn bgs(&self, dispcnt: &DispCnt) -> Vec<Bg> {
+        let mut bgs: Vec<Bg> = (0..4)
+            .filter(|idx| dispcnt.bg_enabled[*idx])
+            .filter_map(|idx| match (dispcnt.mode, idx) {
+                (0, _) | (1, 0 | 1) => Some(Bg::Text(self.text_bg(idx))),
+                (1, 2) | (2, 2 | 3) => Some(Bg::Affine(self.affine_bg(idx))),
+                (3, 2) => {
+                    let cnt = self.cpu.get_memory_u16(BGCNT + 4);
+                    Some(Bg::Bitmap(BitmapBg {
+                        priority: cnt & 0b11,
+                        mosaic: (cnt >> 6) & 1 == 1,
+                    }))
+                }
+                _ => None,
+            })
+            .collect();
+        bgs.sort_by_key(Bg::order);
+        bgs
+    }
 
-        let window = video_subsystem
-            .window(
-                "GBA Emu",
-                GBA_VIDEO_WIDTH * VIDEO_SCALE,
-                GBA_VIDEO_HEIGHT * VIDEO_SCALE,
-            )
-            .position_centered()
-            .build()
-            .unwrap();
+    /// Color of the background at screen position x, y or None if it's transparent
+    fn bg_pixel(&self, bg: &Bg, mosaic: (u32, u32), x: u32, y: u32) -> Option<u16> {
+        let (x, y) = mosaic_position(bg.mosaic(), mosaic, x, y);
+        match bg {
+            Bg::Text(bg) => self.text_bg_pixel(bg, x, y),
+            Bg::Affine(bg) => self.affine_bg_pixel(bg, x, y),
+            Bg::Bitmap(_) => Some(
+                self.cpu
+                    .get_memory_u16(VRAM + (y * GBA_VIDEO_WIDTH + x) * 2),
+            ),
+        }
+    }
 
-        let mut canvas = window.into_canvas().build().unwrap();
+    /// Decode the window registers, without enabled windows every layer is visible
+    fn windows(&self, dispcnt: &DispCnt) -> Windows {
+        let win_in = self.cpu.get_memory_u16(WININ);
+        let win_out = self.cpu.get_memory_u16(WINOUT);
+        if !dispcnt.win_enabled.contains(&true) && !dispcnt.obj_win_enabled {
+            return Windows {
+                rects: Vec::new(),
+                outside: ALL_LAYERS,
+            };
+        }
 
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
-        canvas.present();
+        // edges past the screen or in the wrong order extend the window to the edge
+        let edges = |value: u16, size: u32| {
+            let (start, end) = ((value >> 8) as u32, (value & 0xFF) as u32);
+            if end > size || start > end {
+                (start, size)
+            } else {
+                (start, end)
+            }
+        };
 
-        for (color, point) in self.get_points() {
-            canvas.set_draw_color(color);
-            let point = point.scale(VIDEO_SCALE as i32);
-            let rect = Rect::new(point.x, point.y, VIDEO_SCALE, VIDEO_SCALE);
-            canvas.fill_rect(rect).unwrap();
+        let rects = (0..2)
+            .filter(|idx| dispcnt.win_enabled[*idx])
+            .map(|idx| {
+                let (left, right) = edges(
+                    self.cpu.get_memory_u16(WIN0H + idx as u32 * 2),
+                    GBA_VIDEO_WIDTH,
+                );
+                let (top, bottom) = edges(
+                    self.cpu.get_memory_u16(WIN0H + 4 + idx as u32 * 2),
+                    GBA_VIDEO_HEIGHT,
+                );
+                let rect = WindowRect {
+                    left,
+                    right,
+                    top,
+                    bottom,
+                };
+                (rect, (win_in >> (idx * 8)) & ALL_LAYERS)
+            })
+            .collect();
+
+        // TODO: OBJ window sprites aren't rendered, so the OBJ window is always empty
+        Windows {
+            rects,
+            outside: win_out & ALL_LAYERS,
         }
+    }
+
+    /// Decode the color special effect registers
+    fn blend(&self) -> Blend {
+        let cnt = self.cpu.get_memory_u16(BLDCNT);
+        let alpha = self.cpu.get_memory_u16(BLDALPHA) as u32;
+        let coefficient = |value: u32| (value & 0x1F).min(16);
 
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.present();
+        Blend {
+            effect: match (cnt >> 6) & 0b11 {
+                0 => Effect::None,
+                1 => Effect::Alpha,
+                2 => Effect::Brighten,
+                _ => Effect::Darken,
+            },
+            first: cnt & 0x3F,
+            second: (cnt >> 8) & 0x3F,
+            eva: coefficient(alpha),
+            evb: coefficient(alpha >> 8),
+            evy: coefficient(self.cpu.get_memory_u16(BLDY) as u32),
+        }
+    }
 
-        let mut event_pump = sdl_context.event_pump().unwrap();
-        let mut i = 0;
-        'running: loop {
-            i = (i + 1) % 255;
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => break 'running,
-                    _ => {}
+    /// Layer bits and colors of the two topmost of the visible `layers` at x, y.
+    /// Sprites are drawn over the backgrounds of the same priority and the
+    /// backdrop is under everything.
+    fn top_layers(
+        &self,
+        bgs: &[Bg],
+        obj: Option<ObjPixel>,
+        layers: u16,
+        mosaic: (u32, u32),
+        x: u32,
+        y: u32,
+    ) -> [(u16, u16); 2] {
+        let mut top = Vec::with_capacity(4);
+        let mut obj = obj.filter(|_| layers & LAYER_OBJ != 0);
+        for bg in bgs {
+            let (priority, index) = bg.order();
+            if (layers >> index) & 1 == 0 {
+                continue;
+            }
+            let Some(color) = self.bg_pixel(bg, mosaic, x, y) else {
+                continue;
+            };
+
+            if let Some(pixel) = obj.filter(|pixel| pixel.priority <= priority) {
+                top.push((LAYER_OBJ, pixel.color));
+                obj = None;
+            }
+            top.push((1 << index, color));
+            if top.len() >= 2 {
+                break;
+            }
+        }
+        if let Some(pixel) = obj {
+            top.push((LAYER_OBJ, pixel.color));
+        }
+
+        let backdrop = (LAYER_BACKDROP, self.bg_color(0));
+        top.extend([backdrop, backdrop]);
+        [top[0], top[1]]
+    }
+
+    /// Render the backgrounds and sprites of the modes 0-3
+    fn render_layers(&self, dispcnt: &DispCnt) -> Vec<u16> {
+        let bgs = self.bgs(dispcnt);
+        let objs = self.render_objs(dispcnt);
+        let windows = self.windows(dispcnt);
+        let blend = self.blend();
+        let mosaic = self.bg_mosaic_size();
+
+        let mut pixels = Vec::with_capacity((GBA_VIDEO_WIDTH * GBA_VIDEO_HEIGHT) as usize);
+        for y in 0..GBA_VIDEO_HEIGHT {
+            for x in 0..GBA_VIDEO_WIDTH {
+                let obj = objs[(y * GBA_VIDEO_WIDTH + x) as usize];
+                let layers = windows.layers(x, y);
+                let [top, below] = self.top_layers(&bgs, obj, layers, mosaic, x, y);
+                if layers & LAYER_EFFECTS != 0 {
+                    pixels.push(blend.apply(top, below));
+                } else {
+                    pixels.push(top.1);
                 }
             }
-            ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
         }
+
+        pixels
+    }
+
+    /// Render the current screen as BGR555 colors, one per pixel
+    fn render(&self) -> Vec<u16> {
+        let dispcnt = DispCnt::from(self.cpu.get_memory_u16(DISPCNT));
+        if dispcnt.forced_blank {
+            return vec![WHITE; (GBA_VIDEO_WIDTH * GBA_VIDEO_HEIGHT) as usize];
+        }
+
+        match dispcnt.mode {
+            0..=3 => self.render_layers(&dispcnt),
+            // TODO: bitmap modes 4 and 5, 6 and 7 are invalid
+            _ => vec![self.bg_color(0); (GBA_VIDEO_WIDTH * GBA_VIDEO_HEIGHT) as usize],
+        }
+    }
+
+    /// Render the current screen as RGBA pixels, 4 bytes per pixel in row-major order
+    pub fn framebuffer(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity((GBA_VIDEO_WIDTH * GBA_VIDEO_HEIGHT * 4) as usize);
+        for value in self.render() {
+            let (r, g, b) = bgr555_to_rgb(value);
+            buffer.extend_from_slice(&[r, g, b, 0xFF]);
+        }
+
+        buffer
+    }
+
+    /// Write the current screen into a PNG file
+    pub fn save_png(&self, path: &str) -> io::Result<()> {
+        let file = File::create(path)?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), GBA_VIDEO_WIDTH, GBA_VIDEO_HEIGHT);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.framebuffer())?;
+        Ok(())
+    }
+}
+
+/// Position of the pixel that's shown at x, y. With the mosaic effect
+/// it's the top left pixel of the `size` block the position is in.
+fn mosaic_position(enabled: bool, size: (u32, u32), x: u32, y: u32) -> (u32, u32) {
+    if !enabled {
+        return (x, y);
+    }
+
+    (x - x % size.0, y - y % size.1)
+}
+
+/// Red, green and blue channels of a BGR555 color
+fn bgr555_channels(value: u16) -> [u32; 3] {
+    [0, 5, 10].map(|shift| ((value >> shift) & 0x1F) as u32)
+}
+
+/// BGR555 color of the red, green and blue channels
+fn channels_bgr555(channels: [u32; 3]) -> u16 {
+    (channels[0] | (channels[1] << 5) | (channels[2] << 10)) as u16
+}
+
+/// Convert a BGR555 color into 8 bit red, green and blue
+fn bgr555_to_rgb(value: u16) -> (u8, u8, u8) {
+    let channel = |shift: u16| (((value >> shift) & 0x1F) as u32 * 255 / 31) as u8;
+    (channel(0), channel(5), channel(10))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsupported_mode_shows_backdrop() {
+        let mut video = Video::new(Cpu::new());
+        video.cpu_mut().set_memory_u16(BG_PALETTE, 0x001F);
+        for mode in 4..=7 {
+            video.cpu_mut().set_memory_u16(DISPCNT, 0x0400 | mode);
+            let framebuffer = video.framebuffer();
+            assert!(framebuffer
+                .chunks(4)
+                .all(|pixel| pixel == [0xFF, 0, 0, 0xFF]));
+        }
+    }
+
+    #[test]
+    fn test_forced_blank() {
+        let mut cpu = Cpu::new();
+        // mode 3 with BG2 and forced blank
+        cpu.set_memory_u16(DISPCNT, 0x0483);
+        for addr in (VRAM..VRAM + 0x100).step_by(2) {
+            cpu.set_memory_u16(addr, 0x001F);
+        }
+
+        let video = Video::new(cpu);
+        let framebuffer = video.framebuffer();
+        assert_eq!(framebuffer.len(), 240 * 160 * 4);
+        assert!(framebuffer.iter().all(|byte| *byte == 0xFF));
+    }
+
+    #[test]
+    fn test_bgr555_to_rgb() {
+        assert_eq!(bgr555_to_rgb(0x001F), (255, 0, 0));
+        assert_eq!(bgr555_to_rgb(0x03E0), (0, 255, 0));
+        assert_eq!(bgr555_to_rgb(0x7C00), (0, 0, 255));
+        // 15 of 31 in every channel
+        assert_eq!(bgr555_to_rgb(0x3DEF), (123, 123, 123));
+        // bit 15 is unused
+        assert_eq!(bgr555_to_rgb(0xFFFF), (255, 255, 255));
+    }
+
+    #[test]
+    fn test_palettes() {
+        let mut cpu = Cpu::new();
+        cpu.set_memory_u16(BG_PALETTE + 2, 0x001F);
+        cpu.set_memory_u16(OBJ_PALETTE + 2, 0x7C00);
+        cpu.set_memory_u16(OBJ_PALETTE + 255 * 2, 0x03E0);
+
+        let video = Video::new(cpu);
+        assert_eq!(video.bg_palette(1), (255, 0, 0));
+        assert_eq!(video.obj_palette(1), (0, 0, 255));
+        assert_eq!(video.obj_palette(255), (0, 255, 0));
+        assert_eq!(video.bg_palette(0), (0, 0, 0));
+    }
+
+    const RED: u16 = 0x001F;
+    const BLUE: u16 = 0x7C00;
+
+    fn pixel(pixels: &[u16], x: u32, y: u32) -> u16 {
+        pixels[(y * GBA_VIDEO_WIDTH + x) as usize]
+    }
+
+    #[test]
+    fn test_mode0_single_tile() {
+        let mut cpu = Cpu::new();
+        // Mode 0, BG0 enabled
+        cpu.set_memory_u16(DISPCNT, 0x0100);
+        // 16 colors, char base block 0, screen base block 31
+        cpu.set_memory_u16(BGCNT, 31 << 8);
+        // scrolled so that the map wraps around horizontally
+        cpu.set_memory_u16(BGOFS, 252);
+        cpu.set_memory_u16(BGOFS + 2, 2);
+        // backdrop and color 1 of palette bank 0
+        cpu.set_memory_u16(BG_PALETTE, BLUE);
+        cpu.set_memory_u16(BG_PALETTE + 2, RED);
+        // tile 1 has every pixel set to color 1
+        for offset in (0..32).step_by(2) {
+            cpu.set_memory_u16(VRAM + 32 + offset, 0x1111);
+        }
+        // top left tile of the map uses tile 1, the rest use the empty tile 0
+        cpu.set_memory_u16(VRAM + 31 * 0x800, 1);
+
+        let pixels = Video::new(cpu).render();
+        // screen (4, 0) is background (0, 2)
+        assert_eq!(pixel(&pixels, 4, 0), RED);
+        assert_eq!(pixel(&pixels, 11, 5), RED);
+        // outside of the tile
+        assert_eq!(pixel(&pixels, 3, 0), BLUE);
+        assert_eq!(pixel(&pixels, 12, 0), BLUE);
+        assert_eq!(pixel(&pixels, 4, 6), BLUE);
+    }
+
+    #[test]
+    fn test_mode0_priority() {
+        let mut cpu = Cpu::new();
+        // Mode 0, BG0 and BG1 enabled
+        cpu.set_memory_u16(DISPCNT, 0x0300);
+        // BG0 has lower priority than BG1
+        cpu.set_memory_u16(BGCNT, (30 << 8) | 1);
+        cpu.set_memory_u16(BGCNT + 2, 31 << 8);
+        cpu.set_memory_u16(BG_PALETTE + 2, RED);
+        cpu.set_memory_u16(BG_PALETTE + 4, BLUE);
+        // tile 1 uses color 1 and tile 2 color 2
+        for offset in (0..32).step_by(2) {
+            cpu.set_memory_u16(VRAM + 32 + offset, 0x1111);
+            cpu.set_memory_u16(VRAM + 64 + offset, 0x2222);
+        }
+        // BG0 covers the two first tiles, BG1 only the second one
+        cpu.set_memory_u16(VRAM + 30 * 0x800, 1);
+        cpu.set_memory_u16(VRAM + 30 * 0x800 + 2, 1);
+        cpu.set_memory_u16(VRAM + 31 * 0x800 + 2, 2);
+
+        let pixels = Video::new(cpu).render();
+        assert_eq!(pixel(&pixels, 0, 0), RED);
+        assert_eq!(pixel(&pixels, 8, 0), BLUE);
+        assert_eq!(pixel(&pixels, 16, 0), 0);
+    }
+
+    #[test]
+    fn test_mode0_mosaic() {
+        let mut cpu = Cpu::new();
+        cpu.set_memory_u16(DISPCNT, 0x0100);
+        // mosaic enabled, screen base block 31
+        cpu.set_memory_u16(BGCNT, (31 << 8) | (1 << 6));
+        // 2x2 blocks
+        cpu.set_memory_u16(MOSAIC, 0x0011);
+        cpu.set_memory_u16(BG_PALETTE, BLUE);
+        cpu.set_memory_u16(BG_PALETTE + 2, RED);
+        // tile 1 has every other column set to color 1
+        for offset in (0..32).step_by(2) {
+            cpu.set_memory_u16(VRAM + 32 + offset, 0x0101);
+        }
+        cpu.set_memory_u16(VRAM + 31 * 0x800, 1);
+
+        let pixels = Video::new(cpu).render();
+        for y in 0..8 {
+            assert_eq!(pixel(&pixels, 0, y), RED);
+            assert_eq!(pixel(&pixels, 1, y), RED);
+            assert_eq!(pixel(&pixels, 2, y), RED);
+            assert_eq!(pixel(&pixels, 3, y), RED);
+        }
+        assert_eq!(pixel(&pixels, 8, 0), BLUE);
+    }
+
+    #[test]
+    fn test_mode3_mosaic() {
+        let mut cpu = Cpu::new();
+        cpu.set_memory_u16(DISPCNT, 0x0403);
+        cpu.set_memory_u16(BGCNT + 4, 1 << 6);
+        // blocks of 2 pixels
+        cpu.set_memory_u16(MOSAIC, 0x0011);
+        cpu.set_memory_u16(VRAM, RED);
+        cpu.set_memory_u16(VRAM + 2, BLUE);
+        cpu.set_memory_u16(VRAM + 4, BLUE);
+
+        let buffer = Video::new(cpu).framebuffer();
+        let rgba = |x: u32, y: u32| {
+            let idx = ((y * GBA_VIDEO_WIDTH + x) * 4) as usize;
+            buffer[idx..idx + 4].to_vec()
+        };
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            assert_eq!(rgba(x, y), [0xFF, 0, 0, 0xFF], "{x} {y}");
+        }
+        assert_eq!(rgba(2, 0), [0, 0, 0xFF, 0xFF]);
+        assert_eq!(rgba(3, 1), [0, 0, 0xFF, 0xFF]);
+        assert_eq!(rgba(0, 2), [0, 0, 0, 0xFF]);
+    }
+
+    /// Mode 2 with a 128x128 BG2 using `matrix` and `reference`. Tile 1 is
+    /// red at the top left of the map and tile 2 blue right of it.
+    fn render_affine(matrix: [i16; 4], reference: (u32, u32), wrap: bool) -> Vec<u16> {
+        let mut cpu = Cpu::new();
+        cpu.set_memory_u16(DISPCNT, 0x0402);
+        // char base block 0, screen base block 8
+        cpu.set_memory_u16(BGCNT + 4, (8 << 8) | ((wrap as u16) << 13));
+        cpu.set_memory_u16(BG_PALETTE, 0x03E0);
+        cpu.set_memory_u16(BG_PALETTE + 2, RED);
+        cpu.set_memory_u16(BG_PALETTE + 4, BLUE);
+        for offset in (0..64).step_by(2) {
+            cpu.set_memory_u16(VRAM + 64 + offset, 0x0101);
+            cpu.set_memory_u16(VRAM + 128 + offset, 0x0202);
+        }
+        cpu.set_memory_u16(VRAM + 8 * 0x800, 0x0201);
+
+        for (idx, value) in matrix.into_iter().enumerate() {
+            cpu.set_memory_u16(BG2PA + idx as u32 * 2, value as u16);
+        }
+        cpu.set_memory(BG2PA + 8, reference.0);
+        cpu.set_memory(BG2PA + 12, reference.1);
+
+        Video::new(cpu).render()
+    }
+
+    #[test]
+    fn test_affine_identity() {
+        let pixels = render_affine([0x100, 0, 0, 0x100], (0, 0), false);
+        assert_eq!(pixel(&pixels, 0, 0), RED);
+        assert_eq!(pixel(&pixels, 7, 7), RED);
+        assert_eq!(pixel(&pixels, 8, 0), BLUE);
+        assert_eq!(pixel(&pixels, 15, 7), BLUE);
+        // empty tile and outside of the background show the backdrop
+        assert_eq!(pixel(&pixels, 0, 8), 0x03E0);
+        assert_eq!(pixel(&pixels, 128, 0), 0x03E0);
+
+        // half a tile to the right with a negative reference point
+        let pixels = render_affine([0x100, 0, 0, 0x100], ((-4_i32 << 8) as u32, 0), false);
+        assert_eq!(pixel(&pixels, 3, 0), 0x03E0);
+        assert_eq!(pixel(&pixels, 4, 0), RED);
+        assert_eq!(pixel(&pixels, 12, 0), BLUE);
+
+        // wraps around to the start of the map
+        let pixels = render_affine([0x100, 0, 0, 0x100], (128 << 8, 0), true);
+        assert_eq!(pixel(&pixels, 0, 0), RED);
+        assert_eq!(pixel(&pixels, 8, 0), BLUE);
+    }
+
+    #[test]
+    fn test_affine_rotation() {
+        // rotated by 90 degrees, texture (15 - y, x) at screen (x, y)
+        let pixels = render_affine([0, -0x100, 0x100, 0], (15 << 8, 0), false);
+        assert_eq!(pixel(&pixels, 0, 0), BLUE);
+        assert_eq!(pixel(&pixels, 7, 7), BLUE);
+        assert_eq!(pixel(&pixels, 0, 8), RED);
+        assert_eq!(pixel(&pixels, 7, 15), RED);
+        assert_eq!(pixel(&pixels, 8, 0), 0x03E0);
+        assert_eq!(pixel(&pixels, 0, 16), 0x03E0);
+    }
+
+    #[test]
+    fn test_obj_and_bg_priorities() {
+        let mut cpu = Cpu::new();
+        // Mode 0, BG0, BG1 and sprites enabled, 1D sprite mapping
+        cpu.set_memory_u16(DISPCNT, 0x1340);
+        // BG0 priority 0 covers the first tile, BG1 priority 2 the first two
+        cpu.set_memory_u16(BGCNT, 30 << 8);
+        cpu.set_memory_u16(BGCNT + 2, (31 << 8) | 2);
+        cpu.set_memory_u16(BG_PALETTE + 2, RED);
+        cpu.set_memory_u16(BG_PALETTE + 4, BLUE);
+        for offset in (0..32).step_by(2) {
+            cpu.set_memory_u16(VRAM + 32 + offset, 0x1111);
+            cpu.set_memory_u16(VRAM + 64 + offset, 0x2222);
+        }
+        cpu.set_memory_u16(VRAM + 30 * 0x800, 1);
+        cpu.set_memory_u16(VRAM + 31 * 0x800, 2);
+        cpu.set_memory_u16(VRAM + 31 * 0x800 + 2, 2);
+
+        // 16x8 sprite with priority 1 at 4, 0 over both tiles, the rest are disabled
+        for index in 1..128 {
+            cpu.set_memory_u16(0x07000000 + index * 8, 1 << 9);
+        }
+        let green = 0x03E0;
+        cpu.set_memory_u16(OBJ_PALETTE + 2, green);
+        cpu.set_memory_u16(0x07000000, 1 << 14);
+        cpu.set_memory_u16(0x07000002, 4);
+        cpu.set_memory_u16(0x07000004, 1 << 10);
+        for offset in (0..64).step_by(2) {
+            cpu.set_memory_u16(0x06010000 + offset, 0x1111);
+        }
+
+        let pixels = Video::new(cpu).render();
+        // BG0 is over the sprite, the sprite is over BG1
+        assert_eq!(pixel(&pixels, 4, 0), RED);
+        assert_eq!(pixel(&pixels, 8, 0), green);
+        assert_eq!(pixel(&pixels, 19, 0), green);
+        assert_eq!(pixel(&pixels, 20, 0), 0);
+        assert_eq!(pixel(&pixels, 12, 8), 0);
+    }
+
+    #[test]
+    fn test_win0_clipping() {
+        let mut cpu = Cpu::new();
+        // Mode 0, BG0 and WIN0 enabled
+        cpu.set_memory_u16(DISPCNT, 0x2100);
+        cpu.set_memory_u16(BGCNT, 31 << 8);
+        cpu.set_memory_u16(BG_PALETTE, BLUE);
+        cpu.set_memory_u16(BG_PALETTE + 2, RED);
+        for offset in (0..32).step_by(2) {
+            cpu.set_memory_u16(VRAM + 32 + offset, 0x1111);
+        }
+        // BG0 covers the whole screen
+        for entry in 0..32 * 32 {
+            cpu.set_memory_u16(VRAM + 31 * 0x800 + entry * 2, 1);
+        }
+        // BG0 is only visible inside the rectangle from 8, 4 to 16, 12
+        cpu.set_memory_u16(WIN0H, (8 << 8) | 16);
+        cpu.set_memory_u16(WIN0H + 4, (4 << 8) | 12);
+        cpu.set_memory_u16(WININ, 0x0001);
+        cpu.set_memory_u16(WINOUT, 0x0000);
+
+        let pixels = Video::new(cpu).render();
+        for (x, y) in [(8, 4), (15, 4), (8, 11), (15, 11)] {
+            assert_eq!(pixel(&pixels, x, y), RED, "{x} {y}");
+        }
+        for (x, y) in [(7, 4), (16, 4), (8, 3), (8, 12), (0, 0), (239, 159)] {
+            assert_eq!(pixel(&pixels, x, y), BLUE, "{x} {y}");
+        }
+        assert_eq!(pixels.iter().filter(|color| **color == RED).count(), 64);
+    }
+
+    /// Mode 3 with a row of `color` pixels and the backdrop under them after
+    /// applying the color special effect `bldcnt`
+    fn render_blend(color: u16, bldcnt: u16, bldalpha: u16, bldy: u16) -> Vec<u16> {
+        let mut cpu = Cpu::new();
+        cpu.set_memory_u16(DISPCNT, 0x0403);
+        cpu.set_memory_u16(BG_PALETTE, 0x7FFF);
+        for x in 0..GBA_VIDEO_WIDTH {
+            cpu.set_memory_u16(VRAM + x * 2, color);
+        }
+        cpu.set_memory_u16(BLDCNT, bldcnt);
+        cpu.set_memory_u16(BLDALPHA, bldalpha);
+        cpu.set_memory_u16(BLDY, bldy);
+        Video::new(cpu).render()
+    }
+
+    #[test]
+    fn test_brightness_decrease() {
+        // darken BG2 with the maximum coefficient
+        let mut cpu = Cpu::new();
+        cpu.set_memory_u16(DISPCNT, 0x0403);
+        for x in 0..GBA_VIDEO_WIDTH {
+            cpu.set_memory_u16(VRAM + x * 2, 0x7FFF);
+        }
+        cpu.set_memory_u16(BLDCNT, 0x00C4);
+        cpu.set_memory_u16(BLDY, 16);
+        let buffer = Video::new(cpu).framebuffer();
+        assert!(buffer.chunks(4).all(|pixel| pixel == [0, 0, 0, 0xFF]));
+
+        // half way, 31 - 31 * 8 / 16 = 16
+        let pixels = render_blend(0x7FFF, 0x00C4, 0, 8);
+        assert_eq!(pixel(&pixels, 0, 0), 0x4210);
+        let pixels = render_blend(0x001F, 0x00C4, 0, 4);
+        assert_eq!(pixel(&pixels, 0, 0), 0x0018);
+        // coefficients over 16 are 16
+        let pixels = render_blend(0x001F, 0x00C4, 0, 31);
+        assert_eq!(pixel(&pixels, 0, 0), 0);
+    }
+
+    #[test]
+    fn test_brightness_increase_and_alpha() {
+        // 0 + 31 * 8 / 16 = 15
+        let pixels = render_blend(0, 0x0084, 0, 8);
+        assert_eq!(pixel(&pixels, 0, 0), 0x3DEF);
+        // BG2 isn't a first target
+        let pixels = render_blend(0, 0x0081, 0, 8);
+        assert_eq!(pixel(&pixels, 0, 0), 0);
+
+        // half of red and all of the white backdrop, clamped to 31
+        let pixels = render_blend(RED, 0x2044, 0x1008, 0);
+        assert_eq!(pixel(&pixels, 0, 0), 0x7FFF);
+        // half of both
+        let pixels = render_blend(RED, 0x2044, 0x0808, 0);
+        assert_eq!(pixel(&pixels, 0, 0), 0x3DEF | 0x001F);
+        // the backdrop isn't a second target
+        let pixels = render_blend(RED, 0x0044, 0x0808, 0);
+        assert_eq!(pixel(&pixels, 0, 0), RED);
+    }
+
+    #[test]
+    fn test_mode3_framebuffer() {
+        let mut cpu = Cpu::new();
+        cpu.set_memory_u16(DISPCNT, 0x0403);
+        cpu.set_memory_u16(VRAM + (120 + 80 * GBA_VIDEO_WIDTH) * 2, RED);
+        cpu.set_memory_u16(VRAM + (136 + 80 * GBA_VIDEO_WIDTH) * 2, 0x03E0);
+
+        let buffer = Video::new(cpu).framebuffer();
+        assert_eq!(
+            buffer.len(),
+            (GBA_VIDEO_WIDTH * GBA_VIDEO_HEIGHT * 4) as usize
+        );
+        let red = ((120 + 80 * GBA_VIDEO_WIDTH) * 4) as usize;
+        assert_eq!(buffer[red..red + 4], [0xFF, 0, 0, 0xFF]);
+        let green = ((136 + 80 * GBA_VIDEO_WIDTH) * 4) as usize;
+        assert_eq!(buffer[green..green + 4], [0, 0xFF, 0, 0xFF]);
+        assert_eq!(buffer[0..4], [0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn test_headless_render() {
+        // "My first GBA demo" from the README
+        let mut cpu = Cpu::new();
+        cpu.set_memory(DISPCNT, 0x0403);
+        cpu.set_memory_u16(VRAM + (120 + 80 * GBA_VIDEO_WIDTH) * 2, 0x001F);
+        cpu.set_memory_u16(VRAM + (136 + 80 * GBA_VIDEO_WIDTH) * 2, 0x03E0);
+        cpu.set_memory_u16(VRAM + (120 + 96 * GBA_VIDEO_WIDTH) * 2, 0x7C00);
+
+        let video = Video::new(cpu);
+        let pixels = video.render();
+        assert_eq!(pixel(&pixels, 120, 80), RED);
+        assert_eq!(pixel(&pixels, 136, 80), 0x03E0);
+        assert_eq!(pixel(&pixels, 120, 96), BLUE);
+        assert_eq!(pixels.iter().filter(|color| **color != 0).count(), 3);
+    }
+
+    #[test]
+    fn test_save_png() {
+        let mut cpu = Cpu::new();
+        cpu.set_memory_u16(DISPCNT, 0x0403);
+        cpu.set_memory_u16(VRAM, RED);
+
+        let path = std::env::temp_dir().join("gba_emu_test_save_png.png");
+        let path = path.to_str().unwrap();
+        Video::new(cpu).save_png(path).unwrap();
+
+        let data = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            data[0..8],
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n']
+        );
     }
 }
@@ -0,0 +1,206 @@
+//! Sprites (OBJs) of the object attribute memory
+
+use super::{DispCnt, Video, GBA_VIDEO_HEIGHT, GBA_VIDEO_WIDTH};
+use crate::mem::{OAM_START, VRAM_START};
+
+/// Start of the object attribute memory, 128 entries of 8 bytes
+const OAM: u32 = OAM_START;
+/// Start of the sprite tiles in VRAM
+const OBJ_TILES: u32 = VRAM_START + 0x10000;
+
+/// Width and height in pixels by the shape and the size of the sprite
+const OBJ_SIZES: [[(u32, u32); 4]; 3] = [
+    // square
+    [(8, 8), (16, 16), (32, 32), (64, 64)],
+    // horizontal
+    [(16, 8), (32, 8), (32, 16), (64, 32)],
+    // vertical
+    [(8, 16), (8, 32), (16, 32), (32, 64)],
+];
+
+/// Decoded OAM entry of a regular sprite
+#[derive(Debug)]
+struct Obj {
+    /// Screen position of the top left corner, can be partly off-screen
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    /// First tile in 32 byte units from the start of the sprite tiles
+    tile: u32,
+    /// Priority relative to the backgrounds, 0 is the highest
+    priority: u16,
+    /// Palette bank of the 16 color sprites
+    palette: u16,
+    /// 256 colors/1 palette mode, otherwise 16 colors/16 palettes
+    colors_256: bool,
+    hflip: bool,
+    vflip: bool,
+}
+
+/// Color and priority of the topmost sprite at a screen position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct ObjPixel {
+    pub color: u16,
+    pub priority: u16,
+}
+
+impl Video {
+    /// Decode the OAM entry, None if the sprite isn't drawn as a regular sprite
+    fn obj(&self, index: u32) -> Option<Obj> {
+        let attr0 = self.cpu.get_memory_u16(OAM + index * 8);
+        let attr1 = self.cpu.get_memory_u16(OAM + index * 8 + 2);
+        let attr2 = self.cpu.get_memory_u16(OAM + index * 8 + 4);
+
+        // TODO: rotation/scaling sprites
+        let affine = (attr0 >> 8) & 1 == 1;
+        let disabled = (attr0 >> 9) & 1 == 1;
+        // TODO: OBJ window sprites
+        let obj_window = (attr0 >> 10) & 0b11 == 2;
+        let shape = (attr0 >> 14) as usize;
+        if affine || disabled || obj_window || shape == 3 {
+            return None;
+        }
+
+        let (width, height) = OBJ_SIZES[shape][(attr1 >> 14) as usize];
+        // Y wraps around at 256 and X is a signed 9 bit value
+        let y = (attr0 & 0xFF) as i32;
+        let x = (attr1 & 0x1FF) as i32;
+        Some(Obj {
+            x: if x >= GBA_VIDEO_WIDTH as i32 {
+                x - 512
+            } else {
+                x
+            },
+            y: if y >= GBA_VIDEO_HEIGHT as i32 {
+                y - 256
+            } else {
+                y
+            },
+            width,
+            height,
+            tile: (attr2 & 0x3FF) as u32,
+            priority: (attr2 >> 10) & 0b11,
+            palette: attr2 >> 12,
+            colors_256: (attr0 >> 13) & 1 == 1,
+            hflip: (attr1 >> 12) & 1 == 1,
+            vflip: (attr1 >> 13) & 1 == 1,
+        })
+    }
+
+    /// Color of the sprite at x, y inside of it or None if it's transparent.
+    /// With `one_d` mapping the tiles of a sprite follow each other, otherwise
+    /// the tiles are in a 32x32 tile grid.
+    fn obj_pixel(&self, obj: &Obj, x: u32, y: u32, one_d: bool) -> Option<u16> {
+        let x = if obj.hflip { obj.width - 1 - x } else { x };
+        let y = if obj.vflip { obj.height - 1 - y } else { y };
+
+        // 256 color tiles take two 32 byte units
+        let tile_size = if obj.colors_256 { 2 } else { 1 };
+        let row_tiles = if one_d { obj.width / 8 * tile_size } else { 32 };
+        let tile = (obj.tile + (y / 8) * row_tiles + (x / 8) * tile_size) & 0x3FF;
+        let tile_addr = OBJ_TILES + tile * 32;
+
+        let (x, y) = (x % 8, y % 8);
+        let idx = if obj.colors_256 {
+            self.cpu.get_memory_u8(tile_addr + y * 8 + x)
+        } else {
+            (self.cpu.get_memory_u8(tile_addr + y * 4 + x / 2) >> ((x & 1) * 4)) & 0xF
+        };
+
+        // Color 0 is always transparent
+        match idx {
+            0 => None,
+            idx if obj.colors_256 => Some(self.obj_color(idx)),
+            idx => Some(self.obj_color(obj.palette as u8 * 16 + idx)),
+        }
+    }
+
+    /// Topmost sprite pixel of every screen position, lower priority value
+    /// and then the lower OAM number wins
+    pub(super) fn render_objs(&self, dispcnt: &DispCnt) -> Vec<Option<ObjPixel>> {
+        let mut pixels = vec![None; (GBA_VIDEO_WIDTH * GBA_VIDEO_HEIGHT) as usize];
+        if !dispcnt.obj_enabled {
+            return pixels;
+        }
+
+        for index in 0..128 {
+            let Some(obj) = self.obj(index) else {
+                continue;
+            };
+
+            for obj_y in 0..obj.height {
+                let y = obj.y + obj_y as i32;
+                if !(0..GBA_VIDEO_HEIGHT as i32).contains(&y) {
+                    continue;
+                }
+
+                for obj_x in 0..obj.width {
+                    let x = obj.x + obj_x as i32;
+                    if !(0..GBA_VIDEO_WIDTH as i32).contains(&x) {
+                        continue;
+                    }
+
+                    let pixel = &mut pixels[(y as u32 * GBA_VIDEO_WIDTH + x as u32) as usize];
+                    if matches!(pixel, Some(old) if old.priority <= obj.priority) {
+                        continue;
+                    }
+                    if let Some(color) = self.obj_pixel(&obj, obj_x, obj_y, dispcnt.obj_1d) {
+                        *pixel = Some(ObjPixel {
+                            color,
+                            priority: obj.priority,
+                        });
+                    }
+                }
+            }
+        }
+
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_obj_decode() {
+        let mut cpu = Cpu::new();
+        // 256 colors, horizontal shape at y 250 and x 500 with flips
+        cpu.set_memory_u16(OAM, (1 << 14) | (1 << 13) | 250);
+        cpu.set_memory_u16(OAM + 2, (2 << 14) | (1 << 13) | (1 << 12) | 500);
+        cpu.set_memory_u16(OAM + 4, (3 << 12) | (2 << 10) | 0x123);
+        // disabled and affine
+        cpu.set_memory_u16(OAM + 8, 1 << 9);
+        cpu.set_memory_u16(OAM + 16, 1 << 8);
+
+        let video = Video::new(cpu);
+        let obj = video.obj(0).unwrap();
+        assert_eq!((obj.x, obj.y), (-12, -6));
+        assert_eq!((obj.width, obj.height), (32, 16));
+        assert_eq!((obj.tile, obj.priority, obj.palette), (0x123, 2, 3));
+        assert!(obj.colors_256 && obj.hflip && obj.vflip);
+        assert!(video.obj(1).is_none());
+        assert!(video.obj(2).is_none());
+    }
+
+    #[test]
+    fn test_obj_tile_mapping() {
+        let mut cpu = Cpu::new();
+        // 16x16 sprite at 0, 0 using tile 0
+        cpu.set_memory_u16(OAM + 2, 1 << 14);
+        cpu.set_memory_u16(crate::video::OBJ_PALETTE + 2, 0x001F);
+        // top right tile with 1D mapping is tile 1, with 2D mapping 1 too,
+        // the bottom left tile is 2 with 1D mapping and 32 with 2D mapping
+        cpu.set_memory_u8(OBJ_TILES + 2 * 32, 0x01);
+        cpu.set_memory_u8(OBJ_TILES + 32 * 32, 0x10);
+
+        let video = Video::new(cpu);
+        let obj = video.obj(0).unwrap();
+        assert_eq!(video.obj_pixel(&obj, 0, 8, true), Some(0x001F));
+        assert_eq!(video.obj_pixel(&obj, 1, 8, true), None);
+        assert_eq!(video.obj_pixel(&obj, 0, 8, false), None);
+        assert_eq!(video.obj_pixel(&obj, 1, 8, false), Some(0x001F));
+    }
+}
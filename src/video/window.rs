@@ -0,0 +1,428 @@
+//! Window of the emulator, shows the screen and runs the cpu frame by frame
+
+use sdl2::audio::AudioSpecDesired;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use std::time::{Duration, Instant};
+
+use super::{Video, DISPCNT, GBA_VIDEO_HEIGHT, GBA_VIDEO_WIDTH};
+use crate::cpu::Cpu;
+use crate::keypad::Key;
+use crate::sound::Sound;
+use crate::stats::Stats;
+
+/// Default value for how many times larger the window is compared to the real GBA screen
+const DEFAULT_VIDEO_SCALE: u32 = 6;
+
+/// Default target frame rate, close to the 59.73 frames per second of a real GBA
+const DEFAULT_FPS: u32 = 60;
+
+/// How often the stats in the window title are updated
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keyboard keys mapped to the GBA buttons
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyMap {
+    pub a: Keycode,
+    pub b: Keycode,
+    pub select: Keycode,
+    pub start: Keycode,
+    pub right: Keycode,
+    pub left: Keycode,
+    pub up: Keycode,
+    pub down: Keycode,
+    pub r: Keycode,
+    pub l: Keycode,
+}
+
+impl Default for KeyMap {
+    /// Arrow keys for the D-pad, X and Z for A and B, Enter and Backspace for
+    /// Start and Select, and S and A for the shoulder buttons
+    fn default() -> Self {
+        Self {
+            a: Keycode::X,
+            b: Keycode::Z,
+            select: Keycode::Backspace,
+            start: Keycode::Return,
+            right: Keycode::Right,
+            left: Keycode::Left,
+            up: Keycode::Up,
+            down: Keycode::Down,
+            r: Keycode::S,
+            l: Keycode::A,
+        }
+    }
+}
+
+impl KeyMap {
+    /// GBA button of the keyboard key, if it's mapped
+    pub fn key(&self, keycode: Keycode) -> Option<Key> {
+        [
+            (self.a, Key::A),
+            (self.b, Key::B),
+            (self.select, Key::Select),
+            (self.start, Key::Start),
+            (self.right, Key::Right),
+            (self.left, Key::Left),
+            (self.up, Key::Up),
+            (self.down, Key::Down),
+            (self.r, Key::R),
+            (self.l, Key::L),
+        ]
+        .into_iter()
+        .find_map(|(mapped, key)| (mapped == keycode).then_some(key))
+    }
+}
+
+impl Video {
+    pub fn new(cpu: Cpu) -> Self {
+        Self::with_scale(cpu, DEFAULT_VIDEO_SCALE)
+    }
+
+    pub fn with_scale(cpu: Cpu, scale: u32) -> Self {
+        Self {
+            cpu,
+            scale,
+            fps: DEFAULT_FPS,
+            cpu_stopped: false,
+            key_map: KeyMap::default(),
+            show_stats: false,
+        }
+    }
+
+    /// Use other keyboard keys for the GBA buttons
+    pub fn set_key_map(&mut self, key_map: KeyMap) {
+        self.key_map = key_map;
+    }
+
+    /// Press or release the GBA button mapped to the keyboard key
+    fn set_key(&mut self, keycode: Keycode, pressed: bool) {
+        if let Some(key) = self.key_map.key(keycode) {
+            self.cpu.set_key(key, pressed);
+        }
+    }
+
+    /// Set the target frame rate, `fps` must be larger than 0
+    pub fn set_fps(&mut self, fps: u32) {
+        self.fps = fps;
+    }
+
+    /// Show the frames and instructions per second in the window title,
+    /// updated once a second
+    pub fn set_show_stats(&mut self, show_stats: bool) {
+        self.show_stats = show_stats;
+    }
+
+    /// Don't run the cpu from the video loop, for example after it has failed
+    pub fn stop_cpu(&mut self) {
+        self.cpu_stopped = true;
+    }
+
+    /// Width and height of the window in pixels
+    pub fn window_size(&self) -> (u32, u32) {
+        (GBA_VIDEO_WIDTH * self.scale, GBA_VIDEO_HEIGHT * self.scale)
+    }
+
+    /// Warn about a display mode that only shows the backdrop
+    pub fn initialize_screen(&self) {
+        let mode = self.cpu.get_memory_u16(DISPCNT) & 0b111;
+        if mode > 3 {
+            println!("BG Mode {mode} is not supported, only the backdrop is shown");
+        }
+    }
+
+    /// Run the cpu until the start of the next frame, unless it has stopped
+    fn step_frame(&mut self) {
+        if self.cpu_stopped {
+            return;
+        }
+
+        if let Err(e) = self.cpu.run_frame() {
+            println!("Cpu stopped: {e}");
+            self.cpu_stopped = true;
+        }
+    }
+
+    fn present(&self, canvas: &mut Canvas<Window>) {
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+
+        for (idx, pixel) in self.framebuffer().chunks(4).enumerate() {
+            // screen is already cleared to black
+            if pixel[0..3] == [0, 0, 0] {
+                continue;
+            }
+
+            let x = (idx % GBA_VIDEO_WIDTH as usize) as i32;
+            let y = (idx / GBA_VIDEO_WIDTH as usize) as i32;
+            canvas.set_draw_color(Color::RGB(pixel[0], pixel[1], pixel[2]));
+            let point = Point::new(x, y).scale(self.scale as i32);
+            let rect = Rect::new(point.x, point.y, self.scale, self.scale);
+            canvas.fill_rect(rect).unwrap();
+        }
+
+        canvas.present();
+    }
+
+    pub fn draw(&mut self) {
+        let sdl_context = sdl2::init().unwrap();
+        let video_subsystem = sdl_context.video().unwrap();
+
+        let (width, height) = self.window_size();
+        let window = video_subsystem
+            .window("GBA Emu", width, height)
+            .position_centered()
+            .build()
+            .unwrap();
+
+        let mut canvas = window.into_canvas().build().unwrap();
+
+        let desired = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: None,
+        };
+        // keep running without sound if there's no audio device
+        let mut audio = match sdl_context.audio().and_then(|audio| {
+            audio.open_playback(None, &desired, |spec| Sound::new(spec.freq as u32))
+        }) {
+            Ok(device) => {
+                device.resume();
+                Some(device)
+            }
+            Err(e) => {
+                println!("Failed to open audio: {e}");
+                None
+            }
+        };
+
+        let mut event_pump = sdl_context.event_pump().unwrap();
+        let mut state = LoopState::default();
+        // stats of the last second in the title
+        let mut stats_update = (Instant::now(), *self.cpu.stats());
+        let mut title_stats = None;
+        // end of the last emulated frame, the stats count the time between frames
+        let mut last_frame = None;
+        'running: loop {
+            let frame_start = Instant::now();
+            let paused = state.paused;
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::Quit { .. }
+                    | Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        repeat,
+                        ..
+                    } => {
+                        state.key_down(keycode, repeat);
+                        self.set_key(keycode, true);
+                    }
+                    Event::KeyUp {
+                        keycode: Some(keycode),
+                        ..
+                    } => {
+                        state.key_up(keycode);
+                        self.set_key(keycode, false);
+                    }
+                    _ => {}
+                }
+            }
+
+            let mut update_title = state.paused != paused;
+            if self.show_stats && stats_update.0.elapsed() >= STATS_INTERVAL {
+                title_stats = Some(self.cpu.stats().since(&stats_update.1));
+                stats_update = (Instant::now(), *self.cpu.stats());
+                update_title = true;
+            }
+            if update_title {
+                let title = window_title(state.paused, title_stats.as_ref());
+                canvas.window_mut().set_title(&title).unwrap();
+            }
+
+            if state.next_frame() {
+                let start = *last_frame.get_or_insert_with(Instant::now);
+                self.step_frame();
+                if let Some(device) = &mut audio {
+                    device.lock().update_registers(&mut self.cpu);
+                }
+                self.present(&mut canvas);
+                let end = Instant::now();
+                self.cpu.add_run_time(end - start);
+                last_frame = Some(end);
+            } else {
+                // time spent paused isn't emulation time
+                last_frame = None;
+            }
+            ::std::thread::sleep(frame_sleep(frame_start.elapsed(), self.fps, state.turbo));
+        }
+    }
+}
+
+/// State of the video loop controlled with the keyboard
+#[derive(Debug, Default, PartialEq)]
+struct LoopState {
+    /// Space toggles the pause
+    paused: bool,
+    /// N runs a single frame while paused
+    step: bool,
+    /// Fast forward while tab is held down
+    turbo: bool,
+}
+
+impl LoopState {
+    /// Key press, `repeat` is set for the repeated presses of a held key
+    fn key_down(&mut self, keycode: Keycode, repeat: bool) {
+        match keycode {
+            Keycode::Space if !repeat => self.paused = !self.paused,
+            Keycode::N if self.paused => self.step = true,
+            Keycode::Tab => self.turbo = true,
+            _ => {}
+        }
+    }
+
+    fn key_up(&mut self, keycode: Keycode) {
+        if keycode == Keycode::Tab {
+            self.turbo = false;
+        }
+    }
+
+    /// Should the next frame be emulated, consumes the pending step
+    fn next_frame(&mut self) -> bool {
+        let step = std::mem::take(&mut self.step);
+        !self.paused || step
+    }
+}
+
+/// Title of the window, with the emulation speed if it's shown
+fn window_title(paused: bool, stats: Option<&Stats>) -> String {
+    let mut title = String::from("GBA Emu");
+    if let Some(stats) = stats {
+        title.push_str(&format!(
+            " - {:.1} fps, {:.2} MIPS",
+            stats.fps(),
+            stats.ips() / 1_000_000.0
+        ));
+    }
+    if paused {
+        title.push_str(" (paused)");
+    }
+    title
+}
+
+/// How long to sleep after a frame that took `elapsed` to keep the frame rate at `fps`
+fn frame_sleep(elapsed: Duration, fps: u32, turbo: bool) -> Duration {
+    if turbo {
+        return Duration::ZERO;
+    }
+
+    (Duration::from_secs(1) / fps).saturating_sub(elapsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::BootConfig, keypad::KEYINPUT, test_rom::rom_bytes};
+
+    #[test]
+    fn test_loop_pause_and_step() {
+        let mut state = LoopState::default();
+        assert!(state.next_frame());
+
+        // stepping does nothing while running
+        state.key_down(Keycode::N, false);
+        assert!(!state.step);
+
+        state.key_down(Keycode::Space, false);
+        assert!(state.paused);
+        // holding space doesn't keep toggling the pause
+        state.key_down(Keycode::Space, true);
+        assert!(state.paused);
+        assert!(!state.next_frame());
+
+        // one frame per step
+        state.key_down(Keycode::N, false);
+        assert!(state.next_frame());
+        assert!(!state.next_frame());
+
+        state.key_down(Keycode::Tab, false);
+        assert!(state.turbo);
+        state.key_up(Keycode::Tab);
+        assert!(!state.turbo);
+
+        state.key_down(Keycode::Space, false);
+        assert!(!state.paused);
+        assert!(state.next_frame());
+        assert!(state.next_frame());
+    }
+
+    #[test]
+    fn test_frame_sleep() {
+        let frame = Duration::from_secs(1) / 60;
+        // fast frame sleeps for the rest of the frame
+        assert_eq!(
+            frame_sleep(Duration::from_millis(4), 60, false),
+            frame - Duration::from_millis(4)
+        );
+        // slow frame doesn't sleep at all
+        assert_eq!(
+            frame_sleep(Duration::from_millis(40), 60, false),
+            Duration::ZERO
+        );
+        assert_eq!(
+            frame_sleep(Duration::from_millis(4), 30, false),
+            Duration::from_secs(1) / 30 - Duration::from_millis(4)
+        );
+        assert_eq!(frame_sleep(Duration::ZERO, 60, true), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_window_title() {
+        assert_eq!(window_title(false, None), "GBA Emu");
+        assert_eq!(window_title(true, None), "GBA Emu (paused)");
+
+        let stats = Stats {
+            instructions: 16_000_000,
+            frames: 59,
+            elapsed: Duration::from_secs(1),
+        };
+        assert_eq!(
+            window_title(true, Some(&stats)),
+            "GBA Emu - 59.0 fps, 16.00 MIPS (paused)"
+        );
+    }
+
+    #[test]
+    fn test_key_map() {
+        let mut cpu = Cpu::new();
+        cpu.initialize_cpu(&rom_bytes(0x200), BootConfig::default())
+            .unwrap();
+        let mut video = Video::new(cpu);
+        assert_eq!(video.key_map.key(Keycode::X), Some(Key::A));
+        assert_eq!(video.key_map.key(Keycode::Space), None);
+
+        video.set_key_map(KeyMap {
+            a: Keycode::K,
+            ..KeyMap::default()
+        });
+        assert_eq!(video.key_map.key(Keycode::X), None);
+        video.set_key(Keycode::K, true);
+        assert_eq!(video.cpu().get_memory_u16(KEYINPUT), 0x03FE);
+    }
+
+    #[test]
+    fn test_window_size() {
+        let video = Video::new(Cpu::new());
+        assert_eq!(video.window_size(), (240 * 6, 160 * 6));
+
+        let video = Video::with_scale(Cpu::new(), 2);
+        assert_eq!(video.window_size(), (480, 320));
+    }
+}
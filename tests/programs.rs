@@ -0,0 +1,128 @@
+//! Tiny hand assembled ARM and Thumb programs run from the ROM entry point
+//! until they halt on a branch into itself. Only uses the headless parts of
+//! the emulator, so it also runs with `--no-default-features` without SDL.
+
+#[allow(dead_code)]
+#[path = "../src/test_rom.rs"]
+mod test_rom;
+
+use cgba_emulator::Cpu;
+use test_rom::arm_rom;
+
+/// Most steps any of the programs takes
+const MAX_STEPS: u64 = 1000;
+
+/// Run the program until it halts
+fn run(words: &[u32]) -> Cpu {
+    let mut cpu = Cpu::new();
+    let steps = cpu.run_rom_steps(&arm_rom(words), MAX_STEPS).unwrap();
+    assert!(cpu.is_halted(), "didn't halt in {steps} steps");
+    cpu
+}
+
+/// Two Thumb instructions in one ROM word, the first one in the lower half
+fn thumb(first: u16, second: u16) -> u32 {
+    first as u32 | (second as u32) << 16
+}
+
+#[test]
+fn test_add_sub() {
+    let cpu = run(&[
+        0xE3A0000A, // mov r0, #10
+        0xE3A01003, // mov r1, #3
+        0xE0802001, // add r2, r0, r1
+        0xE0403001, // sub r3, r0, r1
+        0xE2434008, // sub r4, r3, #8
+        0xEAFFFFFE, // b .
+    ]);
+    assert_eq!((cpu.r2, cpu.r3, cpu.r4), (13, 7, -1_i32 as u32));
+    assert_eq!(cpu.pc, 0x08000014);
+}
+
+#[test]
+fn test_branch_forward() {
+    let cpu = run(&[
+        0xE3A00001, // mov r0, #1
+        0xEA000000, // b skip
+        0xE3A00002, // mov r0, #2
+        0xE3A01003, // skip: mov r1, #3
+        0xEAFFFFFE, // b .
+    ]);
+    assert_eq!((cpu.r0, cpu.r1), (1, 3));
+}
+
+#[test]
+fn test_countdown_loop() {
+    let cpu = run(&[
+        0xE3A00005, // mov r0, #5
+        0xE3A01000, // mov r1, #0
+        0xE2811002, // loop: add r1, r1, #2
+        0xE2500001, // subs r0, r0, #1
+        0x1AFFFFFC, // bne loop
+        0xE2502001, // subs r2, r0, #1
+        0x5A000000, // bpl skip
+        0xE3A03001, // mov r3, #1
+        0xEAFFFFFE, // skip: b .
+    ]);
+    assert_eq!((cpu.r0, cpu.r1, cpu.r2, cpu.r3), (0, 10, -1_i32 as u32, 1));
+}
+
+#[test]
+fn test_run_from_rom_mirror() {
+    let cpu = run(&[
+        0xE3A0040A, // mov r0, #0x0A000000
+        0xE2800010, // add r0, r0, #0x10
+        0xE12FFF10, // bx r0
+        0xE3A01001, // mov r1, #1
+        0xE3A02002, // mirror: mov r2, #2
+        0xEAFFFFFE, // b .
+    ]);
+    assert_eq!((cpu.r1, cpu.r2), (0, 2));
+    assert_eq!(cpu.pc, 0x0A000014);
+}
+
+#[test]
+fn test_load_store() {
+    let cpu = run(&[
+        0xE3A00403, // mov r0, #0x03000000
+        0xE3A01012, // mov r1, #0x12
+        0xE5801004, // str r1, [r0, #4]
+        0xE5902004, // ldr r2, [r0, #4]
+        0xE5B03004, // ldr r3, [r0, #4]!
+        0xE4801004, // str r1, [r0], #4
+        0xEAFFFFFE, // b .
+    ]);
+    assert_eq!((cpu.r2, cpu.r3), (0x12, 0x12));
+    assert_eq!(cpu.r0, 0x03000008);
+    assert_eq!(cpu.get_memory(0x03000004), 0x12);
+    assert_eq!(cpu.get_memory(0x03000000), 0);
+}
+
+#[test]
+fn test_thumb() {
+    let cpu = run(&[
+        0xE28F0001, // add r0, pc, #1
+        0xE12FFF10, // bx r0
+        thumb(
+            0x2107, // mov r1, #7
+            0x3103, // add r1, #3
+        ),
+        thumb(
+            0x2001, // mov r0, #1
+            0x1A0A, // sub r2, r1, r0
+        ),
+        thumb(
+            0x4B01, // ldr r3, [pc, #4]
+            0x601A, // str r2, [r3]
+        ),
+        thumb(
+            0xE7FE, // b .
+            0x0000, // padding for the literal
+        ),
+        0x03000010, // literal for the ldr
+    ]);
+    assert_eq!((cpu.r1, cpu.r2, cpu.r3), (10, 9, 0x03000010));
+    assert_eq!(cpu.get_memory(0x03000010), 9);
+    assert_eq!(cpu.pc, 0x08000014);
+    assert!(cpu.cpsr() & (1 << 5) != 0);
+}
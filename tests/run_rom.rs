@@ -0,0 +1,41 @@
+#[allow(dead_code)]
+#[path = "../src/test_rom.rs"]
+mod test_rom;
+
+use cgba_emulator::{instr::arm::Instruction, Cpu, Debugger, GBAHeader};
+use test_rom::arm_rom;
+
+const PROGRAM: [u32; 3] = [
+    0xE3A00005, // mov r0, #5
+    0xE2801001, // add r1, r0, #1
+    0xEAFFFFFE, // b .
+];
+
+#[test]
+fn test_run_rom_until_halt() {
+    let bytes = arm_rom(&PROGRAM);
+    assert_eq!(
+        GBAHeader::from_file(&bytes).unwrap().rom_entry_point,
+        PROGRAM[0]
+    );
+    assert!(matches!(
+        Instruction::try_from(PROGRAM[0]),
+        Ok(Instruction::Alu(_))
+    ));
+
+    let mut cpu = Cpu::new();
+    let steps = cpu.run_rom_steps(&bytes, 100).unwrap();
+    assert_eq!(steps, 3);
+    assert!(cpu.is_halted());
+    assert_eq!((cpu.r0, cpu.r1), (5, 6));
+    assert_eq!(cpu.pc, 0x08000008);
+}
+
+#[test]
+fn test_debugger_script() {
+    let mut debugger = Debugger::new(Cpu::new());
+    debugger.initialize(&arm_rom(&PROGRAM)).unwrap();
+    debugger.run_file("n\nn\nset r2 1234").unwrap();
+    assert_eq!(debugger.cpu.r1, 6);
+    assert_eq!(debugger.cpu.r2, 0x1234);
+}